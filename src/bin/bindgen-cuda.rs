@@ -0,0 +1,126 @@
+//! Runs the [`bindgen_cuda::Builder`] pipeline outside of `build.rs`, to iterate
+//! on kernels without going through `cargo build`.
+//!
+//! ```text
+//! bindgen-cuda [--out-dir DIR] [--bindings FILE] [--lib NAME] [--watch]
+//! ```
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+const USAGE: &str = "\
+Usage: bindgen-cuda [OPTIONS]
+
+Compiles the kernels of the crate in the current directory (src/**/*.cu).
+
+Options:
+      --out-dir <DIR>    Where kernels are compiled [default: target/bindgen-cuda]
+      --bindings <FILE>  Writes the PTX bindings to FILE
+      --lib <NAME>       Builds a static library instead of PTX
+      --watch            Rebuilds whenever a .cu or .cuh file changes
+  -h, --help             Prints this help";
+
+/// How often sources are polled in `--watch` mode.
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+struct Args {
+    out_dir: PathBuf,
+    bindings: Option<PathBuf>,
+    lib: Option<String>,
+    watch: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        out_dir: PathBuf::from("target/bindgen-cuda"),
+        bindings: None,
+        lib: None,
+        watch: false,
+    };
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        let mut value = || argv.next().ok_or(format!("missing value for {arg}"));
+        match arg.as_str() {
+            "--out-dir" => args.out_dir = value()?.into(),
+            "--bindings" => args.bindings = Some(value()?.into()),
+            "--lib" => args.lib = Some(value()?),
+            "--watch" => args.watch = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            _ => return Err(format!("unexpected argument {arg}")),
+        }
+    }
+    Ok(args)
+}
+
+/// Builds every kernel once, returning whether it succeeded. Compilation errors
+/// are reported here, other failures by the builder which panics.
+fn build(args: &Args) -> bool {
+    let start = Instant::now();
+    let result = std::panic::catch_unwind(|| {
+        let builder = bindgen_cuda::Builder::default();
+        match &args.lib {
+            Some(name) => {
+                builder.build_lib(name);
+                Ok(())
+            }
+            None => builder
+                .build_ptx()
+                .and_then(|bindings| match &args.bindings {
+                    Some(path) => bindings.write(path),
+                    None => Ok(()),
+                }),
+        }
+    });
+    match result {
+        Ok(Ok(())) => {
+            eprintln!("built in {:.2?}", start.elapsed());
+            true
+        }
+        Ok(Err(e)) => {
+            eprintln!("{e}");
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+/// Modification time of every kernel and header of the crate.
+fn snapshot() -> BTreeMap<PathBuf, SystemTime> {
+    ["src/**/*.cu", "src/**/*.cuh"]
+        .iter()
+        .flat_map(|pattern| glob::glob(pattern).expect("valid pattern"))
+        .filter_map(Result::ok)
+        .filter_map(|path| {
+            let modified = path.metadata().and_then(|m| m.modified()).ok()?;
+            Some((path, modified))
+        })
+        .collect()
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|e| {
+        eprintln!("error: {e}\n\n{USAGE}");
+        std::process::exit(2);
+    });
+    std::fs::create_dir_all(&args.out_dir).expect("create output directory");
+    // The builder expects to run from a build script.
+    std::env::set_var("OUT_DIR", &args.out_dir);
+
+    let mut sources = snapshot();
+    let success = build(&args);
+    if !args.watch {
+        std::process::exit(if success { 0 } else { 1 });
+    }
+    eprintln!("watching for changes...");
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let current = snapshot();
+        if current != sources {
+            sources = current;
+            build(&args);
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Sharing compiled kernels between machines, see [`crate::Builder::artifact_cache`].
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Where compiled kernels are looked up before compiling them, and stored after.
+/// Entries are named after a hash of the nvcc version, the nvcc arguments (compute
+/// caps included) and the content of the kernel and of every header it includes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ArtifactCache {
+    /// A directory, typically on a shared filesystem or restored by the CI.
+    Directory(PathBuf),
+    /// An HTTP endpoint answering `GET {url}/{key}` with what was sent by
+    /// `PUT {url}/{key}`, and 404 for unknown keys. Requires the `http-cache` feature.
+    #[cfg(feature = "http-cache")]
+    Http(String),
+}
+
+impl ArtifactCache {
+    /// The cache named by `BINDGEN_CUDA_CACHE`, a URL or a directory.
+    pub(crate) fn from_var(value: &str) -> Self {
+        if value.starts_with("http://") || value.starts_with("https://") {
+            #[cfg(feature = "http-cache")]
+            return Self::Http(value.to_string());
+            #[cfg(not(feature = "http-cache"))]
+            panic!("BINDGEN_CUDA_CACHE={value} requires the `http-cache` feature of bindgen_cuda");
+        }
+        Self::Directory(value.into())
+    }
+
+    /// The artifact stored under `key`, if any.
+    pub(crate) fn get(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match self {
+            Self::Directory(dir) => match std::fs::read(dir.join(key)) {
+                Ok(content) => Ok(Some(content)),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            },
+            #[cfg(feature = "http-cache")]
+            Self::Http(url) => {
+                let url = format!("{}/{key}", url.trim_end_matches('/'));
+                match ureq::get(&url).call() {
+                    Ok(response) => {
+                        let mut content = vec![];
+                        std::io::Read::read_to_end(&mut response.into_reader(), &mut content)?;
+                        Ok(Some(content))
+                    }
+                    Err(ureq::Error::Status(404, _)) => Ok(None),
+                    Err(e) => Err(std::io::Error::other(e)),
+                }
+            }
+        }
+    }
+
+    /// Stores `content` under `key`.
+    pub(crate) fn put(&self, key: &str, content: &[u8]) -> std::io::Result<()> {
+        match self {
+            Self::Directory(dir) => {
+                // Other builds may be reading the same entry, only expose complete files.
+                std::fs::create_dir_all(dir)?;
+                let partial = dir.join(format!("{key}.{}.partial", std::process::id()));
+                std::fs::write(&partial, content)?;
+                std::fs::rename(&partial, dir.join(key))
+            }
+            #[cfg(feature = "http-cache")]
+            Self::Http(url) => {
+                let url = format!("{}/{key}", url.trim_end_matches('/'));
+                ureq::put(&url)
+                    .send_bytes(content)
+                    .map(drop)
+                    .map_err(std::io::Error::other)
+            }
+        }
+    }
+}
+
+/// The cache key of an artifact with the given `extension` depending on `parts`.
+/// Unlike `DefaultHasher`, FNV-1a gives the same key on every platform and Rust
+/// version, which matters once the cache is shared.
+pub(crate) fn key(parts: &[Vec<u8>], extension: &str) -> String {
+    const OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+    let mut hash = OFFSET;
+    for part in parts {
+        // Length prefixed so that moving bytes between parts changes the key.
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(part) {
+            hash ^= u128::from(*byte);
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+    format!("{hash:032x}.{extension}")
+}
@@ -0,0 +1,233 @@
+//! Builder configuration kept as data, see [`crate::Builder::config_file`].
+use crate::{
+    expand_braces, glob_paths, parse_compute_caps, rerun_if_changed, Builder, ComputeCapSpec,
+    KernelConfig,
+};
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// What [`Builder::build`] builds, the `[output]` table of the config file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Target {
+    /// PTX, with the bindings written to the given file if any.
+    Ptx(Option<PathBuf>),
+    /// A static library with the given name.
+    Lib(String),
+    /// A shared library with the given name.
+    Shared(String),
+}
+
+/// A parsed config file, panicking with the offending key on invalid entries.
+struct Config<'a> {
+    path: &'a Path,
+}
+
+impl Config<'_> {
+    fn invalid(&self, key: &str, expected: &str) -> ! {
+        panic!("{}: `{key}` must be {expected}", self.path.display())
+    }
+
+    fn check_keys(&self, table: &Table, section: &str, known: &[&str]) {
+        for key in table.keys() {
+            if !known.contains(&key.as_str()) {
+                panic!(
+                    "{}: unknown key `{section}{key}`, expected one of {known:?}",
+                    self.path.display()
+                );
+            }
+        }
+    }
+
+    fn string(&self, table: &Table, key: &str) -> Option<String> {
+        table.get(key).map(|value| match value {
+            Value::String(s) => s.clone(),
+            _ => self.invalid(key, "a string"),
+        })
+    }
+
+    fn strings(&self, table: &Table, key: &str) -> Option<Vec<String>> {
+        table.get(key).map(|value| match value {
+            Value::String(s) => vec![s.clone()],
+            Value::Array(values) => values
+                .iter()
+                .map(|value| match value {
+                    Value::String(s) => s.clone(),
+                    _ => self.invalid(key, "a list of strings"),
+                })
+                .collect(),
+            _ => self.invalid(key, "a list of strings"),
+        })
+    }
+
+    /// `-DNAME=value` arguments, or `-DNAME` for `true`.
+    fn defines(&self, table: &Table, key: &str) -> Vec<String> {
+        let Some(value) = table.get(key) else {
+            return vec![];
+        };
+        let Value::Table(defines) = value else {
+            self.invalid(key, "a table of defines");
+        };
+        defines
+            .iter()
+            .filter_map(|(name, value)| match value {
+                Value::Boolean(true) => Some(format!("-D{name}")),
+                Value::Boolean(false) => None,
+                Value::String(s) => Some(format!("-D{name}={s}")),
+                Value::Integer(i) => Some(format!("-D{name}={i}")),
+                Value::Float(f) => Some(format!("-D{name}={f:?}")),
+                _ => self.invalid(key, "a table of strings, numbers or booleans"),
+            })
+            .collect()
+    }
+
+    /// A [`Builder::compute_cap_spec`], given as is or as a list of compute caps
+    /// written like in `CUDA_COMPUTE_CAP`: `89`, `8.9` or `"sm_89"`.
+    fn arch(&self, table: &Table) -> Option<ComputeCapSpec> {
+        let invalid = || -> ! { self.invalid("arch", "a specification or a list of compute caps") };
+        let compute_cap = |value: &Value| match value {
+            Value::Integer(cap) => cap.to_string(),
+            Value::Float(cap) => format!("{cap:?}"),
+            Value::String(cap) => cap.clone(),
+            _ => invalid(),
+        };
+        table.get("arch").map(|value| match value {
+            Value::String(spec) => ComputeCapSpec::parse(spec).unwrap_or_else(|| invalid()),
+            Value::Array(caps) => {
+                let caps: Vec<_> = caps.iter().map(compute_cap).collect();
+                ComputeCapSpec::List(
+                    parse_compute_caps(&caps.join(";")).unwrap_or_else(|| invalid()),
+                )
+            }
+            value => ComputeCapSpec::List(
+                parse_compute_caps(&compute_cap(value)).unwrap_or_else(|| invalid()),
+            ),
+        })
+    }
+
+    fn kernel(&self, table: &Table) -> KernelConfig {
+        self.check_keys(
+            table,
+            "kernel.",
+            &["path", "name", "args", "defines", "entry_points"],
+        );
+        let path = self
+            .string(table, "path")
+            .unwrap_or_else(|| self.invalid("kernel.path", "set for every [[kernel]]"));
+        let mut kernel = KernelConfig::new(path);
+        if let Some(name) = self.string(table, "name") {
+            kernel = kernel.name(&name);
+        }
+        for arg in self.strings(table, "args").unwrap_or_default() {
+            kernel = kernel.arg(arg);
+        }
+        for define in self.defines(table, "defines") {
+            kernel = kernel.arg(define);
+        }
+        if let Some(entry_points) = self.strings(table, "entry_points") {
+            kernel = kernel.entry_points(entry_points);
+        }
+        kernel
+    }
+
+    fn output(&self, table: &Table) -> Target {
+        self.check_keys(table, "output.", &["mode", "bindings", "name"]);
+        let name = || {
+            self.string(table, "name")
+                .unwrap_or_else(|| self.invalid("output.name", "set for libraries"))
+        };
+        match self.string(table, "mode").as_deref() {
+            None | Some("ptx") => Target::Ptx(self.string(table, "bindings").map(PathBuf::from)),
+            Some("lib") => Target::Lib(name()),
+            Some("shared") => Target::Shared(name()),
+            Some(_) => self.invalid("output.mode", "\"ptx\", \"lib\" or \"shared\""),
+        }
+    }
+}
+
+/// Applies the config file at `path` on top of `builder`.
+pub(crate) fn apply(mut builder: Builder, path: &Path) -> Builder {
+    rerun_if_changed(path);
+    let content = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Could not read {}: {e}", path.display()));
+    let table: Table = content
+        .parse()
+        .unwrap_or_else(|e| panic!("Invalid {}: {e}", path.display()));
+    let config = Config { path };
+    config.check_keys(
+        &table,
+        "",
+        &[
+            "kernels", "exclude", "include", "args", "defines", "arch", "kernel", "output",
+        ],
+    );
+
+    if let Some(patterns) = config.strings(&table, "kernels") {
+        let mut kernel_paths = vec![];
+        for pattern in patterns {
+            kernel_paths.extend(
+                glob_paths(&pattern, &builder.glob_options)
+                    .unwrap_or_else(|e| panic!("Invalid pattern {pattern:?}: {e}")),
+            );
+        }
+        kernel_paths.sort();
+        kernel_paths.dedup();
+        builder.kernel_paths = kernel_paths;
+        builder.add_configured_kernels();
+    }
+    if let Some(patterns) = config.strings(&table, "exclude") {
+        let options = builder.glob_options.match_options();
+        let excluded: Vec<_> = patterns
+            .iter()
+            .flat_map(|pattern| expand_braces(pattern))
+            .map(|pattern| {
+                glob::Pattern::new(&pattern)
+                    .unwrap_or_else(|e| panic!("Invalid pattern {pattern:?}: {e}"))
+            })
+            .collect();
+        builder.kernel_paths.retain(|path| {
+            !excluded
+                .iter()
+                .any(|pattern| pattern.matches_path_with(path, options))
+        });
+        // Sources configured with `[[kernel]]` or `Builder::kernel` are always compiled.
+        builder.add_configured_kernels();
+    }
+    if let Some(patterns) = config.strings(&table, "include") {
+        let mut include_paths = vec![];
+        for pattern in patterns {
+            // Plain paths, directories in particular, are given as is.
+            if !pattern.contains(['*', '?', '[', '{']) {
+                include_paths.push(PathBuf::from(pattern));
+                continue;
+            }
+            include_paths.extend(
+                glob_paths(&pattern, &builder.glob_options)
+                    .unwrap_or_else(|e| panic!("Invalid pattern {pattern:?}: {e}")),
+            );
+        }
+        builder.include_paths = include_paths;
+    }
+    builder = builder.args(config.strings(&table, "args").unwrap_or_default());
+    builder = builder.args(config.defines(&table, "defines"));
+    if let Some(spec) = config.arch(&table) {
+        builder.compute_cap_spec = Some(spec);
+    }
+    match table.get("kernel") {
+        None => {}
+        Some(Value::Array(kernels)) => {
+            for kernel in kernels {
+                let Value::Table(kernel) = kernel else {
+                    config.invalid("kernel", "an array of tables, [[kernel]]");
+                };
+                builder = builder.kernel(config.kernel(kernel));
+            }
+        }
+        Some(_) => config.invalid("kernel", "an array of tables, [[kernel]]"),
+    }
+    match table.get("output") {
+        None => {}
+        Some(Value::Table(output)) => builder.target = Some(config.output(output)),
+        Some(_) => config.invalid("output", "a table, [output]"),
+    }
+    builder
+}
@@ -0,0 +1,348 @@
+//! Canned CUDA environments reproducing commonly reported setups, to test
+//! detection logic (here and in downstream build scripts) without the actual hardware.
+//! ```
+//! # std::env::set_var("OUT_DIR", std::env::temp_dir());
+//! use bindgen_cuda::{fixtures, Builder};
+//! let builder = Builder::default().probe(fixtures::healthy());
+//! assert_eq!(builder.compute_cap().unwrap(), 89);
+//! ```
+use crate::Probe;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A fake environment, answering the builder's probes with canned values.
+#[derive(Debug, Clone, Default)]
+pub struct Fixture {
+    vars: HashMap<String, String>,
+    outputs: HashMap<String, String>,
+    files: Vec<PathBuf>,
+    contents: HashMap<PathBuf, String>,
+    links: HashMap<PathBuf, PathBuf>,
+    /// Shared by clones, so that a fixture given to a builder can still be inspected.
+    commands: Arc<Mutex<Vec<String>>>,
+}
+
+impl Fixture {
+    /// An environment where nothing is installed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the environment variable `key`.
+    pub fn var(mut self, key: &str, value: &str) -> Self {
+        self.vars.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Sets the standard output of `program` run with `args`.
+    pub fn output(mut self, program: &str, args: &[&str], stdout: &str) -> Self {
+        self.outputs
+            .insert(command_key(program, args), stdout.to_string());
+        self
+    }
+
+    /// The commands the builder asked this fixture or its clones to run, like
+    /// `nvcc --version`, in order.
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.lock().expect("fixture commands lock").clone()
+    }
+
+    /// Marks `path` as an existing file.
+    pub fn file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.files.push(path.into());
+        self
+    }
+
+    /// Marks `path` as an existing file containing `content`.
+    pub fn file_content<P: Into<PathBuf>>(mut self, path: P, content: &str) -> Self {
+        let path = path.into();
+        self.contents.insert(path.clone(), content.to_string());
+        self.file(path)
+    }
+
+    /// Marks `link` as a symlink to the existing file `target`, like the `/usr/bin/nvcc`
+    /// distributions install.
+    /// ```
+    /// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+    /// use bindgen_cuda::{fixtures::Fixture, Builder};
+    /// let fixture = Fixture::new()
+    ///     .var("PATH", "/usr/bin")
+    ///     .file("/usr/local/cuda-12.4/bin/nvcc")
+    ///     .symlink("/usr/bin/nvcc", "/usr/local/cuda-12.4/bin/nvcc")
+    ///     .toolkit("/usr/local/cuda-12.4");
+    /// let builder = Builder::default().probe(fixture);
+    /// assert_eq!(
+    ///     builder.cuda_root_dir().unwrap(),
+    ///     std::path::Path::new("/usr/local/cuda-12.4")
+    /// );
+    /// ```
+    pub fn symlink<P: Into<PathBuf>, Q: Into<PathBuf>>(mut self, link: P, target: Q) -> Self {
+        let link = link.into();
+        self.links.insert(link.clone(), target.into());
+        self.file(link)
+    }
+
+    /// A CUDA toolkit installed in `root`, `cuda.h` included.
+    pub fn toolkit<P: Into<PathBuf>>(self, root: P) -> Self {
+        self.file(root.into().join("include").join("cuda.h"))
+    }
+
+    /// A GPU reported by `nvidia-smi` with the given compute cap (`"8.9"`) and driver version.
+    pub fn nvidia_smi(self, compute_cap: &str, driver_version: &str) -> Self {
+        self.nvidia_smi_at("nvidia-smi", compute_cap, driver_version)
+    }
+
+    /// Like [`Fixture::nvidia_smi`], for an `nvidia-smi` which is not in the `PATH`.
+    pub fn nvidia_smi_at(self, program: &str, compute_cap: &str, driver_version: &str) -> Self {
+        self.output(
+            program,
+            &["--query-gpu=compute_cap", "--format=csv"],
+            &format!("compute_cap\n{compute_cap}\n"),
+        )
+        .output(
+            program,
+            &["--query-gpu=driver_version", "--format=csv"],
+            &format!("driver_version\n{driver_version}\n"),
+        )
+    }
+
+    /// Visual Studio installed in `installation` with the given MSVC toolset (`"14.38.33130"`),
+    /// as reported by `vswhere`.
+    pub fn msvc<P: Into<PathBuf>>(self, installation: P, toolset: &str) -> Self {
+        let installation = installation.into();
+        let vswhere = "C:/Program Files (x86)/Microsoft Visual Studio/Installer/vswhere.exe";
+        let vc = installation.join("VC");
+        self.output(
+            vswhere,
+            &[
+                "-latest",
+                "-products",
+                "*",
+                "-requires",
+                "Microsoft.VisualStudio.Component.VC.Tools.x86.x64",
+                "-version",
+                "[15.0,18.0)",
+                "-property",
+                "installationPath",
+            ],
+            &format!("{}\r\n", installation.display()),
+        )
+        .file_content(
+            vc.join("Auxiliary/Build/Microsoft.VCToolsVersion.default.txt"),
+            &format!("{toolset}\r\n"),
+        )
+        .file(vc.join(format!("Tools/MSVC/{toolset}/bin/Hostx64/x64/cl.exe")))
+    }
+
+    /// An `nvcc` of the given release (`"12.4"`) able to target the given gpu codes.
+    pub fn nvcc(self, release: &str, gpu_codes: &[usize]) -> Self {
+        let codes: String = gpu_codes.iter().map(|c| format!("sm_{c}\n")).collect();
+        let version = format!(
+            "nvcc: NVIDIA (R) Cuda compiler driver\n\
+             Copyright (c) 2005-2024 NVIDIA Corporation\n\
+             Cuda compilation tools, release {release}, V{release}.0\n"
+        );
+        self.output("nvcc", &["--list-gpu-code"], &codes)
+            .output("nvcc", &["--version"], &version)
+    }
+}
+
+impl Probe for Fixture {
+    fn var(&self, key: &str) -> Option<String> {
+        self.vars.get(key).cloned()
+    }
+
+    fn output(&self, program: &str, args: &[&str]) -> Option<String> {
+        let key = command_key(program, args);
+        self.commands
+            .lock()
+            .expect("fixture commands lock")
+            .push(key.clone());
+        self.outputs.get(&key).cloned()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.iter().any(|f| f == path)
+    }
+
+    fn read_to_string(&self, path: &Path) -> Option<String> {
+        self.contents.get(path).cloned()
+    }
+
+    fn canonicalize(&self, path: &Path) -> Option<PathBuf> {
+        match self.links.get(path) {
+            Some(target) => Some(target.clone()),
+            None => self.is_file(path).then(|| path.to_path_buf()),
+        }
+    }
+}
+
+fn command_key(program: &str, args: &[&str]) -> String {
+    std::iter::once(program)
+        .chain(args.iter().copied())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+const CUDA_11_CODES: &[usize] = &[35, 37, 50, 52, 53, 60, 61, 62, 70, 72, 75, 80, 86, 87];
+const CUDA_12_CODES: &[usize] = &[50, 52, 53, 60, 61, 62, 70, 72, 75, 80, 86, 87, 89, 90];
+
+/// A working setup: CUDA 12.4 in `/usr/local/cuda` and an sm_89 GPU.
+pub fn healthy() -> Fixture {
+    Fixture::new()
+        .toolkit("/usr/local/cuda")
+        .nvidia_smi("8.9", "550.54")
+        .nvcc("12.4", CUDA_12_CODES)
+}
+
+/// The toolkit is installed but `nvidia-smi` is not, like in most CI containers.
+/// Detection fails unless `CUDA_COMPUTE_CAP` is set.
+/// ```
+/// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+/// use bindgen_cuda::{fixtures, Builder, Error};
+/// let builder = Builder::default().probe(fixtures::smi_missing());
+/// assert!(matches!(builder.compute_cap(), Err(Error::NvidiaSmi(_))));
+///
+/// let builder = Builder::default().probe(fixtures::smi_missing().var("CUDA_COMPUTE_CAP", "80"));
+/// assert_eq!(builder.compute_cap().unwrap(), 80);
+/// ```
+pub fn smi_missing() -> Fixture {
+    Fixture::new()
+        .toolkit("/usr/local/cuda")
+        .nvcc("12.4", CUDA_12_CODES)
+}
+
+/// The GPU is more recent than what the toolkit can target, an H100 with CUDA 11.7.
+/// ```
+/// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+/// use bindgen_cuda::{fixtures, Builder, Error};
+/// let builder = Builder::default().probe(fixtures::smi_newer_than_nvcc());
+/// assert!(matches!(
+///     builder.compute_cap(),
+///     Err(Error::UnsupportedComputeCap { compute_cap: 90, .. })
+/// ));
+/// ```
+pub fn smi_newer_than_nvcc() -> Fixture {
+    Fixture::new()
+        .toolkit("/usr/local/cuda")
+        .nvidia_smi("9.0", "535.104")
+        .nvcc("11.7", CUDA_11_CODES)
+}
+
+/// The toolkit (12.4) is more recent than what the driver supports (525, CUDA 12.0).
+/// Compilation works, but the produced PTX is rejected when loaded at runtime.
+/// ```
+/// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+/// use bindgen_cuda::{fixtures, Builder};
+/// let builder = Builder::default().probe(fixtures::nvcc_newer_than_driver());
+/// assert_eq!(builder.compute_cap().unwrap(), 86);
+/// ```
+pub fn nvcc_newer_than_driver() -> Fixture {
+    Fixture::new()
+        .toolkit("/usr/local/cuda")
+        .nvidia_smi("8.6", "525.60")
+        .nvcc("12.4", CUDA_12_CODES)
+}
+
+/// A standard Windows install, only findable through `CUDA_PATH`, next to
+/// Visual Studio 2022.
+/// ```
+/// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+/// use bindgen_cuda::{fixtures, Builder};
+/// let builder = Builder::default().probe(fixtures::windows());
+/// assert_eq!(builder.compute_cap().unwrap(), 86);
+/// ```
+pub fn windows() -> Fixture {
+    let root = "C:/Program Files/NVIDIA GPU Computing Toolkit/CUDA/v12.2";
+    Fixture::new()
+        .var("CUDA_PATH", root)
+        .var("CARGO_CFG_TARGET_ENV", "msvc")
+        .toolkit(root)
+        .nvidia_smi("8.6", "536.67")
+        .nvcc("12.2", CUDA_12_CODES)
+        .msvc(
+            "C:/Program Files/Microsoft Visual Studio/2022/Community",
+            "14.38.33130",
+        )
+}
+
+/// A Jetson Orin: JetPack 5 ships CUDA 11.4 and no `nvidia-smi` at all, so
+/// `CUDA_COMPUTE_CAP=87` has to be set.
+/// ```
+/// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+/// use bindgen_cuda::{fixtures, Builder, Error};
+/// let builder = Builder::default().probe(fixtures::jetson_orin());
+/// assert!(matches!(builder.compute_cap(), Err(Error::NvidiaSmi(_))));
+/// ```
+pub fn jetson_orin() -> Fixture {
+    Fixture::new()
+        .toolkit("/usr/local/cuda")
+        .nvcc("11.4", CUDA_11_CODES)
+}
+
+/// Ubuntu under WSL2: the toolkit is installed in the distribution, while the driver,
+/// `nvidia-smi` included, comes from Windows through `/usr/lib/wsl/lib`, outside the `PATH`.
+/// ```
+/// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+/// use bindgen_cuda::{fixtures, Builder};
+/// let builder = Builder::default().probe(fixtures::wsl2());
+/// assert_eq!(builder.compute_cap().unwrap(), 86);
+/// ```
+pub fn wsl2() -> Fixture {
+    Fixture::new()
+        .var("WSL_DISTRO_NAME", "Ubuntu")
+        .toolkit("/usr/local/cuda")
+        .file("/usr/local/cuda/lib64/libcudart.so")
+        .file("/usr/lib/wsl/lib/libcuda.so")
+        .nvidia_smi_at("/usr/lib/wsl/lib/nvidia-smi", "8.6", "551.61")
+        .nvcc("12.4", CUDA_12_CODES)
+}
+
+/// CUDA 12.4 made available by an HPC environment module (`module load cuda/12.4`),
+/// in a prefix only known through the `nvcc` it puts in the `PATH`.
+/// ```
+/// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+/// use bindgen_cuda::{fixtures, Builder};
+/// let builder = Builder::default().probe(fixtures::environment_module());
+/// assert_eq!(
+///     builder.cuda_root_dir().unwrap(),
+///     std::path::Path::new("/sw/cuda/12.4")
+/// );
+/// ```
+pub fn environment_module() -> Fixture {
+    Fixture::new()
+        .var("PATH", "/sw/cuda/12.4/bin")
+        .file("/sw/cuda/12.4/bin/nvcc")
+        .toolkit("/sw/cuda/12.4")
+        .nvidia_smi("8.0", "550.54")
+        .nvcc("12.4", CUDA_12_CODES)
+}
+
+/// CUDA 12.4 installed with `conda install cuda-toolkit` in the active environment.
+pub fn conda() -> Fixture {
+    Fixture::new()
+        .var("CONDA_PREFIX", "/opt/conda/envs/ml")
+        .toolkit("/opt/conda/envs/ml/targets/x86_64-linux")
+        .nvidia_smi("8.0", "550.54")
+        .nvcc("12.4", CUDA_12_CODES)
+}
+
+/// The CUDA 12 pip wheels (`nvidia-cuda-runtime-cu12`, `nvidia-cuda-nvcc-cu12`)
+/// installed in the active virtual environment.
+/// ```
+/// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+/// use bindgen_cuda::{fixtures, Builder};
+/// let builder = Builder::default().probe(fixtures::pip_wheels());
+/// assert!(builder
+///     .cuda_root_dir()
+///     .unwrap()
+///     .ends_with("site-packages/nvidia/cuda_runtime"));
+/// ```
+pub fn pip_wheels() -> Fixture {
+    Fixture::new()
+        .var("VIRTUAL_ENV", "/home/user/project/.venv")
+        .toolkit("/home/user/project/.venv/lib/python3.11/site-packages/nvidia/cuda_runtime")
+        .nvidia_smi("8.9", "550.54")
+        .nvcc("12.4", CUDA_12_CODES)
+}
@@ -0,0 +1,201 @@
+//! Lightweight `#include` scanning, to know which headers a kernel depends on
+//! without running the preprocessor.
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// The header named by an `#include` directive, if `line` is one.
+fn included(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start();
+    let rest = rest.strip_prefix("include")?.trim_start();
+    let (open, close) = match rest.chars().next()? {
+        '"' => ('"', '"'),
+        '<' => ('<', '>'),
+        _ => return None,
+    };
+    let rest = rest.strip_prefix(open)?;
+    rest.find(close).map(|end| &rest[..end])
+}
+
+/// `path` with its `.` and `..` components resolved lexically, so that the same
+/// header reached through different directories is seen once. `..` components at
+/// the start of relative paths are kept.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    use std::path::Component;
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                // The parent of the root is the root.
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            component => normalized.push(component),
+        }
+    }
+    if normalized.as_os_str().is_empty() {
+        normalized.push(".");
+    }
+    normalized
+}
+
+/// Resolves `header` the way the preprocessor would, first next to the including
+/// file, then in each of the `include_dirs`. Headers found nowhere (system headers,
+/// CUDA headers) are ignored. Also returns the include dir it was found through,
+/// if it wasn't found next to the including file.
+fn resolve<'a>(
+    header: &str,
+    including: &Path,
+    include_dirs: &'a [PathBuf],
+) -> Option<(PathBuf, Option<&'a Path>)> {
+    let sibling = including
+        .parent()
+        .map(|dir| dir.join(header))
+        .filter(|path| path.is_file());
+    if let Some(path) = sibling {
+        return Some((normalize(&path), None));
+    }
+    include_dirs
+        .iter()
+        .map(|dir| (dir.join(header), Some(dir.as_path())))
+        .find(|(path, _)| path.is_file())
+        .map(|(path, dir)| (normalize(&path), dir))
+}
+
+/// Visits every header transitively included by `source`, along with the include
+/// dir it was found through.
+fn walk<'a>(
+    source: &Path,
+    include_dirs: &'a [PathBuf],
+    mut visit: impl FnMut(&Path, Option<&'a Path>),
+) {
+    let mut headers = BTreeSet::new();
+    let mut queue = vec![source.to_path_buf()];
+    while let Some(file) = queue.pop() {
+        let Ok(content) = std::fs::read_to_string(&file) else {
+            continue;
+        };
+        for header in content.lines().filter_map(included) {
+            if let Some((path, dir)) = resolve(header, &file, include_dirs) {
+                visit(&path, dir);
+                if headers.insert(path.clone()) {
+                    queue.push(path);
+                }
+            }
+        }
+    }
+}
+
+/// Every header transitively included by `source` which can be found in the source
+/// tree or in `include_dirs`.
+pub(crate) fn closure(source: &Path, include_dirs: &[PathBuf]) -> BTreeSet<PathBuf> {
+    let mut headers = BTreeSet::new();
+    walk(source, include_dirs, |path, _| {
+        headers.insert(path.to_path_buf());
+    });
+    headers
+}
+
+/// The `include_dirs` which at least one header transitively included by `source`
+/// is only found through.
+pub(crate) fn used_dirs(source: &Path, include_dirs: &[PathBuf]) -> BTreeSet<PathBuf> {
+    let mut dirs = BTreeSet::new();
+    walk(source, include_dirs, |_, dir| {
+        dirs.extend(dir.map(Path::to_path_buf));
+    });
+    dirs
+}
+
+/// The prerequisites of the make rules of a dependency file, as written by nvcc with
+/// `--generate-dependencies-with-compile`. Spaces in paths are escaped with `\`.
+pub(crate) fn dependency_file(content: &str) -> Vec<PathBuf> {
+    let content = content.replace("\\\r\n", " ").replace("\\\n", " ");
+    let mut dependencies = vec![];
+    for line in content.lines() {
+        // Targets end with a colon followed by a space, unlike drive letters.
+        let Some(start) = line
+            .find(": ")
+            .or(line.ends_with(':').then(|| line.len() - 1))
+        else {
+            continue;
+        };
+        let mut dependency = String::new();
+        let mut chars = line[start + 1..].chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if matches!(chars.peek(), Some(' ' | '#')) => {
+                    dependency.extend(chars.next());
+                }
+                '$' if chars.peek() == Some(&'$') => {
+                    dependency.extend(chars.next());
+                }
+                c if c.is_whitespace() => {
+                    if !dependency.is_empty() {
+                        dependencies.push(PathBuf::from(std::mem::take(&mut dependency)));
+                    }
+                }
+                c => dependency.push(c),
+            }
+        }
+        if !dependency.is_empty() {
+            dependencies.push(PathBuf::from(dependency));
+        }
+    }
+    dependencies
+}
+
+/// `source` with the headers it includes from the source tree or `include_dirs`
+/// inlined, recursively, so that it compiles on its own. `#line` directives keep
+/// diagnostics pointing at the original files. Headers with `#pragma once` are only
+/// inlined once, the others are left to their include guards.
+pub(crate) fn inline(source: &Path, include_dirs: &[PathBuf]) -> std::io::Result<String> {
+    let mut inlined = String::new();
+    inline_into(
+        source,
+        include_dirs,
+        &mut vec![],
+        &mut BTreeSet::new(),
+        &mut inlined,
+    )?;
+    Ok(inlined)
+}
+
+fn inline_into(
+    file: &Path,
+    include_dirs: &[PathBuf],
+    stack: &mut Vec<PathBuf>,
+    once: &mut BTreeSet<PathBuf>,
+    inlined: &mut String,
+) -> std::io::Result<()> {
+    let content = std::fs::read_to_string(file)?;
+    stack.push(file.to_path_buf());
+    for (i, line) in content.lines().enumerate() {
+        let directive: String = line.split_whitespace().collect();
+        if directive == "#pragmaonce" {
+            once.insert(file.to_path_buf());
+            inlined.push('\n');
+            continue;
+        }
+        let header = included(line).and_then(|header| resolve(header, file, include_dirs));
+        let Some((header, _)) = header else {
+            inlined.push_str(line);
+            inlined.push('\n');
+            continue;
+        };
+        if !once.contains(&header) && !stack.contains(&header) {
+            inlined.push_str(&format!("#line 1 {}\n", line_path(&header)));
+            inline_into(&header, include_dirs, stack, once, inlined)?;
+        }
+        inlined.push_str(&format!("#line {} {}\n", i + 2, line_path(file)));
+    }
+    stack.pop();
+    Ok(())
+}
+
+/// `path` as a quoted `#line` file name.
+pub(crate) fn line_path(path: &Path) -> String {
+    format!("{:?}", path.to_string_lossy().replace('\\', "/"))
+}
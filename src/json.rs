@@ -0,0 +1,160 @@
+//! Reading back the JSON files the builder writes (manifests), without a dependency.
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A JSON value. Numbers are kept as written.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// The value of `key`, for objects.
+    pub(crate) fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_number<T: std::str::FromStr>(&self) -> Option<T> {
+        match self {
+            Json::Number(n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Parses `text`, `None` when it isn't a single valid JSON value.
+pub(crate) fn parse(text: &str) -> Option<Json> {
+    let mut chars = text.chars().peekable();
+    let value = value(&mut chars)?;
+    skip_whitespace(&mut chars);
+    chars.peek().is_none().then_some(value)
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn literal(chars: &mut Peekable<Chars>, word: &str, value: Json) -> Option<Json> {
+    for expected in word.chars() {
+        if chars.next()? != expected {
+            return None;
+        }
+    }
+    Some(value)
+}
+
+fn value(chars: &mut Peekable<Chars>) -> Option<Json> {
+    skip_whitespace(chars);
+    match *chars.peek()? {
+        'n' => literal(chars, "null", Json::Null),
+        't' => literal(chars, "true", Json::Bool(true)),
+        'f' => literal(chars, "false", Json::Bool(false)),
+        '"' => string(chars).map(Json::String),
+        '[' => {
+            chars.next();
+            let mut items = vec![];
+            skip_whitespace(chars);
+            if chars.peek() == Some(&']') {
+                chars.next();
+                return Some(Json::Array(items));
+            }
+            loop {
+                items.push(value(chars)?);
+                skip_whitespace(chars);
+                match chars.next()? {
+                    ',' => continue,
+                    ']' => return Some(Json::Array(items)),
+                    _ => return None,
+                }
+            }
+        }
+        '{' => {
+            chars.next();
+            let mut entries = vec![];
+            skip_whitespace(chars);
+            if chars.peek() == Some(&'}') {
+                chars.next();
+                return Some(Json::Object(entries));
+            }
+            loop {
+                skip_whitespace(chars);
+                let key = string(chars)?;
+                skip_whitespace(chars);
+                if chars.next()? != ':' {
+                    return None;
+                }
+                entries.push((key, value(chars)?));
+                skip_whitespace(chars);
+                match chars.next()? {
+                    ',' => continue,
+                    '}' => return Some(Json::Object(entries)),
+                    _ => return None,
+                }
+            }
+        }
+        c if c == '-' || c.is_ascii_digit() => {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+                    break;
+                }
+                number.push(c);
+                chars.next();
+            }
+            Some(Json::Number(number))
+        }
+        _ => None,
+    }
+}
+
+fn string(chars: &mut Peekable<Chars>) -> Option<String> {
+    if chars.next()? != '"' {
+        return None;
+    }
+    let mut s = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(s),
+            '\\' => match chars.next()? {
+                '"' => s.push('"'),
+                '\\' => s.push('\\'),
+                '/' => s.push('/'),
+                'b' => s.push('\u{8}'),
+                'f' => s.push('\u{c}'),
+                'n' => s.push('\n'),
+                'r' => s.push('\r'),
+                't' => s.push('\t'),
+                'u' => {
+                    let code: String = (0..4).map(|_| chars.next()).collect::<Option<_>>()?;
+                    s.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                _ => return None,
+            },
+            c => s.push(c),
+        }
+    }
+}
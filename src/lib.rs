@@ -1,15 +1,337 @@
 #![deny(missing_docs)]
+#![allow(clippy::needless_doctest_main)]
 #![doc = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/README.md"))]
 use rayon::prelude::*;
 use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::OsString;
 use std::hash::{Hash, Hasher};
-use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+mod cache;
+#[cfg(feature = "toml")]
+mod config;
+pub mod fixtures;
+mod includes;
+mod json;
+mod pipeline;
+
+pub use cache::ArtifactCache;
+/// The `cc` version [`Builder::host_build`] works with.
+#[cfg(feature = "cc")]
+pub use cc;
+pub use pipeline::Pipeline;
 
 /// Error messages
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// `CUDA_COMPUTE_CAP` is not a valid compute cap.
+    InvalidComputeCap(String),
+    /// The compute cap could not be detected with `nvidia-smi`.
+    NvidiaSmi(String),
+    /// `nvcc` could not be run, or its output could not be understood.
+    Nvcc(String),
+    /// The installed `nvcc` cannot target the requested compute cap.
+    UnsupportedComputeCap {
+        /// The requested compute cap.
+        compute_cap: usize,
+        /// The compute caps `nvcc` can target.
+        supported: Vec<usize>,
+    },
+    /// Several kernels map to the same constant or output file name.
+    NameCollision {
+        /// The colliding name.
+        name: String,
+        /// The kernels sharing it.
+        paths: Vec<PathBuf>,
+    },
+    /// The steps of a [`Pipeline`] cannot be ordered.
+    Pipeline(String),
+    /// A generated file could not be written.
+    Io(String),
+    /// The CUDA toolkit doesn't match [`Builder::require_cuda`].
+    CudaRequirement {
+        /// The requirement, like `>=11.8`.
+        required: String,
+        /// The version `nvcc --version` reported.
+        found: String,
+    },
+    /// The NVIDIA driver doesn't match [`Builder::require_driver`].
+    DriverRequirement {
+        /// The requirement, like `>=520`.
+        required: String,
+        /// The version `nvidia-smi` reported.
+        found: String,
+    },
+    /// A kernel lacks entry points listed with [`KernelConfig::entry_points`].
+    MissingEntryPoints {
+        /// The kernel name, as in `ALL_KERNELS`.
+        kernel: String,
+        /// The entry points its PTX doesn't define.
+        missing: Vec<String>,
+    },
+    /// nvcc was killed for running past [`Builder::timeout`] or [`Builder::build_timeout`].
+    Timeout {
+        /// The file nvcc was working on.
+        input: PathBuf,
+        /// What nvcc was doing, like `"compiling"`.
+        action: &'static str,
+        /// How long nvcc ran before being killed.
+        elapsed: std::time::Duration,
+    },
+    /// nvcc failed to compile some kernels.
+    Compilation {
+        /// The files nvcc failed on.
+        inputs: Vec<PathBuf>,
+        /// What nvcc reported, deduplicated across kernels and architectures.
+        diagnostics: Vec<Diagnostic>,
+        /// The failed commands and their raw output.
+        output: String,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidComputeCap(compute_cap) => write!(
+                f,
+                "Could not parse CUDA_COMPUTE_CAP={compute_cap:?}, expected compute caps like \
+                 `89`, `8.9`, `sm_89` or `compute_89`, several ones being separated by commas \
+                 (`80,89,90`)"
+            ),
+            Error::NvidiaSmi(message) => write!(f, "{message}"),
+            Error::Nvcc(message) => write!(f, "{message}"),
+            Error::UnsupportedComputeCap {
+                compute_cap,
+                supported,
+            } => write!(
+                f,
+                "nvcc cannot target gpu arch {compute_cap}. Available nvcc targets are {supported:?}."
+            ),
+            Error::Pipeline(message) => write!(f, "{message}"),
+            Error::Io(message) => write!(f, "{message}"),
+            Error::CudaRequirement { required, found } => write!(
+                f,
+                "CUDA {required} is required, found CUDA {found}. Install a matching toolkit, \
+                 or point CUDA_PATH at one and put its nvcc first in the PATH."
+            ),
+            Error::DriverRequirement { required, found } => write!(
+                f,
+                "NVIDIA driver {required} is required, found driver {found}. Update the driver \
+                 to run the kernels compiled by this CUDA toolkit."
+            ),
+            Error::MissingEntryPoints { kernel, missing } => write!(
+                f,
+                "Kernel {kernel} doesn't define the entry points {missing:?}, they may be \
+                 excluded by preprocessor conditions or be templates which are never instantiated."
+            ),
+            Error::Timeout {
+                input,
+                action,
+                elapsed,
+            } => write!(
+                f,
+                "nvcc was killed after {:.1}s {action} {}, which exceeded the time allowed by \
+                 `Builder::timeout` or `Builder::build_timeout`",
+                elapsed.as_secs_f64(),
+                input.display()
+            ),
+            Error::NameCollision { name, paths } if paths.windows(2).all(|w| w[0] == w[1]) => {
+                write!(
+                    f,
+                    "Kernel {:?} is configured several times but all map to {name}, see \
+                     `KernelConfig::name` to disambiguate them.",
+                    paths[0]
+                )
+            }
+            Error::NameCollision { name, paths } => write!(
+                f,
+                "Kernels {paths:?} all map to {name}, see `Builder::naming` to disambiguate them."
+            ),
+            Error::Compilation {
+                inputs,
+                diagnostics,
+                output,
+            } => {
+                let inputs = inputs
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                // Fall back to the raw output when nothing could be parsed from it.
+                if !diagnostics.iter().any(|d| d.severity == Severity::Error) {
+                    return write!(f, "nvcc failed on {inputs}\n{output}");
+                }
+                let errors = diagnostics
+                    .iter()
+                    .filter(|d| d.severity == Severity::Error)
+                    .count();
+                write!(f, "nvcc failed on {inputs} with {errors} error(s)")?;
+                for diagnostic in diagnostics {
+                    write!(f, "\n{diagnostic}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Where the builder gets information about the CUDA installation from.
+/// The default probe reads the process environment and runs `nvidia-smi` and `nvcc`,
+/// [`fixtures`] contains canned ones reproducing commonly broken setups.
+pub trait Probe: std::fmt::Debug + Send + Sync {
+    /// The value of the environment variable `key`.
+    fn var(&self, key: &str) -> Option<String>;
+    /// The standard output of `program` run with `args`, or `None` if it could not
+    /// be run or failed.
+    fn output(&self, program: &str, args: &[&str]) -> Option<String>;
+    /// Whether `path` exists and is a file.
+    fn is_file(&self, path: &Path) -> bool;
+    /// The content of the file at `path`, if it can be read.
+    fn read_to_string(&self, path: &Path) -> Option<String> {
+        std::fs::read_to_string(path).ok()
+    }
+    /// The absolute path of `path` with symlinks resolved, if it exists.
+    fn canonicalize(&self, path: &Path) -> Option<PathBuf> {
+        std::fs::canonicalize(path).ok()
+    }
+}
+
+/// Version of the layout of the files generated by [`Bindings::write`], bumped whenever
+/// generated items change in an incompatible way. Generated files export it as
+/// `BINDINGS_VERSION`, so code consuming them (like a runtime loader) can check at
+/// compile time that it was written against the same layout:
+/// ```ignore
+/// const _: () = assert!(
+///     kernels::BINDINGS_VERSION == 1,
+///     "kernel bindings were generated by an incompatible bindgen_cuda, rebuild them"
+/// );
+/// ```
+pub const BINDINGS_VERSION: u32 = 1;
+
+/// The type of the kernels generated by [`Builder::build_nvrtc`], compiled at runtime
+/// for the compute cap of the device.
+const NVRTC_KERNEL: &str = r#"/// A kernel compiled at runtime with NVRTC.
+#[derive(Debug, Clone, Copy)]
+pub struct NvrtcKernel {
+    /// The kernel name, as in `ALL_KERNELS`.
+    pub name: &'static str,
+    /// The kernel source, with its headers inlined.
+    pub source: &'static str,
+    /// The NVRTC options of the kernel, without the architecture.
+    pub options: &'static [&'static str],
+}
+impl NvrtcKernel {
+    /// The NVRTC options compiling the kernel for `compute_cap`.
+    pub fn options(&self, compute_cap: u32) -> Vec<String> {
+        let mut options: Vec<String> = self.options.iter().map(|o| o.to_string()).collect();
+        options.push(format!("--gpu-architecture=compute_{compute_cap}"));
+        options
+    }
+    /// Compiles the kernel for the device of `ctx`.
+    pub fn compile(
+        &self,
+        ctx: &cudarc::driver::CudaContext,
+    ) -> Result<cudarc::nvrtc::Ptx, Box<dyn std::error::Error>> {
+        use cudarc::driver::sys::CUdevice_attribute::*;
+        let major = ctx.attribute(CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR)?;
+        let minor = ctx.attribute(CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR)?;
+        let options = cudarc::nvrtc::CompileOptions {
+            options: self.options((major * 10 + minor) as u32),
+            name: Some(format!("{}.cu", self.name)),
+            ..Default::default()
+        };
+        Ok(cudarc::nvrtc::compile_ptx_with_opts(self.source, options)?)
+    }
+}
+"#;
+
+/// Compute caps for which [`Builder::emit_cfgs`] declares cfgs, whether they are
+/// targeted or not.
+const KNOWN_COMPUTE_CAPS: [usize; 20] = [
+    35, 37, 50, 52, 53, 60, 61, 62, 70, 72, 75, 80, 86, 87, 89, 90, 100, 101, 103, 120,
+];
+
+/// CUDA major versions for which [`Builder::emit_cfgs`] declares cfgs.
+const KNOWN_CUDA_MAJORS: [usize; 4] = [10, 11, 12, 13];
+
+/// Environment variables affecting how kernels are compiled. nvcc reads
+/// `NVCC_PREPEND_FLAGS` and `NVCC_APPEND_FLAGS` itself, `CUDA_NVCC_FLAGS` (the
+/// variable CMake uses) is appended by [`Builder`].
+const BUILD_VARS: [&str; 5] = [
+    "CUDA_COMPUTE_CAP",
+    "NVCC_CCBIN",
+    "NVCC_PREPEND_FLAGS",
+    "NVCC_APPEND_FLAGS",
+    "CUDA_NVCC_FLAGS",
+];
+
+/// Environment variables naming a compiler cache to run nvcc through, in order of
+/// precedence, see [`Builder::compiler_wrapper`].
+const WRAPPER_VARS: [&str; 2] = ["NVCC_WRAPPER", "CMAKE_CUDA_COMPILER_LAUNCHER"];
+
+/// Remembers the environment variables looked up through it, so they can be
+/// reported to cargo by [`Builder::emit_rerun_hints`].
 #[derive(Debug)]
-pub enum Error {}
+struct Recorder<'a> {
+    probe: &'a dyn Probe,
+    vars: &'a Mutex<BTreeSet<String>>,
+}
+
+impl Probe for Recorder<'_> {
+    fn var(&self, key: &str) -> Option<String> {
+        self.vars
+            .lock()
+            .expect("consulted vars lock")
+            .insert(key.to_string());
+        self.probe.var(key)
+    }
+
+    fn output(&self, program: &str, args: &[&str]) -> Option<String> {
+        self.probe.output(program, args)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.probe.is_file(path)
+    }
+
+    fn read_to_string(&self, path: &Path) -> Option<String> {
+        self.probe.read_to_string(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> Option<PathBuf> {
+        self.probe.canonicalize(path)
+    }
+}
+
+/// The [`Probe`] looking at the actual system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemProbe;
+
+impl Probe for SystemProbe {
+    fn var(&self, key: &str) -> Option<String> {
+        std::env::var(key).ok()
+    }
+
+    fn output(&self, program: &str, args: &[&str]) -> Option<String> {
+        let output = Command::new(program).args(args).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+}
 
 /// Core builder to setup the bindings options
 #[derive(Debug)]
@@ -18,9 +340,77 @@ pub struct Builder {
     kernel_paths: Vec<PathBuf>,
     watch: Vec<PathBuf>,
     include_paths: Vec<PathBuf>,
-    compute_cap: Option<usize>,
+    compute_cap: OnceLock<Result<usize, Error>>,
+    cuda_version: OnceLock<Result<(usize, usize), Error>>,
+    msvc: OnceLock<Option<PathBuf>>,
+    probe: Arc<dyn Probe>,
+    consulted_vars: Mutex<BTreeSet<String>>,
     out_dir: PathBuf,
-    extra_args: Vec<&'static str>,
+    extra_args: Vec<String>,
+    cpp_args: Vec<String>,
+    cpp_std: Option<CppStd>,
+    msvc_runtime: Option<MsvcRuntime>,
+    relaxed_constexpr: bool,
+    extended_lambda: bool,
+    versioned_args: Vec<(VersionReq, String)>,
+    cuda_requirements: Vec<(VersionReq, String)>,
+    driver_requirements: Vec<(VersionReq, String)>,
+    fat_compute_caps: Vec<usize>,
+    compute_cap_spec: Option<ComputeCapSpec>,
+    spec_compute_caps: OnceLock<Result<Vec<usize>, Error>>,
+    ptx_compute_caps: OnceLock<Vec<usize>>,
+    profile: Option<KernelProfile>,
+    virtual_arch: bool,
+    gpu_code: Vec<String>,
+    naming: Naming,
+    variants: Vec<Variant>,
+    kernel_configs: Vec<KernelConfig>,
+    unity_build: Option<String>,
+    deprecated_archs: DeprecatedArchs,
+    reproducible: bool,
+    device_lto: bool,
+    archiver: Archiver,
+    thin_archive: bool,
+    append_archive: bool,
+    fallback_stub: bool,
+    command_hooks: Vec<CommandHook>,
+    compiler_wrapper: Option<String>,
+    artifact_cache: Option<ArtifactCache>,
+    post_process: Vec<PostProcess>,
+    preprocess: Vec<Preprocess>,
+    /// The files written by the [`Builder::preprocess`] hooks, by source.
+    preprocessed: Mutex<BTreeMap<PathBuf, PathBuf>>,
+    /// Files and directories the jobs need, only written when they run so that plans
+    /// have no side effects.
+    pending: Mutex<PendingWrites>,
+    glob_options: GlobOptions,
+    response_file: Option<bool>,
+    num_threads: usize,
+    nvcc_threads: Option<usize>,
+    split_compile: Option<SplitCompile>,
+    report_timings: bool,
+    timings: Mutex<Vec<Timing>>,
+    job_server: JobServer,
+    staging: StagingMode,
+    single_threaded: bool,
+    keep_going: bool,
+    stream_output: bool,
+    timeout: Option<std::time::Duration>,
+    build_timeout: Option<std::time::Duration>,
+    /// When the first job of the build started, see [`Builder::build_timeout`].
+    started: OnceLock<std::time::Instant>,
+    keep_intermediates: bool,
+    provenance: bool,
+    /// The `--version` of the tools nvcc ran, see [`Builder::provenance`].
+    tool_versions: Mutex<BTreeMap<String, Option<String>>>,
+    dependency_files: bool,
+    ptx_per_compute_cap: bool,
+    cargo_profile: bool,
+    host_tool: Option<HostTool>,
+    verbosity: Verbosity,
+    /// What [`Builder::build`] builds, from the config file.
+    #[cfg(feature = "toml")]
+    target: Option<config::Target>,
 }
 
 impl Default for Builder {
@@ -28,60 +418,588 @@ impl Default for Builder {
         // Use only physical cores for rayon.
         // Builds can be super consuming and exhaust resources quite fast
         // like when building flash attention kernels
-        let num_cpus = std::env::var("RAYON_NUM_THREADS").map_or_else(
+        let num_threads = std::env::var("RAYON_NUM_THREADS").map_or_else(
             |_| num_cpus::get_physical(),
             |s| usize::from_str(&s).expect("RAYON_NUM_THREADS is not set to a valid integer"),
         );
+        let job_server = JobServer::from_env();
+        let single_threaded = cfg!(feature = "single-threaded")
+            || std::env::var("BINDGEN_CUDA_SINGLE_THREADED")
+                .is_ok_and(|s| !matches!(s.as_str(), "" | "0" | "false"));
 
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(num_cpus)
-            .build_global()
-            .expect("build rayon global threadpool");
+        let out_dir = std::env::var_os("OUT_DIR").expect("Expected OUT_DIR environement variable to be present, is this running within `build.rs`?").into();
 
-        let out_dir = std::env::var("OUT_DIR").expect("Expected OUT_DIR environement variable to be present, is this running within `build.rs`?").into();
-
-        let cuda_root = cuda_include_dir();
-        let kernel_paths = default_kernels().unwrap_or_default();
-        let include_paths = default_include().unwrap_or_default();
+        let probe: Arc<dyn Probe> = Arc::new(SystemProbe);
+        let consulted_vars = Mutex::default();
+        // The `ci-check` feature pretends the system has a toolkit, probes set with
+        // `Builder::probe` still decide for themselves.
+        let cuda_root = if cfg!(feature = "ci-check") {
+            Some("ci".into())
+        } else {
+            cuda_include_dir(&Recorder {
+                probe: &*probe,
+                vars: &consulted_vars,
+            })
+        };
+        let glob_options = GlobOptions::default();
+        let kernel_paths = default_kernels(&glob_options).unwrap_or_default();
+        let include_paths = default_include(&glob_options).unwrap_or_default();
         let extra_args = vec![];
         let watch = vec![];
-        let compute_cap = compute_cap().ok();
+        let compute_cap = OnceLock::new();
         Self {
             cuda_root,
             kernel_paths,
             watch,
             include_paths,
             extra_args,
+            cpp_args: vec![],
+            cpp_std: None,
+            msvc_runtime: None,
+            relaxed_constexpr: false,
+            extended_lambda: false,
+            versioned_args: vec![],
+            cuda_requirements: vec![],
+            driver_requirements: vec![],
+            fat_compute_caps: vec![],
+            compute_cap_spec: None,
+            spec_compute_caps: OnceLock::new(),
+            ptx_compute_caps: OnceLock::new(),
+            profile: None,
+            virtual_arch: false,
+            gpu_code: vec![],
+            naming: Naming::default(),
+            variants: vec![],
+            kernel_configs: vec![],
+            unity_build: None,
+            deprecated_archs: DeprecatedArchs::default(),
+            reproducible: false,
+            device_lto: false,
+            archiver: Archiver::default(),
+            thin_archive: false,
+            append_archive: false,
+            fallback_stub: false,
+            command_hooks: vec![],
+            compiler_wrapper: None,
+            artifact_cache: None,
+            post_process: vec![],
+            preprocess: vec![],
+            preprocessed: Mutex::default(),
+            pending: Mutex::default(),
             compute_cap,
+            cuda_version: OnceLock::new(),
+            msvc: OnceLock::new(),
             out_dir,
+            glob_options,
+            response_file: None,
+            num_threads,
+            nvcc_threads: None,
+            split_compile: None,
+            report_timings: false,
+            timings: Mutex::default(),
+            job_server,
+            staging: StagingMode::default(),
+            single_threaded,
+            keep_going: false,
+            stream_output: false,
+            timeout: None,
+            build_timeout: None,
+            started: OnceLock::new(),
+            keep_intermediates: false,
+            provenance: false,
+            tool_versions: Mutex::default(),
+            dependency_files: false,
+            ptx_per_compute_cap: false,
+            cargo_profile: false,
+            host_tool: None,
+            verbosity: Verbosity::default(),
+            #[cfg(feature = "toml")]
+            target: None,
+            probe,
+            consulted_vars,
         }
     }
 }
 
 /// Helper struct to create a rust file when buildings PTX files.
 pub struct Bindings {
-    write: bool,
     paths: Vec<PathBuf>,
+    /// Name of each kernel, see [`Builder::variant`].
+    names: Vec<String>,
+    /// Path of each compiled kernel, relative to `OUT_DIR`.
+    outputs: Vec<String>,
+    kind: Output,
+    device_functions: Vec<Vec<String>>,
+    /// Kernels (`.entry`) of each PTX module.
+    entry_points: Vec<Vec<String>>,
+    /// NVRTC options of each kernel, see [`Builder::build_nvrtc`].
+    nvrtc_options: Vec<Vec<String>>,
+    /// Compute cap of each kernel, see [`Builder::ptx_per_compute_cap`].
+    compute_caps: Vec<Option<usize>>,
+    kernel_enum: bool,
+    smoke_tests: bool,
+    modules: bool,
+    naming: Naming,
+    manifest: Manifest,
+    stub: bool,
+    out_dir: PathBuf,
+    compression: Option<Compression>,
+    doc_comments: bool,
+    allow_lints: bool,
+    rustfmt: bool,
+}
+
+/// Format of the device libraries produced by [`Builder::build_device_lib`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceLibFormat {
+    /// LTO-IR wrapped in a fatbin (`code=lto_XX`), to be linked by nvJitLink
+    /// with link time optimization enabled.
+    LtoIr,
+    /// Relocatable cubin (`-rdc=true`) for the current compute cap, to be linked
+    /// by nvJitLink or the driver linker (`cuLinkAddData`).
+    Cubin,
+}
+
+impl DeviceLibFormat {
+    fn nvcc_args(&self, compute_caps: &[usize]) -> Vec<String> {
+        match self {
+            DeviceLibFormat::LtoIr => compute_caps
+                .iter()
+                .map(|c| format!("--generate-code=arch=compute_{c},code=lto_{c}"))
+                .chain(["--fatbin".to_string()])
+                .collect(),
+            // A cubin only holds a single architecture
+            DeviceLibFormat::Cubin => vec![
+                format!("--gpu-architecture=sm_{}", compute_caps[0]),
+                "--cubin".to_string(),
+            ],
+        }
+    }
+}
+
+/// Flavor of the kernels being built, see [`Builder::fat_compute_caps`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KernelProfile {
+    /// Only targets the local GPU, fast to compile.
+    Thin,
+    /// Targets every compute cap of [`Builder::fat_compute_caps`].
+    Fat,
+}
+
+impl KernelProfile {
+    fn name(&self) -> &'static str {
+        match self {
+            KernelProfile::Thin => "thin",
+            KernelProfile::Fat => "fat",
+        }
+    }
+}
+
+/// How PTX is compressed before being embedded, see [`Bindings::compress`].
+/// Each variant requires the feature of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Compression {
+    /// gzip, decompressed with the `flate2` crate.
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// zstd, decompressed with the `zstd` crate. Smaller and faster to decompress.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    fn extension(&self) -> &'static str {
+        match *self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => "gz",
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => "zst",
+        }
+    }
+
+    // Without any compression feature, the enum is empty and `bytes` unused.
+    #[allow(unused_variables)]
+    fn compress(&self, bytes: &[u8]) -> Vec<u8> {
+        match *self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                use std::io::Write;
+                // The gzip header mtime is left to 0, keeping the output reproducible.
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(bytes).expect("gzip ptx");
+                encoder.finish().expect("gzip ptx")
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::encode_all(bytes, 19).expect("zstd ptx"),
+        }
+    }
+
+    /// Body of the generated `decompress` function, turning `bytes` back into PTX.
+    fn decompressor(&self) -> &'static str {
+        match *self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                "    let mut ptx = String::new();\n    \
+                 std::io::Read::read_to_string(&mut ::flate2::read::GzDecoder::new(bytes), &mut ptx)\n        \
+                 .expect(\"decompress ptx\");\n    \
+                 ptx\n"
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                "    let ptx = ::zstd::decode_all(bytes).expect(\"decompress ptx\");\n    \
+                 String::from_utf8(ptx).expect(\"ptx to be utf-8\")\n"
+            }
+        }
+    }
+}
+
+/// Language of a source file, deduced from its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Language {
+    Cuda,
+    Cpp,
+}
+
+impl Language {
+    fn of(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("cpp" | "cc" | "cxx" | "c++") => Language::Cpp,
+            _ => Language::Cuda,
+        }
+    }
+}
+
+/// What kind of artifact each kernel is compiled to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Ptx,
+    DeviceLib(DeviceLibFormat),
+    OptixIr,
+    /// Sources compiled at runtime with NVRTC, see [`Builder::build_nvrtc`].
+    Nvrtc,
+}
+
+impl Output {
+    fn extension(&self) -> &'static str {
+        match self {
+            Output::Ptx => "ptx",
+            Output::DeviceLib(DeviceLibFormat::LtoIr) => "fatbin",
+            Output::DeviceLib(DeviceLibFormat::Cubin) => "cubin",
+            Output::OptixIr => "optixir",
+            Output::Nvrtc => "nvrtc.cu",
+        }
+    }
+}
+
+/// Options controlling how glob patterns are expanded into kernel and include paths.
+/// ```no_run
+/// use bindgen_cuda::{GlobOptions, SymlinkPolicy};
+/// let options = GlobOptions::default()
+///     .case_sensitive(false)
+///     .symlinks(SymlinkPolicy::Skip);
+/// let builder = bindgen_cuda::Builder::default()
+///     .glob_options(options)
+///     .kernel_paths_glob("src/{attn,quant}/**/*.cu");
+/// ```
+#[derive(Debug, Clone)]
+pub struct GlobOptions {
+    case_sensitive: bool,
+    symlinks: SymlinkPolicy,
+}
+
+impl Default for GlobOptions {
+    fn default() -> Self {
+        // Windows filesystems are case insensitive, patterns should behave the same way
+        // otherwise `*.CU` files are silently missed.
+        Self {
+            case_sensitive: !cfg!(windows),
+            symlinks: SymlinkPolicy::default(),
+        }
+    }
+}
+
+impl GlobOptions {
+    /// Whether patterns match case sensitively.
+    /// Defaults to `false` on Windows and `true` everywhere else.
+    pub fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+
+    /// What to do with symlinks matched by a pattern.
+    pub fn symlinks(mut self, symlinks: SymlinkPolicy) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    fn match_options(&self) -> glob::MatchOptions {
+        glob::MatchOptions {
+            case_sensitive: self.case_sensitive,
+            ..Default::default()
+        }
+    }
+}
+
+/// What to do with symlinks matched by a glob pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Keep symlinks, they are resolved by nvcc like any other file.
+    /// Relative includes are looked up next to the symlink.
+    #[default]
+    Follow,
+    /// Replace symlinks by the file they point to, relative includes are
+    /// looked up next to the target (e.g. inside the Nix store).
+    Resolve,
+    /// Ignore symlinks entirely.
+    Skip,
+}
+
+/// How include headers are staged into the output directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StagingMode {
+    /// Copy the headers.
+    #[default]
+    Copy,
+    /// Hardlink the headers, falling back to a copy when the output directory
+    /// lives on another filesystem.
+    Hardlink,
+    /// Symlink the headers to their original location.
+    Symlink,
+}
+
+impl StagingMode {
+    fn stage(&self, source: &Path, destination: &Path) -> std::io::Result<()> {
+        // A link staged by a previous build would be written through, truncating the
+        // header itself.
+        if destination.symlink_metadata().is_ok() {
+            std::fs::remove_file(destination)?;
+        }
+        match self {
+            StagingMode::Copy => std::fs::copy(source, destination).map(|_| ()),
+            StagingMode::Hardlink => std::fs::hard_link(source, destination)
+                .or_else(|_| std::fs::copy(source, destination).map(|_| ())),
+            StagingMode::Symlink => {
+                let source = source.canonicalize()?;
+                #[cfg(unix)]
+                return std::os::unix::fs::symlink(source, destination);
+                #[cfg(windows)]
+                return std::os::windows::fs::symlink_file(source, destination);
+                #[cfg(not(any(unix, windows)))]
+                return std::fs::copy(source, destination).map(|_| ());
+            }
+        }
+    }
+}
+
+/// Zeroes the timestamp, owner and group of every member of the `ar` archive at
+/// `path`, and gives them the same mode, since archivers record the ones of the
+/// object files they were given.
+fn normalize_archive(path: &Path) -> std::io::Result<()> {
+    const MAGIC: &[u8] = b"!<arch>\n";
+    const HEADER: usize = 60;
+    let mut archive = std::fs::read(path)?;
+    if !archive.starts_with(MAGIC) {
+        return Ok(());
+    }
+    let mut offset = MAGIC.len();
+    while offset + HEADER <= archive.len() {
+        let header = &mut archive[offset..offset + HEADER];
+        let size: usize = std::str::from_utf8(&header[48..58])
+            .ok()
+            .and_then(|size| size.trim().parse().ok())
+            .ok_or_else(|| std::io::Error::other("invalid archive member size"))?;
+        // mtime (12), uid (6), gid (6) and mode (8) fields, space padded
+        header[16..48].copy_from_slice(b"0           0     0     644     ");
+        // Members are aligned on 2 bytes
+        offset += HEADER + size + size % 2;
+    }
+    std::fs::write(path, archive)
 }
 
-fn default_kernels() -> Option<Vec<PathBuf>> {
-    Some(
-        glob::glob("src/**/*.cu")
-            .ok()?
-            .map(|p| p.expect("Invalid path"))
-            .collect(),
-    )
+/// Collects every file in `dir`, recursively, in a stable order.
+fn files_in(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            files_in(&entry, files);
+        } else {
+            files.push(entry);
+        }
+    }
+}
+
+/// Tells cargo to rerun the build script when `path` changes. For symlinks, the target
+/// is watched as well since that is where the content actually changes.
+fn rerun_if_changed(path: &Path) {
+    println!("cargo:rerun-if-changed={}", path.display());
+    let is_symlink = path
+        .symlink_metadata()
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false);
+    if is_symlink {
+        if let Ok(target) = path.canonicalize() {
+            println!("cargo:rerun-if-changed={}", target.display());
+        }
+    }
+}
+
+/// Expands the brace sets of a pattern `src/{attn,quant}/*.cu` into
+/// `["src/attn/*.cu", "src/quant/*.cu"]`. Nested sets are supported.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let Some(open) = pattern.find('{') else {
+        return vec![pattern.to_string()];
+    };
+    let mut depth = 0;
+    let mut close = None;
+    let mut splits = vec![];
+    for (i, c) in pattern[open..].char_indices() {
+        let i = open + i;
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(i);
+                    break;
+                }
+            }
+            ',' if depth == 1 => splits.push(i),
+            _ => {}
+        }
+    }
+    let Some(close) = close else {
+        // Unbalanced braces are left for the glob itself to match (or reject)
+        return vec![pattern.to_string()];
+    };
+    let prefix = &pattern[..open];
+    let suffix = &pattern[close + 1..];
+    let mut bounds = vec![open];
+    bounds.extend(splits);
+    bounds.push(close);
+    bounds
+        .windows(2)
+        .flat_map(|w| {
+            let alternative = &pattern[w[0] + 1..w[1]];
+            expand_braces(&format!("{prefix}{alternative}{suffix}"))
+        })
+        .collect()
+}
+
+/// Expands a glob pattern (with brace sets) according to the options.
+fn glob_paths(pattern: &str, options: &GlobOptions) -> Result<Vec<PathBuf>, glob::PatternError> {
+    let mut paths = vec![];
+    for pattern in expand_braces(pattern) {
+        for path in glob::glob_with(&pattern, options.match_options())? {
+            let path = path.expect("Invalid path");
+            let is_symlink = path
+                .symlink_metadata()
+                .map(|m| m.file_type().is_symlink())
+                .unwrap_or(false);
+            match options.symlinks {
+                SymlinkPolicy::Skip if is_symlink => continue,
+                SymlinkPolicy::Resolve if is_symlink => {
+                    paths.push(path.canonicalize().expect("symlink target to exist"))
+                }
+                _ => paths.push(path),
+            }
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+fn default_kernels(options: &GlobOptions) -> Option<Vec<PathBuf>> {
+    glob_paths("src/**/*.cu", options).ok()
 }
-fn default_include() -> Option<Vec<PathBuf>> {
-    Some(
-        glob::glob("src/**/*.cuh")
-            .ok()?
-            .map(|p| p.expect("Invalid path"))
-            .collect(),
-    )
+
+fn default_include(options: &GlobOptions) -> Option<Vec<PathBuf>> {
+    glob_paths("src/**/*.cuh", options).ok()
 }
 
 impl Builder {
+    /// Loads the configuration from a `kernels.toml`, so that the kernel build can be
+    /// reviewed as data. Paths are relative to the crate, like everywhere else, and
+    /// builder methods called afterwards override the file. Requires the `toml` feature.
+    /// ```toml
+    /// kernels = ["src/**/*.cu"]
+    /// exclude = ["src/experimental/**"]
+    /// include = ["src/**/*.cuh", "third_party/cutlass/include"]
+    /// args = ["--use_fast_math"]
+    /// defines = { BLOCK_SIZE = 256, USE_TMA = true }
+    /// arch = [80, 89, 90] # or "native", "all-major", see `Builder::compute_cap_spec`
+    ///
+    /// [[kernel]]
+    /// path = "src/gemm.cu"
+    /// name = "gemm_sm90"
+    /// defines = { USE_WGMMA = true }
+    /// entry_points = ["gemm_f16"]
+    ///
+    /// [output]
+    /// mode = "ptx" # or "lib" and "shared", with a `name`
+    /// bindings = "src/kernels.rs"
+    /// ```
+    /// ```no_run
+    /// bindgen_cuda::Builder::default()
+    ///     .config_file("kernels.toml")
+    ///     .arg("-lineinfo")
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    /// What the file sets shows up in the planned commands:
+    /// ```
+    /// use bindgen_cuda::{fixtures, Builder};
+    /// let dir = std::env::temp_dir().join("bindgen_cuda_config");
+    /// std::fs::create_dir_all(dir.join("out")).unwrap();
+    /// std::fs::write(dir.join("gemm.cu"), "__global__ void gemm() {}\n").unwrap();
+    /// let config = format!(
+    ///     r#"
+    ///     kernels = ['{}/*.cu']
+    ///     args = ["--use_fast_math"]
+    ///     defines = {{ BLOCK_SIZE = 256, SCALE = 1.0, USE_TMA = true }}
+    ///     arch = [8.0, "sm_89"]
+    ///     "#,
+    ///     dir.display()
+    /// );
+    /// std::fs::write(dir.join("kernels.toml"), config).unwrap();
+    /// # std::env::set_var("OUT_DIR", dir.join("out"));
+    /// let plan = Builder::default()
+    ///     .probe(fixtures::healthy())
+    ///     .config_file(dir.join("kernels.toml"))
+    ///     .plan_ptx();
+    /// let command = &plan.commands()[0];
+    /// // PTX targets the oldest compute cap, `8.0` here.
+    /// assert!(command.contains("--gpu-architecture=compute_80"));
+    /// assert!(command.contains(r#""--use_fast_math" "-DBLOCK_SIZE=256" "-DSCALE=1.0" "-DUSE_TMA""#));
+    /// ```
+    /// Unknown keys panic, rather than being silently ignored:
+    /// ```should_panic
+    /// let dir = std::env::temp_dir().join("bindgen_cuda_config_unknown");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// # std::env::set_var("OUT_DIR", &dir);
+    /// std::fs::write(dir.join("kernels.toml"), "[[kernel]]\npath = \"a.cu\"\nargz = []\n").unwrap();
+    /// // panics with: unknown key `kernel.argz`, expected one of ["path", "name", ...]
+    /// bindgen_cuda::Builder::default().config_file(dir.join("kernels.toml"));
+    /// ```
+    /// And so do values of the wrong type:
+    /// ```should_panic
+    /// let dir = std::env::temp_dir().join("bindgen_cuda_config_type");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// # std::env::set_var("OUT_DIR", &dir);
+    /// std::fs::write(dir.join("kernels.toml"), "args = [1, 2]\n").unwrap();
+    /// // panics with: `args` must be a list of strings
+    /// bindgen_cuda::Builder::default().config_file(dir.join("kernels.toml"));
+    /// ```
+    #[cfg(feature = "toml")]
+    pub fn config_file<P: AsRef<Path>>(self, path: P) -> Self {
+        config::apply(self, path.as_ref())
+    }
+
     /// Setup the kernel paths. All path must be set at once and be valid files.
     /// ```no_run
     /// let builder = bindgen_cuda::Builder::default().kernel_paths(vec!["src/mykernel.cu"]);
@@ -93,6 +1011,7 @@ impl Builder {
             panic!("Kernels paths do not exist {inexistent_paths:?}");
         }
         self.kernel_paths = paths;
+        self.add_configured_kernels();
         self
     }
 
@@ -115,9 +1034,12 @@ impl Builder {
         self
     }
 
-    /// Setup the kernel paths. All path must be set at once and be valid files.
+    /// Setup the include paths. All path must be set at once.
+    /// Header files are staged into the output directory and their directory is
+    /// added with `-I`. Directories are passed with `-I` as is, and every file they
+    /// contain is tracked, so headers can include each other by relative path.
     /// ```no_run
-    /// let builder = bindgen_cuda::Builder::default().include_paths(vec!["src/mykernel.cuh"]);
+    /// let builder = bindgen_cuda::Builder::default().include_paths(vec!["src/mykernel.cuh", "third_party/cutlass/include"]);
     /// ```
     pub fn include_paths<P: Into<PathBuf>>(mut self, paths: Vec<P>) -> Self {
         self.include_paths = paths.into_iter().map(|p| p.into()).collect();
@@ -129,10 +1051,8 @@ impl Builder {
     /// let builder = bindgen_cuda::Builder::default().kernel_paths_glob("src/**/*.cu");
     /// ```
     pub fn kernel_paths_glob(mut self, glob: &str) -> Self {
-        self.kernel_paths = glob::glob(glob)
-            .expect("Invalid blob")
-            .map(|p| p.expect("Invalid path"))
-            .collect();
+        self.kernel_paths = glob_paths(glob, &self.glob_options).expect("Invalid blob");
+        self.add_configured_kernels();
         self
     }
 
@@ -141,10 +1061,19 @@ impl Builder {
     /// let builder = bindgen_cuda::Builder::default().kernel_paths_glob("src/**/*.cuh");
     /// ```
     pub fn include_paths_glob(mut self, glob: &str) -> Self {
-        self.include_paths = glob::glob(glob)
-            .expect("Invalid blob")
-            .map(|p| p.expect("Invalid path"))
-            .collect();
+        self.include_paths = glob_paths(glob, &self.glob_options).expect("Invalid blob");
+        self
+    }
+
+    /// Sets how subsequent `*_glob` patterns are matched (case sensitivity, symlinks).
+    /// Patterns also support brace sets like `src/{attn,quant}/**/*.cu`.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default()
+    ///     .glob_options(bindgen_cuda::GlobOptions::default().case_sensitive(false))
+    ///     .kernel_paths_glob("src/**/*.cu");
+    /// ```
+    pub fn glob_options(mut self, options: GlobOptions) -> Self {
+        self.glob_options = options;
         self
     }
 
@@ -161,279 +1090,6065 @@ impl Builder {
 
     /// Sets up extra nvcc compile arguments.
     /// ```no_run
-    /// let builder = bindgen_cuda::Builder::default().arg("--expt-relaxed-constexpr");
+    /// let block_size = 256;
+    /// let builder = bindgen_cuda::Builder::default()
+    ///     .arg("--expt-relaxed-constexpr")
+    ///     .arg(format!("-DBLOCK={block_size}"));
     /// ```
-    pub fn arg(mut self, arg: &'static str) -> Self {
-        self.extra_args.push(arg);
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.extra_args.push(arg.into());
         self
     }
 
-    /// Forces the cuda root to a specific directory.
-    /// By default all standard directories will be visited.
+    /// Sets up several extra nvcc compile arguments at once.
     /// ```no_run
-    /// let builder = bindgen_cuda::Builder::default().cuda_root("/usr/local/cuda");
+    /// let builder = bindgen_cuda::Builder::default().args(["-O3", "--use_fast_math"]);
     /// ```
-    pub fn cuda_root<P>(&mut self, path: P)
+    /// Arguments can also be given in `CUDA_NVCC_FLAGS`, as for CMake: separated by
+    /// spaces or `;`, and quoted when they contain any.
+    /// ```
+    /// use bindgen_cuda::{fixtures, Builder};
+    /// let dir = std::env::temp_dir().join("bindgen_cuda_nvcc_flags");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// std::fs::write(dir.join("gemm.cu"), "").unwrap();
+    /// # std::env::set_var("OUT_DIR", &dir);
+    /// let flags = r#"-O3;--use_fast_math -Xcompiler "-Wall,-Wextra" '-DNAME=a b'"#;
+    /// let plan = Builder::default()
+    ///     .probe(fixtures::healthy().var("CUDA_NVCC_FLAGS", flags))
+    ///     .kernel_paths(vec![dir.join("gemm.cu")])
+    ///     .plan_ptx();
+    /// let expected = r#""-O3" "--use_fast_math" "-Xcompiler" "-Wall,-Wextra" "-DNAME=a b""#;
+    /// assert!(plan.commands()[0].contains(expected));
+    /// ```
+    pub fn args<I, S>(mut self, args: I) -> Self
     where
-        P: Into<PathBuf>,
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
     {
-        self.cuda_root = Some(path.into());
+        self.extra_args.extend(args.into_iter().map(Into::into));
+        self
     }
 
-    /// Consumes the builder and create a lib in the out_dir.
-    /// It then needs to be linked against in your `build.rs`
+    /// Follows the cargo profile being built: `OPT_LEVEL` is mapped to `-O0` through
+    /// `-O3`, and `DEBUG` to `-lineinfo` so that profilers and compute-sanitizer can map
+    /// device code back to the sources. Off by default. Flags set explicitly, with
+    /// [`Builder::args`], [`Builder::arg_if_version`], [`KernelConfig::arg`] or
+    /// `CUDA_NVCC_FLAGS`, take precedence.
     /// ```no_run
-    /// let builder = bindgen_cuda::Builder::default().build_lib("libflash.a");
-    /// println!("cargo:rustc-link-lib=flash");
+    /// // Always optimized, even in debug builds, but still with line info.
+    /// let builder = bindgen_cuda::Builder::default().cargo_profile(true).arg("-O3");
     /// ```
-    pub fn build_lib<P>(self, out_file: P)
-    where
-        P: Into<PathBuf>,
-    {
-        let out_file = out_file.into();
-        let compute_cap = self.compute_cap.expect("Failed to get compute_cap");
-        let out_dir = self.out_dir;
-        for path in &self.watch {
-            println!("cargo:rerun-if-changed={}", path.display());
+    /// Per kernel flags take precedence too:
+    /// ```
+    /// use bindgen_cuda::{fixtures, Builder, KernelConfig};
+    /// let dir = std::env::temp_dir().join("bindgen_cuda_cargo_profile");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// std::fs::write(dir.join("fast.cu"), "").unwrap();
+    /// std::fs::write(dir.join("slow.cu"), "").unwrap();
+    /// # std::env::set_var("OUT_DIR", &dir);
+    /// let plan = Builder::default()
+    ///     .probe(fixtures::healthy().var("OPT_LEVEL", "0").var("DEBUG", "false"))
+    ///     .cargo_profile(true)
+    ///     .kernel_paths(vec![dir.join("fast.cu"), dir.join("slow.cu")])
+    ///     .kernel(KernelConfig::new(dir.join("fast.cu")).arg("-O3"))
+    ///     .plan_ptx();
+    /// let [fast, slow] = plan.commands() else { unreachable!() };
+    /// assert!(fast.contains(r#""-O3""#) && !fast.contains(r#""-O0""#));
+    /// assert!(slow.contains(r#""-O0""#));
+    /// ```
+    pub fn cargo_profile(mut self, cargo_profile: bool) -> Self {
+        self.cargo_profile = cargo_profile;
+        self
+    }
+
+    /// The flags of [`Builder::cargo_profile`], for a compilation of `instance` if any.
+    fn cargo_profile_args(&self, instance: Option<Instance>) -> Vec<&'static str> {
+        if !self.cargo_profile {
+            return vec![];
         }
-        let cu_files: Vec<_> = self
-            .kernel_paths
+        let recorder = self.recorder();
+        let explicit: Vec<String> = self
+            .extra_args
             .iter()
-            .map(|f| {
-                let mut s = DefaultHasher::new();
-                f.display().to_string().hash(&mut s);
-                let hash = s.finish();
-                let mut obj_file = out_dir.join(format!(
-                    "{}-{:x}",
-                    f.file_stem()
-                        .expect("kernels paths should include a filename")
-                        .to_string_lossy(),
-                    hash
-                ));
-                obj_file.set_extension("o");
-                (f, obj_file)
-            })
+            .cloned()
+            .chain(
+                recorder
+                    .var("CUDA_NVCC_FLAGS")
+                    .iter()
+                    .flat_map(|flags| split_flags(flags))
+                    .collect::<Vec<_>>(),
+            )
+            .chain(self.versioned_args().into_iter().cloned())
+            .chain(instance.iter().flat_map(Instance::args))
             .collect();
-        let out_modified: Result<_, _> = out_file.metadata().and_then(|m| m.modified());
-        let should_compile = if let Ok(out_modified) = out_modified {
-            let kernel_modified = self.kernel_paths.iter().any(|entry| {
-                let in_modified = entry
-                    .metadata()
-                    .expect("kernel {entry} should exist")
-                    .modified()
-                    .expect("kernel modified to be accessible");
-                in_modified.duration_since(out_modified).is_ok()
-            });
-            let watch_modified = self.watch.iter().any(|entry| {
-                let in_modified = entry
-                    .metadata()
-                    .expect("watched file {entry} should exist")
-                    .modified()
-                    .expect("watch modified should be accessible");
-                in_modified.duration_since(out_modified).is_ok()
-            });
-            kernel_modified || watch_modified
-        } else {
-            true
+        let mut args = vec![];
+        let release = recorder.var("PROFILE").as_deref() == Some("release");
+        let optimization = match recorder.var("OPT_LEVEL").as_deref() {
+            Some("0") => Some("-O0"),
+            Some("1") => Some("-O1"),
+            Some("2" | "s" | "z") => Some("-O2"),
+            Some("3") => Some("-O3"),
+            Some(_) => None,
+            None if release => Some("-O3"),
+            None => recorder.var("PROFILE").map(|_| "-O0"),
         };
-        let ccbin_env = std::env::var("NVCC_CCBIN");
-        if should_compile {
-            cu_files
-            .par_iter()
-            .map(|(cu_file, obj_file)| {
-                let mut command = std::process::Command::new("nvcc");
-                command
-                    .arg(format!("--gpu-architecture=sm_{compute_cap}"))
-                    .arg("-c")
-                    .args(["-o", obj_file.to_str().expect("valid outfile")])
-                    .args(["--default-stream", "per-thread"])
-                    .args(&self.extra_args);
-                if let Ok(ccbin_path) = &ccbin_env {
-                    command
-                        .arg("-allow-unsupported-compiler")
-                        .args(["-ccbin", ccbin_path]);
-                }
-                command.arg(cu_file);
-                let output = command
-                    .spawn()
-                    .expect("failed spawning nvcc")
-                    .wait_with_output().expect("capture nvcc output");
-                if !output.status.success() {
-                    panic!(
-                        "nvcc error while executing compiling: {:?}\n\n# stdout\n{:#}\n\n# stderr\n{:#}",
-                        &command,
-                        String::from_utf8_lossy(&output.stdout),
-                        String::from_utf8_lossy(&output.stderr)
-                    )
-                }
-                Ok(())
-            })
-            .collect::<Result<(), std::io::Error>>().expect("compile files correctly");
-            let obj_files = cu_files.iter().map(|c| c.1.clone()).collect::<Vec<_>>();
-            let mut command = std::process::Command::new("nvcc");
-            command
-                .arg("--lib")
-                .args([
-                    "-o",
-                    out_file.to_str().expect("library file {out_file} to exist"),
-                ])
-                .args(obj_files);
-            let output = command
-                .spawn()
-                .expect("failed spawning nvcc")
-                .wait_with_output()
-                .expect("Run nvcc");
-            if !output.status.success() {
-                panic!(
-                    "nvcc error while linking: {:?}\n\n# stdout\n{:#}\n\n# stderr\n{:#}",
-                    &command,
-                    String::from_utf8_lossy(&output.stdout),
-                    String::from_utf8_lossy(&output.stderr)
-                )
-            }
+        let optimized = explicit
+            .iter()
+            .any(|a| a.starts_with("-O") || a.starts_with("--optimize"));
+        if let (Some(optimization), false) = (optimization, optimized) {
+            args.push(optimization);
+        }
+        let debug = match recorder.var("DEBUG").as_deref() {
+            Some(debug) => !matches!(debug, "false" | "0" | "none"),
+            None => recorder.var("PROFILE").as_deref() == Some("debug"),
+        };
+        let line_info = ["-lineinfo", "--generate-line-info", "-G", "--device-debug"];
+        if debug && !explicit.iter().any(|a| line_info.contains(&a.as_str())) {
+            args.push("-lineinfo");
         }
+        args
     }
 
-    /// Consumes the builder and outputs 1 ptx file for each kernels
-    /// found.
-    /// This function returns [`Bindings`] which can then be unused
-    /// to create a rust source file that will include those kernels.
+    /// Passes `flag` to the host compiler (`-Xcompiler`), quoted so that nvcc does not
+    /// split it on commas like it does for lists of flags.
     /// ```no_run
-    /// let bindings = bindgen_cuda::Builder::default().build_ptx().unwrap();
-    /// bindings.write("src/lib.rs").unwrap();
+    /// let builder = bindgen_cuda::Builder::default()
+    ///     .host_flag("-fPIC")
+    ///     .host_flag("-Wl,-z,defs");
     /// ```
-    pub fn build_ptx(self) -> Result<Bindings, Error> {
-        let cuda_root = self.cuda_root.expect("Could not find CUDA in standard locations, set it manually using Builder().set_cuda_root(...)");
-        let compute_cap = self.compute_cap.expect("Could not find compute_cap");
-        let cuda_include_dir = cuda_root.join("include");
-        println!(
-            "cargo:rustc-env=CUDA_INCLUDE_DIR={}",
-            cuda_include_dir.display()
-        );
-        let out_dir = self.out_dir;
+    pub fn host_flag<S: AsRef<str>>(mut self, flag: S) -> Self {
+        self.extra_args
+            .push(forward_flag("-Xcompiler", flag.as_ref()));
+        self
+    }
 
-        let mut include_paths = self.include_paths;
-        for path in &mut include_paths {
-            println!("cargo:rerun-if-changed={}", path.display());
-            let destination =
-                out_dir.join(path.file_name().expect("include path to have filename"));
-            std::fs::copy(path.clone(), destination).expect("copy include headers");
-            // remove the filename from the path so it's just the directory
-            path.pop();
-        }
+    /// Passes `flag` to `ptxas` (`-Xptxas`), quoted like [`Builder::host_flag`].
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().ptxas_flag("-v");
+    /// ```
+    pub fn ptxas_flag<S: AsRef<str>>(mut self, flag: S) -> Self {
+        self.extra_args.push(forward_flag("-Xptxas", flag.as_ref()));
+        self
+    }
 
-        include_paths.sort();
-        include_paths.dedup();
+    /// Selects the C runtime host code links against when targeting MSVC, which
+    /// must match the rest of the binary: Rust uses [`MsvcRuntime::Dynamic`] unless
+    /// built with `-C target-feature=+crt-static`. Ignored for other targets.
+    /// ```no_run
+    /// use bindgen_cuda::{Builder, MsvcRuntime};
+    /// let builder = Builder::default().msvc_runtime(MsvcRuntime::Static);
+    /// ```
+    pub fn msvc_runtime(mut self, runtime: MsvcRuntime) -> Self {
+        self.msvc_runtime = Some(runtime);
+        self
+    }
 
-        #[allow(unused)]
-        let mut include_options: Vec<String> = include_paths
-            .into_iter()
-            .map(|s| {
-                "-I".to_string()
-                    + &s.into_os_string()
-                        .into_string()
-                        .expect("include option to be valid string")
-            })
-            .collect::<Vec<_>>();
-        include_options.push(format!("-I{}", cuda_include_dir.display()));
+    /// Sets up an extra nvcc argument only used when the CUDA toolkit version matches
+    /// `requirement`: a version (`12`, `12.4`) prefixed by one of `>=`, `>`, `<=`, `<` or `=`.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default()
+    ///     .arg_if_version(">=12.0", "--expt-relaxed-constexpr")
+    ///     .arg_if_version("<12", "-DCUDA_11");
+    /// ```
+    pub fn arg_if_version<S: Into<String>>(mut self, requirement: &str, arg: S) -> Self {
+        let requirement = VersionReq::parse(requirement)
+            .unwrap_or_else(|| panic!("invalid CUDA version requirement {requirement:?}"));
+        self.versioned_args.push((requirement, arg.into()));
+        self
+    }
 
-        let ccbin_env = std::env::var("NVCC_CCBIN");
-        println!("cargo:rerun-if-env-changed=NVCC_CCBIN");
-        for path in &self.watch {
-            println!("cargo:rerun-if-changed={}", path.display());
-        }
-        let children = self.kernel_paths
-            .par_iter()
-            .flat_map(|p| {
-                println!("cargo:rerun-if-changed={}", p.display());
-                let mut output = p.clone();
-                output.set_extension("ptx");
-                let output_filename = std::path::Path::new(&out_dir).to_path_buf().join("out").with_file_name(output.file_name().expect("kernel to have a filename"));
-
-                let ignore = if let Ok(metadata) = output_filename.metadata() {
-                    let out_modified = metadata.modified().expect("modified to be accessible");
-                    let in_modified = p.metadata().expect("input to have metadata").modified().expect("input metadata to be accessible");
-                    out_modified.duration_since(in_modified).is_ok()
+    /// Fails the build up front, with [`Error::CudaRequirement`], when the CUDA toolkit
+    /// version doesn't match `requirement`, written like for [`Builder::arg_if_version`].
+    /// Can be called several times to require a range.
+    /// ```
+    /// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+    /// use bindgen_cuda::{fixtures, Builder, Error};
+    /// let builder = Builder::default()
+    ///     .probe(fixtures::smi_newer_than_nvcc())
+    ///     .require_cuda(">=11.8");
+    /// let err = builder.build_ptx().err().unwrap();
+    /// assert!(matches!(err, Error::CudaRequirement { .. }));
+    /// assert!(err.to_string().starts_with("CUDA >=11.8 is required, found CUDA 11.7."));
+    /// ```
+    pub fn require_cuda(mut self, requirement: &str) -> Self {
+        let parsed = VersionReq::parse(requirement)
+            .unwrap_or_else(|| panic!("invalid CUDA version requirement {requirement:?}"));
+        self.cuda_requirements
+            .push((parsed, requirement.trim().to_string()));
+        self
+    }
+
+    /// Fails the build up front, with [`Error::DriverRequirement`], when the driver
+    /// version `nvidia-smi` reports doesn't match `requirement`, like `>=520`. Builds
+    /// without a driver, in CI containers for instance, only get a warning.
+    /// ```
+    /// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+    /// use bindgen_cuda::{fixtures, Builder, Error};
+    /// let builder = Builder::default()
+    ///     .probe(fixtures::nvcc_newer_than_driver())
+    ///     .require_driver(">=550");
+    /// let err = builder.build_ptx().err().unwrap();
+    /// assert!(matches!(err, Error::DriverRequirement { .. }));
+    /// ```
+    pub fn require_driver(mut self, requirement: &str) -> Self {
+        let parsed = VersionReq::parse(requirement)
+            .unwrap_or_else(|| panic!("invalid driver version requirement {requirement:?}"));
+        self.driver_requirements
+            .push((parsed, requirement.trim().to_string()));
+        self
+    }
+
+    /// Checks [`Builder::require_cuda`], [`Builder::require_driver`] and the CUDA
+    /// version [`Builder::cpp_std`] needs.
+    fn check_requirements(&self) -> Result<(), Error> {
+        let cpp_std = self
+            .cpp_std
+            .and_then(|std| Some((std.name(), std.min_cuda_version()?)));
+        if self.split_compile.is_some() {
+            let version = self.cuda_version()?;
+            if version < (12, 0) {
+                println!(
+                    "cargo:warning=`split_compile` needs CUDA 12.0 or newer, found {}.{}, kernels are compiled without splitting them",
+                    version.0, version.1
+                );
+            }
+        }
+        if let Some((std, required)) = cpp_std {
+            let version = self.cuda_version()?;
+            if version < required {
+                return Err(Error::CudaRequirement {
+                    required: format!(">={}.{} (for {std})", required.0, required.1),
+                    found: format!("{}.{}", version.0, version.1),
+                });
+            }
+        }
+        if !self.cuda_requirements.is_empty() {
+            let version = self.cuda_version()?;
+            if let Some((_, required)) = self
+                .cuda_requirements
+                .iter()
+                .find(|(requirement, _)| !requirement.matches(version))
+            {
+                return Err(Error::CudaRequirement {
+                    required: required.clone(),
+                    found: format!("{}.{}", version.0, version.1),
+                });
+            }
+        }
+        if !self.driver_requirements.is_empty() {
+            let Some(driver) = driver_version(&self.recorder()) else {
+                println!("cargo:warning=could not detect the NVIDIA driver version, skipping `require_driver`");
+                return Ok(());
+            };
+            let mut parts = driver.split('.').map(|p| p.parse::<usize>());
+            let version = match (parts.next(), parts.next()) {
+                (Some(Ok(major)), Some(Ok(minor))) => (major, minor),
+                (Some(Ok(major)), None) => (major, 0),
+                _ => {
+                    return Err(Error::NvidiaSmi(format!(
+                        "could not parse driver version {driver:?}"
+                    )))
+                }
+            };
+            if let Some((_, required)) = self
+                .driver_requirements
+                .iter()
+                .find(|(requirement, _)| !requirement.matches(version))
+            {
+                return Err(Error::DriverRequirement {
+                    required: required.clone(),
+                    found: driver,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets up extra nvcc arguments only used when compiling C++ host sources
+    /// (`.cpp`, `.cc`, `.cxx`), which [`Builder::build_lib`] archives alongside the kernels.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default()
+    ///     .kernel_paths_glob("src/**/*.{cu,cpp}")
+    ///     .cpp_arg("-Xcompiler=-Wall");
+    /// ```
+    pub fn cpp_arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.cpp_args.push(arg.into());
+        self
+    }
+
+    /// Sets the C++ standard (`--std`) of kernels and host sources. Building fails if
+    /// the CUDA toolkit is too old for it (C++17 needs CUDA 11, C++20 CUDA 12).
+    /// ```no_run
+    /// use bindgen_cuda::CppStd;
+    /// let builder = bindgen_cuda::Builder::default().cpp_std(CppStd::Cpp17);
+    /// ```
+    /// The toolkit is checked up front, with [`Error::CudaRequirement`]:
+    /// ```
+    /// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+    /// use bindgen_cuda::{fixtures, Builder, CppStd};
+    /// let builder = Builder::default()
+    ///     .probe(fixtures::smi_newer_than_nvcc())
+    ///     .cpp_std(CppStd::Cpp20);
+    /// let err = builder.build_ptx().err().unwrap();
+    /// assert!(err.to_string().starts_with("CUDA >=12.0 (for c++20) is required, found CUDA 11.7."));
+    /// ```
+    pub fn cpp_std(mut self, std: CppStd) -> Self {
+        self.cpp_std = Some(std);
+        self
+    }
+
+    /// Allows calling `constexpr` host functions from device code (`--expt-relaxed-constexpr`),
+    /// needed by most kernels using cub or thrust.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().relaxed_constexpr(true);
+    /// ```
+    pub fn relaxed_constexpr(mut self, relaxed_constexpr: bool) -> Self {
+        self.relaxed_constexpr = relaxed_constexpr;
+        self
+    }
+
+    /// Allows `__device__` lambdas (`--expt-extended-lambda`, spelled `--extended-lambda`
+    /// by recent toolkits which accept both), needed by thrust algorithms taking lambdas.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().extended_lambda(true);
+    /// ```
+    pub fn extended_lambda(mut self, extended_lambda: bool) -> Self {
+        self.extended_lambda = extended_lambda;
+        self
+    }
+
+    /// Forces passing nvcc arguments through a response file (`--options-file`) or
+    /// forbids it.
+    /// By default a response file is only used when the command line would exceed
+    /// the platform limit, which happens on Windows with many include directories.
+    /// Commands run through a [`Builder::compiler_wrapper`] never use one, as compiler
+    /// caches don't look into response files.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().response_file(true);
+    /// ```
+    pub fn response_file(mut self, response_file: bool) -> Self {
+        self.response_file = Some(response_file);
+        self
+    }
+
+    /// Sets how include headers are staged into the output directory.
+    /// Hardlinks or symlinks avoid copying headers on every build, which matters
+    /// when sources live in a read-only store (Nix) or in large monorepos.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().staging(bindgen_cuda::StagingMode::Hardlink);
+    /// ```
+    /// Changing the mode between builds replaces what was staged, never the headers:
+    /// ```
+    /// use bindgen_cuda::{fixtures, Builder, StagingMode};
+    /// let dir = std::env::temp_dir().join("bindgen_cuda_staging");
+    /// std::fs::create_dir_all(dir.join("out")).unwrap();
+    /// std::fs::write(dir.join("a.cuh"), "#define A 1\n").unwrap();
+    /// # std::env::set_var("OUT_DIR", dir.join("out"));
+    /// for staging in [StagingMode::Hardlink, StagingMode::Copy, StagingMode::Symlink, StagingMode::Copy] {
+    ///     Builder::default()
+    ///         .probe(fixtures::Fixture::new())
+    ///         .fallback_stub(true)
+    ///         .kernel_paths::<PathBuf>(vec![])
+    ///         .include_paths(vec![dir.join("a.cuh")])
+    ///         .staging(staging)
+    ///         .build_ptx()
+    ///         .unwrap();
+    ///     assert_eq!(std::fs::read_to_string(dir.join("a.cuh")).unwrap(), "#define A 1\n");
+    ///     assert_eq!(std::fs::read_to_string(dir.join("out/a.cuh")).unwrap(), "#define A 1\n");
+    /// }
+    /// # use std::path::PathBuf;
+    /// ```
+    pub fn staging(mut self, staging: StagingMode) -> Self {
+        self.staging = staging;
+        self
+    }
+
+    /// Sets the compute caps of fat builds, meant for distribution: [`Builder::build_lib`]
+    /// embeds code for each of them (plus PTX for newer GPUs), and [`Builder::build_ptx`]
+    /// targets the oldest one so the PTX can be JIT compiled by every other.
+    /// Thin builds only target the local GPU, and are used during development.
+    /// See [`Builder::kernel_profile`] for how the profile is selected.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().fat_compute_caps([70, 75, 80, 86, 89, 90]);
+    /// ```
+    pub fn fat_compute_caps<I: IntoIterator<Item = usize>>(mut self, compute_caps: I) -> Self {
+        self.fat_compute_caps = compute_caps.into_iter().collect();
+        self
+    }
+
+    /// Selects the compute caps to build for like CMake's `CUDA_ARCHITECTURES`: `native`
+    /// detects the local GPU, `all` and `all-major` target every (major) compute cap the
+    /// installed nvcc supports, and explicit lists like `80;89;90` (or `8.9`, `sm_89`, as in
+    /// `CUDA_COMPUTE_CAP`) are validated against it.
+    /// Every build then embeds code for each of them, whatever the profile.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().compute_cap_spec("all-major");
+    /// ```
+    pub fn compute_cap_spec(mut self, spec: &str) -> Self {
+        self.compute_cap_spec = Some(
+            ComputeCapSpec::parse(spec)
+                .unwrap_or_else(|| panic!("invalid compute cap specification {spec:?}")),
+        );
+        self
+    }
+
+    /// Forces the profile being built.
+    /// By default fat kernels are built when `BINDGEN_CUDA_PROFILE=fat`, for release builds,
+    /// or when the crate being built has a `fat-kernels` feature enabled, and thin
+    /// kernels otherwise. Each profile is cached in its own directory.
+    /// ```no_run
+    /// use bindgen_cuda::KernelProfile;
+    /// let builder = bindgen_cuda::Builder::default()
+    ///     .fat_compute_caps([80, 86, 89, 90])
+    ///     .kernel_profile(KernelProfile::Fat);
+    /// ```
+    pub fn kernel_profile(mut self, profile: KernelProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Targets the virtual architecture `compute_XX` instead of the real `sm_XX` one.
+    /// PTX generated for a virtual architecture is JIT compiled by the driver on newer
+    /// GPUs, which is usually what embedding PTX is for.
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default()
+    ///     .virtual_arch(true)
+    ///     .build_ptx()
+    ///     .unwrap();
+    /// ```
+    pub fn virtual_arch(mut self, virtual_arch: bool) -> Self {
+        self.virtual_arch = virtual_arch;
+        self
+    }
+
+    /// Sets `--gpu-code`, the architectures nvcc generates code for, independently
+    /// from the `--gpu-architecture` the sources are compiled against.
+    /// Fat builds generate their own codes and ignore it.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default()
+    ///     .virtual_arch(true)
+    ///     .gpu_code(["sm_80", "compute_80"]);
+    /// ```
+    pub fn gpu_code<I, S>(mut self, codes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.gpu_code = codes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Compiles every kernel into a single module named `name`, with a single constant
+    /// in the bindings, instead of one module per source: loading one large module is
+    /// faster than loading many. This is a unity build: sources are compiled as one
+    /// translation unit which includes them all, so they can't define the same `static`
+    /// symbols or macros. To optimize device code across separately compiled sources,
+    /// see [`Builder::device_lto`] instead.
+    /// ```no_run
+    /// // src/add.cu and src/mul.cu both end up in `KERNELS`
+    /// let bindings = bindgen_cuda::Builder::default()
+    ///     .unity_build("kernels")
+    ///     .build_ptx()
+    ///     .unwrap();
+    /// ```
+    pub fn unity_build(mut self, name: &str) -> Self {
+        self.unity_build = Some(name.to_string());
+        self
+    }
+
+    /// Sets how kernels are named, in the output directory and in the generated bindings.
+    /// Building fails with [`Error::NameCollision`] when two kernels end up with the same
+    /// name, or when a constant generated alongside a kernel clashes with another one:
+    /// ```no_run
+    /// use bindgen_cuda::{NameCase, Naming};
+    /// // src/a/reduce.cu and src/b/reduce.cu become `KERNEL_A_REDUCE` and `KERNEL_B_REDUCE`
+    /// let builder = bindgen_cuda::Builder::default().naming(
+    ///     Naming::default()
+    ///         .prefix("kernel_")
+    ///         .parent_dir(true)
+    ///         .case(NameCase::ScreamingSnake),
+    /// );
+    /// ```
+    pub fn naming(mut self, naming: Naming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    /// Compiles every kernel once per variant, with the given preprocessor defines,
+    /// suffixing its outputs and constants with `suffix`. Once a variant is set, kernels
+    /// are only compiled as variants.
+    /// ```no_run
+    /// // src/gemm.cu becomes gemm_f32.ptx and gemm_f16.ptx, `GEMM_F32` and `GEMM_F16`
+    /// let builder = bindgen_cuda::Builder::default()
+    ///     .variant("f32", &[("TYPENAME", "float")])
+    ///     .variant("f16", &[("TYPENAME", "__half")]);
+    /// ```
+    pub fn variant(mut self, suffix: &str, defines: &[(&str, &str)]) -> Self {
+        self.variants.push(Variant {
+            suffix: suffix.to_string(),
+            defines: defines
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        });
+        self
+    }
+
+    /// Compiles the kernel at `kernel.path` under its own name and with its own nvcc
+    /// arguments. A source configured several times is compiled once per configuration,
+    /// which then replace the default one, so that names must differ. Sources not among
+    /// the kernel paths are added to them, whether [`Builder::kernel_paths`] is called
+    /// before or after.
+    /// ```no_run
+    /// use bindgen_cuda::{Builder, KernelConfig};
+    /// // gemm_sm80.ptx and gemm_sm90.ptx, `GEMM_SM80` and `GEMM_SM90`
+    /// let builder = Builder::default()
+    ///     .kernel(KernelConfig::new("src/gemm.cu").name("gemm_sm80"))
+    ///     .kernel(KernelConfig::new("src/gemm.cu").name("gemm_sm90").arg("-DUSE_TMA"));
+    /// ```
+    /// The order of the calls doesn't matter:
+    /// ```
+    /// use bindgen_cuda::{fixtures, Builder, KernelConfig};
+    /// let dir = std::env::temp_dir().join("bindgen_cuda_kernel");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// std::fs::write(dir.join("add.cu"), "").unwrap();
+    /// std::fs::write(dir.join("gemm.cu"), "").unwrap();
+    /// # std::env::set_var("OUT_DIR", &dir);
+    /// let gemm = || KernelConfig::new(dir.join("gemm.cu")).name("gemm_sm90");
+    /// let before = Builder::default()
+    ///     .probe(fixtures::healthy())
+    ///     .kernel(gemm())
+    ///     .kernel_paths(vec![dir.join("add.cu")])
+    ///     .plan_ptx();
+    /// let after = Builder::default()
+    ///     .probe(fixtures::healthy())
+    ///     .kernel_paths(vec![dir.join("add.cu")])
+    ///     .kernel(gemm())
+    ///     .plan_ptx();
+    /// assert_eq!(before.commands().len(), 2);
+    /// assert_eq!(before.commands(), after.commands());
+    /// ```
+    /// Configurations of the same source must be named apart, otherwise they would
+    /// be compiled to the same output:
+    /// ```should_panic
+    /// use bindgen_cuda::{fixtures, Builder, KernelConfig};
+    /// let dir = std::env::temp_dir().join("bindgen_cuda_kernel_unnamed");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// std::fs::write(dir.join("gemm.cu"), "").unwrap();
+    /// # std::env::set_var("OUT_DIR", &dir);
+    /// // panics with: Kernel ".../gemm.cu" is configured several times but all map to .../gemm-....o
+    /// Builder::default()
+    ///     .probe(fixtures::healthy())
+    ///     .kernel(KernelConfig::new(dir.join("gemm.cu")).arg("-DTILE=64"))
+    ///     .kernel(KernelConfig::new(dir.join("gemm.cu")).arg("-DTILE=128"))
+    ///     .plan_lib("gemm");
+    /// ```
+    pub fn kernel(mut self, kernel: KernelConfig) -> Self {
+        if !kernel.path.exists() {
+            panic!("Kernels paths do not exist {:?}", [&kernel.path]);
+        }
+        self.kernel_configs.push(kernel);
+        self.add_configured_kernels();
+        self
+    }
+
+    /// Adds the sources configured with [`Builder::kernel`] to the kernel paths, after
+    /// these are replaced.
+    fn add_configured_kernels(&mut self) {
+        for config in &self.kernel_configs {
+            if !self.kernel_paths.contains(&config.path) {
+                self.kernel_paths.push(config.path.clone());
+            }
+        }
+    }
+
+    /// Sets the maximum number of kernels compiled concurrently by this builder.
+    /// Defaults to `RAYON_NUM_THREADS` or the number of physical cores.
+    /// When running under cargo, concurrent nvcc processes are additionally limited by
+    /// cargo's jobserver so that `cargo build -j N` is respected.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().num_threads(4);
+    /// ```
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads;
+        self
+    }
+
+    /// Relays how long each nvcc invocation took as cargo warnings, slowest first.
+    /// Timings are always written to `timings.json` in `OUT_DIR`, and also relayed
+    /// when `BINDGEN_CUDA_TIMINGS` is set.
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default()
+    ///     .report_timings(true)
+    ///     .build_ptx()
+    ///     .unwrap();
+    /// ```
+    pub fn report_timings(mut self, report_timings: bool) -> Self {
+        self.report_timings = report_timings;
+        self
+    }
+
+    /// Sets how many threads each nvcc process uses (`--threads`, CUDA 11.5+), to compile
+    /// the architectures of multi-arch builds in parallel. The [`Builder::num_threads`]
+    /// budget is split between concurrent processes and their threads, so the machine
+    /// is not oversubscribed. By default threads left unused by concurrent processes,
+    /// when there are fewer kernels to compile than the budget, are given to each process.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default()
+    ///     .num_threads(16)
+    ///     .nvcc_threads(4);
+    /// ```
+    pub fn nvcc_threads(mut self, nvcc_threads: usize) -> Self {
+        self.nvcc_threads = Some(nvcc_threads.max(1));
+        self
+    }
+
+    /// Splits the device compilation of each kernel across threads (`--split-compile`,
+    /// CUDA 12.0+), which is how a single huge kernel gets compiled in parallel. It
+    /// shares the [`Builder::num_threads`] budget with concurrent processes and
+    /// [`Builder::nvcc_threads`]: each process gets its share of the budget, one thread
+    /// per architecture unless `nvcc_threads` says otherwise, and
+    /// [`SplitCompile::Auto`] the rest. A build with a single kernel therefore splits
+    /// it as much as the machine allows, while builds with more kernels than threads
+    /// keep compiling one kernel per thread. A fixed [`SplitCompile::Threads`] never
+    /// oversubscribes the machine either: fewer kernels are compiled concurrently.
+    /// Toolkits older than 12.0 compile kernels without splitting them, with a warning.
+    /// ```no_run
+    /// use bindgen_cuda::SplitCompile;
+    /// let builder = bindgen_cuda::Builder::default().split_compile(SplitCompile::Auto);
+    /// ```
+    /// Plans show the arguments each kernel gets, here a single kernel for 2
+    /// architectures on 8 threads:
+    /// ```
+    /// use bindgen_cuda::{fixtures, Builder, KernelProfile, SplitCompile};
+    /// let dir = std::env::temp_dir().join("bindgen_cuda_split");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// std::fs::write(dir.join("gemm.cu"), "__global__ void gemm() {}\n").unwrap();
+    /// # std::env::set_var("OUT_DIR", &dir);
+    /// let plan = Builder::default()
+    ///     .probe(fixtures::healthy())
+    ///     .kernel_paths(vec![dir.join("gemm.cu")])
+    ///     .fat_compute_caps([80, 90])
+    ///     .kernel_profile(KernelProfile::Fat)
+    ///     .num_threads(8)
+    ///     .single_threaded(false)
+    ///     .split_compile(SplitCompile::Auto)
+    ///     .plan_lib("gemm");
+    /// assert!(plan.commands()[0].contains(r#""--threads" "2" "--split-compile=4""#));
+    /// ```
+    /// With as many kernels as threads, file level parallelism already keeps every
+    /// thread busy and nothing is split:
+    /// ```
+    /// use bindgen_cuda::{fixtures, Builder, SplitCompile};
+    /// let dir = std::env::temp_dir().join("bindgen_cuda_split_many");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// let kernels: Vec<_> = (0..8).map(|i| dir.join(format!("k{i}.cu"))).collect();
+    /// for kernel in &kernels {
+    ///     std::fs::write(kernel, "").unwrap();
+    /// }
+    /// # std::env::set_var("OUT_DIR", &dir);
+    /// let plan = Builder::default()
+    ///     .probe(fixtures::healthy())
+    ///     .kernel_paths(kernels)
+    ///     .num_threads(8)
+    ///     .single_threaded(false)
+    ///     .split_compile(SplitCompile::Auto)
+    ///     .plan_ptx();
+    /// assert_eq!(plan.commands().len(), 8);
+    /// assert!(plan.commands().iter().all(|c| !c.contains("--split-compile")));
+    /// ```
+    pub fn split_compile(mut self, split_compile: SplitCompile) -> Self {
+        self.split_compile = Some(split_compile);
+        self
+    }
+
+    /// Compiles kernels one at a time, in order, with nvcc's output streamed as it comes.
+    /// This is meant to bisect nondeterministic nvcc failures or make sense of interleaved
+    /// output on busy machines, it can also be enabled with the `BINDGEN_CUDA_SINGLE_THREADED=1`
+    /// environment variable or the `single-threaded` feature.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().single_threaded(true);
+    /// ```
+    pub fn single_threaded(mut self, single_threaded: bool) -> Self {
+        self.single_threaded = single_threaded;
+        self
+    }
+
+    /// Lets every compilation finish when one fails, to report all the failing kernels
+    /// and their diagnostics in a single [`Error::Compilation`]. By default the kernels
+    /// which haven't started compiling yet are skipped after the first failure.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().keep_going(true);
+    /// if let Err(bindgen_cuda::Error::Compilation { inputs, .. }) = builder.build_ptx() {
+    ///     println!("cargo:warning={} kernels failed", inputs.len());
+    /// }
+    /// ```
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
+    }
+
+    /// Relays nvcc's output to stderr line by line as it is printed, prefixed with the
+    /// kernel it comes from, instead of once the kernel is compiled. Cargo shows it
+    /// live with `cargo build -vv`, so long builds don't look stuck. The output is still
+    /// relayed as warnings and reported on failure according to [`Builder::verbosity`].
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().stream_output(true);
+    /// ```
+    pub fn stream_output(mut self, stream_output: bool) -> Self {
+        self.stream_output = stream_output;
+        self
+    }
+
+    /// Kills nvcc, along with the compilers it runs, when a single invocation takes
+    /// longer than `timeout`, failing with [`Error::Timeout`] naming the kernel rather
+    /// than stalling the build. Such nvcc processes are started in their own process
+    /// group on Unix, which is what gets killed. The terminal then doesn't send them
+    /// Ctrl-C: interrupting the build kills the build script, while the nvcc processes
+    /// already running finish in the background. Builds which fail or panic kill them.
+    /// ```no_run
+    /// use std::time::Duration;
+    /// let builder = bindgen_cuda::Builder::default().timeout(Duration::from_secs(600));
+    /// ```
+    pub fn timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Like [`Builder::timeout`], for all the invocations of the build together,
+    /// counted from the first one.
+    /// ```no_run
+    /// use std::time::Duration;
+    /// let builder = bindgen_cuda::Builder::default().build_timeout(Duration::from_secs(1800));
+    /// ```
+    pub fn build_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.build_timeout = Some(timeout);
+        self
+    }
+
+    /// When the job starting now has to be killed, if ever.
+    fn job_deadline(&self) -> Option<std::time::Instant> {
+        let now = std::time::Instant::now();
+        let job = self.timeout.map(|timeout| now + timeout);
+        let build = self
+            .build_timeout
+            .map(|timeout| *self.started.get_or_init(|| now) + timeout);
+        job.into_iter().chain(build).min()
+    }
+
+    /// Keeps the files nvcc generates along the way (`.ii`, `.ptx`, `.cubin`...) with
+    /// `--keep`, in `OUT_DIR/intermediates/<build>/<kernel>`, to debug ptxas without
+    /// reconstructing the nvcc commands by hand. Where they are is printed as warnings,
+    /// and recorded in the [`Manifest`]. The [`Builder::artifact_cache`] is not used.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().keep_intermediates(true);
+    /// ```
+    pub fn keep_intermediates(mut self, keep_intermediates: bool) -> Self {
+        self.keep_intermediates = keep_intermediates;
+        self
+    }
+
+    /// Records how each artifact was compiled in `<artifact>.provenance.json` next to it:
+    /// the nvcc command, the sub-commands it ran (`cicc`, `ptxas`, `fatbinary`...) as
+    /// listed by `--verbose`, and the `--version` of each of these tools. The `PATH`
+    /// of the build machine is left out. The [`Builder::artifact_cache`] is not used,
+    /// so that every artifact is accounted for.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().provenance(true);
+    /// ```
+    pub fn provenance(mut self, provenance: bool) -> Self {
+        self.provenance = provenance;
+        self
+    }
+
+    /// Has nvcc list the headers each kernel includes while compiling it, with
+    /// `--generate-dependencies-with-compile`, into a `.d` file next to its output.
+    /// The next builds recompile the kernels whose headers changed, and cargo reruns
+    /// the build script when any of them changes, without a separate `-M` pass.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().dependency_files(true);
+    /// ```
+    pub fn dependency_files(mut self, dependency_files: bool) -> Self {
+        self.dependency_files = dependency_files;
+        self
+    }
+
+    /// Makes [`Builder::build_ptx`] compile each kernel for every compute cap of the
+    /// build (see [`Builder::compute_cap_spec`]) instead of only the oldest one, into
+    /// `gemm.sm80.ptx`, `gemm.sm90.ptx`... The bindings get one constant per compute cap
+    /// (`GEMM_SM80`), and a `gemm_ptx(compute_cap)` function returning the PTX of the
+    /// newest compute cap the device can run, or `None` when it is older than all of them.
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default()
+    ///     .compute_cap_spec("80;90")
+    ///     .ptx_per_compute_cap(true)
+    ///     .build_ptx()
+    ///     .unwrap();
+    /// bindings.write("src/lib.rs").unwrap();
+    /// ```
+    pub fn ptx_per_compute_cap(mut self, ptx_per_compute_cap: bool) -> Self {
+        self.ptx_per_compute_cap = ptx_per_compute_cap;
+        self
+    }
+
+    /// Enables device link time optimization for [`Builder::build_lib`]: objects are
+    /// compiled to LTO-IR with `-dlto`, then device linked together before being
+    /// archived, so device code can be inlined and optimized across source files.
+    /// The device link step resolves device symbols, the library must then be
+    /// self-contained.
+    /// ```no_run
+    /// bindgen_cuda::Builder::default()
+    ///     .device_lto(true)
+    ///     .build_lib("fused");
+    /// ```
+    pub fn device_lto(mut self, device_lto: bool) -> Self {
+        self.device_lto = device_lto;
+        self
+    }
+
+    /// Sets the tool creating the library of [`Builder::build_lib`], `nvcc --lib` by
+    /// default. Cross builds may need the archiver of the target toolchain instead.
+    /// ```no_run
+    /// use bindgen_cuda::{Archiver, Builder};
+    /// Builder::default()
+    ///     .archiver(Archiver::Ar("llvm-ar".into()))
+    ///     .build_lib("flash");
+    /// ```
+    pub fn archiver(mut self, archiver: Archiver) -> Self {
+        self.archiver = archiver;
+        self
+    }
+
+    /// Makes [`Builder::build_lib`] create a thin archive, which references the objects
+    /// in the output directory instead of copying them. Requires [`Archiver::Ar`].
+    /// ```no_run
+    /// use bindgen_cuda::{Archiver, Builder};
+    /// Builder::default()
+    ///     .archiver(Archiver::Ar("ar".into()))
+    ///     .thin_archive(true)
+    ///     .build_lib("flash");
+    /// ```
+    pub fn thin_archive(mut self, thin_archive: bool) -> Self {
+        self.thin_archive = thin_archive;
+        self
+    }
+
+    /// Makes [`Builder::build_lib`] add the objects to the library if it already exists,
+    /// replacing the members with the same name, rather than creating it anew.
+    /// Requires [`Archiver::Ar`] or [`Archiver::Lib`].
+    /// ```no_run
+    /// use bindgen_cuda::{Archiver, Builder};
+    /// Builder::default()
+    ///     .archiver(Archiver::Ar("ar".into()))
+    ///     .append_archive(true)
+    ///     .build_lib(std::path::Path::new("target/libcombined.a"));
+    /// ```
+    pub fn append_archive(mut self, append_archive: bool) -> Self {
+        self.append_archive = append_archive;
+        self
+    }
+
+    /// Registers a hook receiving every nvcc command right before it is run, which may
+    /// modify it, e.g. to run nvcc through a wrapper like `numactl` or `strace`.
+    /// Hooks run in the order they were registered, and are part of the crate's stable API.
+    /// What a hook does to a reference command is recorded in the build fingerprint,
+    /// so changing it triggers a rebuild, as long as it modifies all commands the same way.
+    /// ```no_run
+    /// use std::process::Command;
+    /// let builder = bindgen_cuda::Builder::default().customize_command(|command| {
+    ///     let mut wrapped = Command::new("numactl");
+    ///     wrapped.arg("--cpunodebind=0").arg(command.get_program()).args(command.get_args());
+    ///     *command = wrapped;
+    /// });
+    /// ```
+    pub fn customize_command<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&mut Command) + Send + Sync + 'static,
+    {
+        self.command_hooks.push(CommandHook(Arc::new(hook)));
+        self
+    }
+
+    /// Runs every nvcc invocation through a compiler cache like `sccache` or `ccache`,
+    /// which may be followed by its own arguments. Without it, the wrapper is taken from
+    /// `NVCC_WRAPPER` or `CMAKE_CUDA_COMPILER_LAUNCHER`, an empty string disables both.
+    /// The wrapper only changes how nvcc is run, switching does not trigger a rebuild.
+    /// It sees every argument on the command line, [`Builder::response_file`] is ignored.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().compiler_wrapper("sccache");
+    /// ```
+    pub fn compiler_wrapper(mut self, wrapper: &str) -> Self {
+        self.compiler_wrapper = Some(wrapper.to_string());
+        self
+    }
+
+    /// Looks up kernels in `cache` before compiling them, and stores the ones compiled,
+    /// so CI workers and teammates reuse each other's PTX, cubins... Without it, the cache
+    /// is taken from `BINDGEN_CUDA_CACHE`, a URL or a directory. Failing to reach the
+    /// cache only warns. Keys hash the nvcc version, the arguments and every file nvcc
+    /// lists with `--generate-dependencies`, hooks of [`Builder::customize_command`] are
+    /// not part of them.
+    /// ```no_run
+    /// use bindgen_cuda::{ArtifactCache, Builder};
+    /// let builder = Builder::default().artifact_cache(ArtifactCache::Directory("/mnt/shared/kernels".into()));
+    /// ```
+    pub fn artifact_cache(mut self, cache: ArtifactCache) -> Self {
+        self.artifact_cache = Some(cache);
+        self
+    }
+
+    /// Registers a hook rewriting the PTX of each kernel, given its name as in `ALL_KERNELS`,
+    /// before the bindings include it, e.g. to override `.version` or rename entry points.
+    /// Hooks run in the order they were registered. nvcc's output is kept as is next to
+    /// the PTX (`kernel.raw.ptx`), and hooks run again on every build.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default()
+    ///     .post_process(|_name, ptx| ptx.replace(".version 8.4", ".version 8.0"));
+    /// ```
+    pub fn post_process<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&str, String) -> String + Send + Sync + 'static,
+    {
+        self.post_process.push(PostProcess(Arc::new(hook)));
+        self
+    }
+
+    /// Registers a hook rewriting each CUDA source, given its path, before it is compiled,
+    /// e.g. to expand templates or patch includes. Hooks run in the order they were
+    /// registered, on every build. The rewritten source is written under `OUT_DIR` and
+    /// compiled instead, the source tree is left untouched. Its includes still resolve
+    /// from the directory of the original, which diagnostics point at.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default()
+    ///     .preprocess(|_path, source| source.replace("@BLOCK_SIZE@", "256"));
+    /// ```
+    pub fn preprocess<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Path, String) -> String + Send + Sync + 'static,
+    {
+        self.preprocess.push(Preprocess(Arc::new(hook)));
+        self
+    }
+
+    /// The file nvcc compiles for the source at `path`: its rewrite by the
+    /// [`Builder::preprocess`] hooks, written before the jobs run only when it changed,
+    /// or `path` itself.
+    fn source(&self, path: &Path) -> PathBuf {
+        // Generated sources, like unity builds, include already rewritten ones.
+        if self.preprocess.is_empty()
+            || Language::of(path) != Language::Cuda
+            || path.starts_with(self.build_dir())
+        {
+            return path.to_path_buf();
+        }
+        let mut preprocessed = self.preprocessed.lock().expect("preprocessed sources lock");
+        if let Some(source) = preprocessed.get(path) {
+            return source.clone();
+        }
+        let content = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("read {}: {e}", path.display()));
+        let content = self
+            .preprocess
+            .iter()
+            .fold(content, |content, hook| (hook.0)(path, content));
+        let content = format!("#line 1 {}\n{content}", includes::line_path(path));
+        let source = self
+            .output_dir(path)
+            .join(path.file_name().expect("kernel to have a filename"));
+        self.write_later(&source, content.into_bytes());
+        preprocessed.insert(path.to_path_buf(), source.clone());
+        source
+    }
+
+    /// The include directories of the source at `path`, starting with its own directory
+    /// when it is compiled from a [`Builder::preprocess`]ed copy.
+    fn source_include_dirs(&self, path: &Path) -> Vec<PathBuf> {
+        let mut include_dirs = self.include_dirs();
+        if self.source(path) != path {
+            let dir = path.parent().unwrap_or(Path::new(""));
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            include_dirs.insert(0, dir.to_path_buf());
+        }
+        include_dirs
+    }
+
+    /// `-I` for the directory of the source at `path`, see [`Builder::source_include_dirs`].
+    fn source_include_option(&self, path: &Path) -> Option<OsString> {
+        if self.source(path) == path {
+            return None;
+        }
+        let mut option = OsString::from("-I");
+        option.push(&self.source_include_dirs(path)[0]);
+        Some(option)
+    }
+
+    /// Makes builds reproducible, so that building the same sources twice produces
+    /// bit for bit identical libraries and bindings: kernels are processed in sorted
+    /// order, build paths are stripped from host objects and kernel paths are passed
+    /// relative to the current directory, and static library members get fixed
+    /// timestamps and owners. Enabled by default when `SOURCE_DATE_EPOCH` is set.
+    /// ```no_run
+    /// bindgen_cuda::Builder::default()
+    ///     .reproducible(true)
+    ///     .build_lib("flash");
+    /// ```
+    pub fn reproducible(mut self, reproducible: bool) -> Self {
+        self.reproducible = reproducible;
+        self
+    }
+
+    /// When no CUDA toolkit can be found, skip compilation instead of panicking, and
+    /// generate bindings with empty placeholders behind `#[cfg(cuda_stub)]`, which the
+    /// build then sets. Lets crates optionally using CUDA build on CPU-only CI.
+    /// Always enabled on docs.rs (`DOCS_RS` is set).
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default()
+    ///     .fallback_stub(true)
+    ///     .build_ptx()
+    ///     .unwrap();
+    /// bindings.write("src/kernels.rs").unwrap();
+    /// ```
+    pub fn fallback_stub(mut self, fallback_stub: bool) -> Self {
+        self.fallback_stub = fallback_stub;
+        self
+    }
+
+    /// Sets what happens when nvcc warns that targeted architectures are deprecated.
+    /// ```no_run
+    /// use bindgen_cuda::DeprecatedArchs;
+    /// let builder = bindgen_cuda::Builder::default()
+    ///     .fat_compute_caps([70, 75, 80, 86, 89, 90])
+    ///     .deprecated_archs(DeprecatedArchs::Deny);
+    /// ```
+    pub fn deprecated_archs(mut self, deprecated_archs: DeprecatedArchs) -> Self {
+        self.deprecated_archs = deprecated_archs;
+        self
+    }
+
+    /// Sets how much of nvcc's output is relayed through `cargo:warning` lines.
+    /// By default only warnings are, prefixed with the kernel they come from.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().verbosity(bindgen_cuda::Verbosity::Verbose);
+    /// ```
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// Forces the cuda root to a specific directory.
+    /// By default all standard directories will be visited.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default().cuda_root("/usr/local/cuda");
+    /// ```
+    pub fn cuda_root<P>(&mut self, path: P)
+    where
+        P: Into<PathBuf>,
+    {
+        self.cuda_root = Some(path.into());
+    }
+
+    /// The root of the CUDA toolkit, set with [`Builder::cuda_root`] or detected from
+    /// `CUDA_PATH`, standard install locations, the active conda or Python environment,
+    /// and the `nvcc` in the `PATH`.
+    /// ```
+    /// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+    /// let builder = bindgen_cuda::Builder::default().probe(bindgen_cuda::fixtures::conda());
+    /// assert_eq!(
+    ///     builder.cuda_root_dir().unwrap(),
+    ///     std::path::Path::new("/opt/conda/envs/ml/targets/x86_64-linux")
+    /// );
+    /// ```
+    pub fn cuda_root_dir(&self) -> Option<&Path> {
+        self.cuda_root.as_deref()
+    }
+
+    /// Consumes the builder and creates a static library out of the kernels.
+    /// Given a bare name like `"flash"`, the library is created in the out_dir
+    /// (`libflash.a`, or `flash.lib` for MSVC targets). Relative paths like
+    /// `"libflash.a"` are also resolved against the out_dir, absolute ones are used as is.
+    /// The `cargo:rustc-link-search` and `cargo:rustc-link-lib=static` directives linking
+    /// it are printed, build scripts printing their own should drop them (see the
+    /// README on upgrading from 0.1).
+    /// C++ host sources (`.cpp`, `.cc`, `.cxx`) among the kernel paths are compiled
+    /// with nvcc as well and archived into the same library.
+    /// ```no_run
+    /// let library = bindgen_cuda::Builder::default().build_lib("flash");
+    /// println!("cargo:warning=built {}", library.path().display());
+    /// ```
+    pub fn build_lib<P>(self, out_file: P) -> Library
+    where
+        P: Into<PathBuf>,
+    {
+        self.check_entry_points("build_lib");
+        let out_file = self.lib_path(out_file.into());
+        let library = Library::new(out_file.clone(), false);
+        for var in BUILD_VARS {
+            println!("cargo:rerun-if-env-changed={var}");
+        }
+        for path in &self.watch {
+            rerun_if_changed(path);
+        }
+        if self.is_stub() {
+            println!(
+                "cargo:warning=CUDA toolkit not found, skipping {}",
+                out_file.display()
+            );
+            return library;
+        }
+        self.check_requirements().unwrap_or_else(|e| panic!("{e}"));
+        let name = lib_fingerprint_name(&out_file);
+        let fingerprint = self.fingerprint(&name);
+        let (compile_jobs, link_jobs, _) = self.lib_jobs(&out_file, false);
+        if !link_jobs.is_empty() {
+            self.run_jobs(compile_jobs)
+                .unwrap_or_else(|e| panic!("{e}"));
+            if !self.append_archive {
+                // Archivers add to existing libraries, which would keep removed kernels.
+                let _ = std::fs::remove_file(&out_file);
+            }
+            for link_job in link_jobs {
+                self.run_jobs(vec![link_job])
+                    .unwrap_or_else(|e| panic!("{e}"));
+            }
+            self.write_timings();
+            self.report_intermediates(&intermediates_group(&out_file));
+            if self.is_reproducible() {
+                normalize_archive(&out_file).expect("normalize static library");
+            }
+        }
+        self.emit_compute_cap(&name);
+        self.store_fingerprint(&name, &fingerprint);
+        self.rerun_if_dependencies_changed(self.object_paths());
+        library.emit_link_directives();
+        library
+    }
+
+    /// Compiles the kernels and C++ sources into objects added to `build`, so that
+    /// `build.compile(..)` archives them together with the host sources `cc` compiles.
+    /// nvcc uses the compiler `build` is configured with as its host compiler, with the
+    /// same flags, except for the runtime flag when [`Builder::msvc_runtime`] is set.
+    /// Requires the `cc` feature.
+    /// ```no_run
+    /// let mut build = bindgen_cuda::cc::Build::new();
+    /// build.cpp(true).file("src/host.cpp");
+    /// bindgen_cuda::Builder::default().host_build(&mut build);
+    /// build.compile("kernels");
+    /// ```
+    #[cfg(feature = "cc")]
+    pub fn host_build(mut self, build: &mut cc::Build) -> Vec<PathBuf> {
+        self.check_entry_points("host_build");
+        let compiler = build.get_compiler();
+        self.host_tool = Some(HostTool {
+            path: compiler.path().to_path_buf(),
+            args: compiler.args().to_vec(),
+        });
+        let objects = self.objects("host");
+        build.objects(&objects);
+        objects
+    }
+
+    /// Compiles the kernels and C++ sources into objects without archiving them, and
+    /// returns their paths, for build systems (or linkers) consuming objects directly.
+    /// With [`Builder::device_lto`], the device linked object comes last.
+    /// ```no_run
+    /// let objects = bindgen_cuda::Builder::default().compile_objects();
+    /// for object in &objects {
+    ///     println!("cargo:warning=compiled {}", object.display());
+    /// }
+    /// ```
+    pub fn compile_objects(self) -> Vec<PathBuf> {
+        self.check_entry_points("compile_objects");
+        self.objects("objects")
+    }
+
+    /// Compiles the objects of [`Builder::compile_objects`], `group` naming their
+    /// device linked object and intermediates.
+    fn objects(&self, group: &str) -> Vec<PathBuf> {
+        for var in BUILD_VARS {
+            println!("cargo:rerun-if-env-changed={var}");
+        }
+        for path in &self.watch {
+            rerun_if_changed(path);
+        }
+        if self.is_stub() {
+            println!("cargo:warning=CUDA toolkit not found, skipping the CUDA objects");
+            return vec![];
+        }
+        self.check_requirements().unwrap_or_else(|e| panic!("{e}"));
+        let (compile_jobs, link_jobs, objects) =
+            self.object_jobs(&self.build_dir().join(group), false);
+        self.run_jobs(compile_jobs)
+            .unwrap_or_else(|e| panic!("{e}"));
+        for link_job in link_jobs {
+            self.run_jobs(vec![link_job])
+                .unwrap_or_else(|e| panic!("{e}"));
+        }
+        self.write_timings();
+        self.report_intermediates(group);
+        self.rerun_if_dependencies_changed(self.object_paths());
+        objects
+    }
+
+    /// Fails when [`KernelConfig::entry_points`] are set for the build `build`, which
+    /// doesn't produce PTX, rather than silently not verifying them.
+    fn check_entry_points(&self, build: &str) {
+        let expecting = self
+            .kernel_configs
+            .iter()
+            .find(|config| !config.entry_points.is_empty());
+        if let Some(config) = expecting {
+            panic!(
+                "the entry points of {} are only verified by `Builder::build_ptx`, not `Builder::{build}`",
+                config.path.display()
+            );
+        }
+    }
+
+    /// Where [`Builder::build_lib`] creates the library `out_file`.
+    fn lib_path(&self, out_file: PathBuf) -> PathBuf {
+        let is_name = out_file.extension().is_none() && out_file.components().count() == 1;
+        if !is_name {
+            return self.out_dir.join(out_file);
+        }
+        let name = out_file.to_string_lossy();
+        if self.recorder().var("CARGO_CFG_TARGET_ENV").as_deref() == Some("msvc") {
+            self.out_dir.join(format!("{name}.lib"))
+        } else {
+            self.out_dir.join(format!("lib{name}.a"))
+        }
+    }
+
+    /// Returns the nvcc commands [`Builder::build_lib`] would run, without running them.
+    /// ```no_run
+    /// let plan = bindgen_cuda::Builder::default().plan_lib("libflash.a");
+    /// println!("{plan}");
+    /// ```
+    /// Relative paths are created in the out_dir:
+    /// ```
+    /// use bindgen_cuda::{fixtures, Builder};
+    /// let dir = std::env::temp_dir().join("bindgen_cuda_lib_path");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// std::fs::write(dir.join("flash.cu"), "__global__ void flash() {}\n").unwrap();
+    /// # std::env::set_var("OUT_DIR", &dir);
+    /// let plan = Builder::default()
+    ///     .probe(fixtures::healthy())
+    ///     .kernel_paths(vec![dir.join("flash.cu")])
+    ///     .plan_lib("libflash.a");
+    /// let link = plan.commands().last().unwrap();
+    /// assert!(link.contains(&format!("{:?}", dir.join("libflash.a"))));
+    /// ```
+    pub fn plan_lib<P>(&self, out_file: P) -> Plan
+    where
+        P: Into<PathBuf>,
+    {
+        let (compile_jobs, link_jobs, up_to_date) =
+            self.lib_jobs(&self.lib_path(out_file.into()), false);
+        Plan::new(compile_jobs.iter().chain(&link_jobs), up_to_date)
+    }
+
+    /// Consumes the builder and creates a shared library out of the kernels, linked by
+    /// `nvcc --shared` from position independent objects. Given a bare name like
+    /// `"kernels"`, the library is created in the out_dir (`libkernels.so`,
+    /// `libkernels.dylib` or `kernels.dll`), like relative paths, absolute ones are
+    /// used as is.
+    /// The `cargo:rustc-link-search` and `cargo:rustc-link-lib=dylib` directives linking
+    /// it are printed, `cargo run` and `cargo test` then find it in the out_dir.
+    /// No rpath is set: binaries run outside of cargo need the library next to them or
+    /// on the loader path (`LD_LIBRARY_PATH`, `DYLD_LIBRARY_PATH`), unless the crate
+    /// prints its own `cargo:rustc-link-arg=-Wl,-rpath,..`.
+    /// On Windows, rustc links against the `kernels.lib` import library, which the
+    /// linker only writes when the DLL exports symbols: declare the `extern "C"`
+    /// functions `__declspec(dllexport)`, or pass a module definition file with
+    /// `.args(["-Xlinker", "/DEF:kernels.def"])`.
+    /// ```no_run
+    /// let library = bindgen_cuda::Builder::default().build_shared("kernels");
+    /// println!("cargo:warning=built {}", library.path().display());
+    /// ```
+    pub fn build_shared<P>(self, out_file: P) -> Library
+    where
+        P: Into<PathBuf>,
+    {
+        self.check_entry_points("build_shared");
+        let out_file = self.shared_lib_path(out_file.into());
+        let library = Library::new(out_file.clone(), true);
+        for var in BUILD_VARS {
+            println!("cargo:rerun-if-env-changed={var}");
+        }
+        for path in &self.watch {
+            rerun_if_changed(path);
+        }
+        if self.is_stub() {
+            println!(
+                "cargo:warning=CUDA toolkit not found, skipping {}",
+                out_file.display()
+            );
+            return library;
+        }
+        self.check_requirements().unwrap_or_else(|e| panic!("{e}"));
+        let name = lib_fingerprint_name(&out_file);
+        let fingerprint = self.fingerprint(&name);
+        let (compile_jobs, link_jobs, _) = self.lib_jobs(&out_file, true);
+        if !link_jobs.is_empty() {
+            self.run_jobs(compile_jobs)
+                .unwrap_or_else(|e| panic!("{e}"));
+            for link_job in link_jobs {
+                self.run_jobs(vec![link_job])
+                    .unwrap_or_else(|e| panic!("{e}"));
+            }
+            self.write_timings();
+            self.report_intermediates(&intermediates_group(&out_file));
+        }
+        self.emit_compute_cap(&name);
+        self.store_fingerprint(&name, &fingerprint);
+        self.rerun_if_dependencies_changed(self.object_paths());
+        library.emit_link_directives();
+        library
+    }
+
+    /// Where [`Builder::build_shared`] creates the library `out_file`.
+    fn shared_lib_path(&self, out_file: PathBuf) -> PathBuf {
+        let is_name = out_file.extension().is_none() && out_file.components().count() == 1;
+        if !is_name {
+            return self.out_dir.join(out_file);
+        }
+        let name = out_file.to_string_lossy();
+        match self.recorder().var("CARGO_CFG_TARGET_OS").as_deref() {
+            Some("windows") => self.out_dir.join(format!("{name}.dll")),
+            Some("macos") => self.out_dir.join(format!("lib{name}.dylib")),
+            _ => self.out_dir.join(format!("lib{name}.so")),
+        }
+    }
+
+    /// Returns the nvcc commands [`Builder::build_shared`] would run, without running them.
+    /// ```no_run
+    /// let plan = bindgen_cuda::Builder::default().plan_shared("libkernels.so");
+    /// println!("{plan}");
+    /// ```
+    pub fn plan_shared<P>(&self, out_file: P) -> Plan
+    where
+        P: Into<PathBuf>,
+    {
+        let (compile_jobs, link_jobs, up_to_date) =
+            self.lib_jobs(&self.shared_lib_path(out_file.into()), true);
+        Plan::new(compile_jobs.iter().chain(&link_jobs), up_to_date)
+    }
+
+    /// Consumes the builder and outputs 1 ptx file for each kernels
+    /// found.
+    /// This function returns [`Bindings`] which can then be unused
+    /// to create a rust source file that will include those kernels.
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default().build_ptx().unwrap();
+    /// bindings.write("src/lib.rs").unwrap();
+    /// ```
+    /// Builds with nothing changed since the previous one return right away, without
+    /// running `nvidia-smi`, nvcc or `vswhere`:
+    /// ```
+    /// use bindgen_cuda::{fixtures, Builder};
+    /// use std::path::PathBuf;
+    /// let dir = std::env::temp_dir().join("bindgen_cuda_up_to_date");
+    /// # let _ = std::fs::remove_dir_all(&dir);
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// # std::env::set_var("OUT_DIR", &dir);
+    /// let build = |fixture: &fixtures::Fixture| {
+    ///     Builder::default()
+    ///         .probe(fixture.clone())
+    ///         .kernel_paths::<PathBuf>(vec![])
+    ///         .cargo_profile(true)
+    ///         .arg_if_version(">=12.0", "-DHAS_CUDA_12")
+    ///         .build_ptx()
+    ///         .unwrap()
+    /// };
+    /// let first = fixtures::windows();
+    /// build(&first);
+    /// assert!(first.commands().contains(&"nvidia-smi --query-gpu=compute_cap --format=csv".to_string()));
+    ///
+    /// let second = fixtures::windows();
+    /// build(&second);
+    /// assert!(second.commands().is_empty());
+    /// ```
+    pub fn build_ptx(self) -> Result<Bindings, Error> {
+        self.build_kernels(Output::Ptx)
+    }
+
+    /// Builds the `[output]` of the [`Builder::config_file`], PTX when it has none.
+    /// Requires the `toml` feature.
+    /// ```no_run
+    /// bindgen_cuda::Builder::default()
+    ///     .config_file("kernels.toml")
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    #[cfg(feature = "toml")]
+    pub fn build(mut self) -> Result<(), Error> {
+        match self.target.take() {
+            None => self.build_ptx().map(drop),
+            Some(config::Target::Ptx(None)) => self.build_ptx().map(drop),
+            Some(config::Target::Ptx(Some(bindings))) => self.build_ptx()?.write(bindings),
+            Some(config::Target::Lib(name)) => {
+                self.build_lib(name);
+                Ok(())
+            }
+            Some(config::Target::Shared(name)) => {
+                self.build_shared(name);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the nvcc commands [`Builder::build_ptx`] would run, and which kernels
+    /// are considered up to date, without running anything.
+    /// ```no_run
+    /// let plan = bindgen_cuda::Builder::default().plan_ptx();
+    /// for command in plan.commands() {
+    ///     println!("{command}");
+    /// }
+    /// ```
+    /// Planning writes nothing, not even the files the commands need:
+    /// ```
+    /// use bindgen_cuda::{fixtures, Builder};
+    /// let dir = std::env::temp_dir().join("bindgen_cuda_plan");
+    /// let _ = std::fs::remove_dir_all(&dir);
+    /// std::fs::create_dir_all(dir.join("out")).unwrap();
+    /// std::fs::write(dir.join("gemm.cu"), "__global__ void gemm() {}\n").unwrap();
+    /// # std::env::set_var("OUT_DIR", dir.join("out"));
+    /// let plan = Builder::default()
+    ///     .probe(fixtures::healthy())
+    ///     .kernel_paths(vec![dir.join("gemm.cu")])
+    ///     .preprocess(|_path, source| source.replace("gemm", "gemm_f32"))
+    ///     .fat_compute_caps([80, 90])
+    ///     .keep_intermediates(true)
+    ///     .response_file(true)
+    ///     .plan_ptx();
+    /// assert_eq!(plan.commands().len(), 1);
+    /// assert!(!plan.commands()[0].contains("--options-file"));
+    /// assert_eq!(std::fs::read_dir(dir.join("out")).unwrap().count(), 0);
+    /// ```
+    pub fn plan_ptx(&self) -> Plan {
+        let (jobs, up_to_date) = self.kernel_jobs(Output::Ptx);
+        Plan::new(&jobs, up_to_date)
+    }
+
+    /// Consumes the builder and outputs 1 relocatable device library for each kernels
+    /// found, meant to be linked at runtime with nvJitLink (or the driver linker) rather
+    /// than loaded directly, for instance to fuse device functions with NVRTC generated kernels.
+    /// The generated bindings embed each library with `include_bytes!` alongside
+    /// the list of `extern "C"` device functions it exports.
+    /// ```no_run
+    /// use bindgen_cuda::DeviceLibFormat;
+    /// let bindings = bindgen_cuda::Builder::default()
+    ///     .build_device_lib(DeviceLibFormat::LtoIr)
+    ///     .unwrap();
+    /// bindings.write("src/lib.rs").unwrap();
+    /// ```
+    pub fn build_device_lib(self, format: DeviceLibFormat) -> Result<Bindings, Error> {
+        self.build_kernels(Output::DeviceLib(format))
+    }
+
+    /// Returns the nvcc commands [`Builder::build_device_lib`] would run, without running them.
+    pub fn plan_device_lib(&self, format: DeviceLibFormat) -> Plan {
+        let (jobs, up_to_date) = self.kernel_jobs(Output::DeviceLib(format));
+        Plan::new(&jobs, up_to_date)
+    }
+
+    /// Consumes the builder and outputs 1 OptiX IR module (`--optix-ir`) for each kernel
+    /// found, to be loaded by OptiX (`optixModuleCreate`) rather than by the driver.
+    /// Like PTX, OptiX IR targets the oldest compute cap's virtual architecture.
+    /// The generated bindings embed each module with `include_bytes!`.
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default().build_optix_ir().unwrap();
+    /// bindings.write("src/lib.rs").unwrap();
+    /// ```
+    pub fn build_optix_ir(self) -> Result<Bindings, Error> {
+        self.build_kernels(Output::OptixIr)
+    }
+
+    /// Consumes the builder and, instead of compiling the kernels, embeds their source to
+    /// compile them at runtime with NVRTC, for the GPU the program actually runs on.
+    /// The headers each kernel includes from the source tree or the include paths are
+    /// inlined into it, and the defines of its [`Builder::variant`] or [`KernelConfig`]
+    /// are passed as NVRTC options. Builder arguments are meant for nvcc and ignored.
+    /// No CUDA toolkit is needed at build time.
+    ///
+    /// Each constant is an `NvrtcKernel`, whose `compile` method uses
+    /// [cudarc](https://github.com/coreylowman/cudarc/) (0.16 or newer, with the `driver`
+    /// and `nvrtc` features), a dependency of the crate including the bindings.
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default().build_nvrtc().unwrap();
+    /// bindings.write("src/kernels.rs").unwrap();
+    /// ```
+    /// ```ignore
+    /// let ctx = cudarc::driver::CudaContext::new(0)?;
+    /// let module = ctx.load_module(kernels::GEMM.compile(&ctx)?)?;
+    /// ```
+    pub fn build_nvrtc(self) -> Result<Bindings, Error> {
+        self.build_kernels(Output::Nvrtc)
+    }
+
+    /// Writes the sources of [`Builder::build_nvrtc`], with their headers inlined.
+    fn write_nvrtc_sources(&self, kernels: &[(PathBuf, Instance<'_>)]) -> Manifest {
+        self.flush_writes();
+        let include_dirs = self.include_dirs();
+        let kernels = kernels
+            .iter()
+            .map(|(p, instance)| {
+                for header in includes::closure(p, &include_dirs) {
+                    rerun_if_changed(&header);
+                }
+                let source = includes::inline(&self.source(p), &self.source_include_dirs(p))
+                    .unwrap_or_else(|e| panic!("read {}: {e}", p.display()));
+                let output = self.kernel_output(p, *instance, Output::Nvrtc);
+                std::fs::create_dir_all(self.output_dir(p))
+                    .expect("create kernel output directory");
+                if std::fs::read_to_string(&output).ok().as_ref() != Some(&source) {
+                    std::fs::write(&output, &source)
+                        .unwrap_or_else(|e| panic!("write {}: {e}", output.display()));
+                }
+                ManifestEntry {
+                    name: self.kernel_name(p, *instance),
+                    source: p.clone(),
+                    output,
+                    compute_caps: vec![],
+                    flags: instance.args().collect(),
+                    content_hash: fnv1a(source.as_bytes()),
+                    intermediates: vec![],
+                }
+            })
+            .collect();
+        Manifest {
+            nvcc_version: None,
+            kernels,
+        }
+    }
+
+    /// Returns the nvcc commands [`Builder::build_optix_ir`] would run, without running them.
+    /// ```no_run
+    /// let plan = bindgen_cuda::Builder::default().plan_optix_ir();
+    /// println!("{plan}");
+    /// ```
+    pub fn plan_optix_ir(&self) -> Plan {
+        let (jobs, up_to_date) = self.kernel_jobs(Output::OptixIr);
+        Plan::new(&jobs, up_to_date)
+    }
+
+    fn build_kernels(self, kind: Output) -> Result<Bindings, Error> {
+        match kind {
+            Output::Ptx => {}
+            Output::DeviceLib(_) => self.check_entry_points("build_device_lib"),
+            Output::OptixIr => self.check_entry_points("build_optix_ir"),
+            Output::Nvrtc => self.check_entry_points("build_nvrtc"),
+        }
+        let fingerprint = self.fingerprint(kind.extension());
+        // NVRTC kernels are compiled at runtime, they don't need the toolkit.
+        let nvrtc = kind == Output::Nvrtc;
+        let stub = !nvrtc && self.is_stub();
+        if !stub && !nvrtc {
+            self.check_requirements()?;
+        }
+        println!("cargo:rustc-check-cfg=cfg(cuda_stub)");
+        if !stub && !nvrtc {
+            println!(
+                "cargo:rustc-env=CUDA_INCLUDE_DIR={}",
+                self.cuda_include_dir().display()
+            );
+        }
+        for path in &self.include_paths {
+            rerun_if_changed(path);
+            if path.is_dir() {
+                continue;
+            }
+            let destination = self
+                .out_dir
+                .join(path.file_name().expect("include path to have filename"));
+            self.staging
+                .stage(path, &destination)
+                .expect("stage include headers");
+        }
+        for var in BUILD_VARS {
+            println!("cargo:rerun-if-env-changed={var}");
+        }
+        for path in self.watch.iter().chain(&self.kernel_paths) {
+            rerun_if_changed(path);
+        }
+
+        let kernels = self.kernels(kind);
+        check_collisions(kernels.iter().map(|(p, instance)| {
+            let output = self.kernel_output(p, *instance, kind);
+            (output.display().to_string(), p.clone())
+        }))?;
+        let manifest = if stub {
+            println!("cargo:warning=CUDA toolkit not found, kernels are replaced by empty stubs");
+            println!("cargo:rustc-cfg=cuda_stub");
+            Manifest::default()
+        } else if nvrtc {
+            self.write_nvrtc_sources(&kernels)
+        } else {
+            let (jobs, _) = self.kernel_jobs(kind);
+            let compiled_jobs = jobs.len();
+            let (jobs, compiled) = self.restore_cached(jobs);
+            self.run_jobs(jobs)?;
+            self.store_cached(&compiled);
+            self.report_intermediates(kind.extension());
+            self.run_post_process(kind);
+            self.write_timings();
+            self.emit_compute_cap(kind.extension());
+            self.store_fingerprint(kind.extension(), &fingerprint);
+            self.rerun_if_dependencies_changed(
+                kernels
+                    .iter()
+                    .map(|(p, instance)| self.raw_output(p, *instance, kind)),
+            );
+            let manifest_path = self
+                .build_dir()
+                .join(format!("{}-manifest.json", kind.extension()));
+            // Up to date kernels keep the manifest of the build which compiled them,
+            // recomputing it would detect the compute cap and hash every output.
+            let cached = std::fs::read_to_string(&manifest_path)
+                .ok()
+                .and_then(|json| Manifest::from_json(&json))
+                .filter(|manifest| compiled_jobs == 0 && manifest.kernels.len() == kernels.len());
+            match cached {
+                Some(manifest) => manifest,
+                None => {
+                    let manifest = self.manifest(kind, compiled_jobs);
+                    std::fs::write(manifest_path, manifest.to_json())
+                        .expect("write build manifest");
+                    manifest
+                }
+            }
+        };
+
+        let entry_points = match kind {
+            Output::Ptx if !stub => kernels
+                .iter()
+                .map(|(p, instance)| {
+                    let output = self.kernel_output(p, *instance, kind);
+                    let ptx = std::fs::read_to_string(&output)
+                        .unwrap_or_else(|e| panic!("read {}: {e}", output.display()));
+                    ptx_entry_points(&ptx)
+                })
+                .collect(),
+            _ => vec![vec![]; kernels.len()],
+        };
+        if kind == Output::Ptx && !stub {
+            for ((p, instance), found) in kernels.iter().zip(&entry_points) {
+                let expected = instance.config.map(|c| &c.entry_points[..]).unwrap_or(&[]);
+                let missing: Vec<_> = expected
+                    .iter()
+                    .filter(|e| !found.contains(e))
+                    .cloned()
+                    .collect();
+                if !missing.is_empty() {
+                    return Err(Error::MissingEntryPoints {
+                        kernel: self.kernel_name(p, *instance),
+                        missing,
+                    });
+                }
+            }
+        }
+
+        let outputs = kernels
+            .iter()
+            .map(|(p, instance)| {
+                let output = self.kernel_output(p, *instance, kind);
+                let output = output.strip_prefix(&self.out_dir).unwrap_or(&output);
+                output
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .collect();
+        let names = kernels
+            .iter()
+            .map(|(p, instance)| self.kernel_name(p, *instance))
+            .collect();
+        let nvrtc_options = match kind {
+            Output::Nvrtc => kernels
+                .iter()
+                .map(|(_, instance)| instance.args().collect())
+                .collect(),
+            _ => vec![],
+        };
+        let compute_caps = kernels
+            .iter()
+            .map(|(_, instance)| instance.compute_cap)
+            .collect();
+        let kernel_paths: Vec<_> = kernels.into_iter().map(|(p, _)| p).collect();
+        let device_functions = match kind {
+            Output::Ptx | Output::OptixIr | Output::Nvrtc => vec![],
+            // A unity build exports the functions of every kernel.
+            Output::DeviceLib(_) if self.unity_build.is_some() => {
+                let functions = self
+                    .cuda_sources()
+                    .iter()
+                    .flat_map(|p| {
+                        let source = std::fs::read_to_string(self.source(p))
+                            .expect("kernel source to be readable");
+                        exported_device_functions(&source)
+                    })
+                    .collect::<Vec<_>>();
+                vec![functions; kernel_paths.len()]
+            }
+            Output::DeviceLib(_) => kernel_paths
+                .iter()
+                .map(|p| {
+                    let source = std::fs::read_to_string(self.source(p))
+                        .expect("kernel source to be readable");
+                    exported_device_functions(&source)
+                })
+                .collect(),
+        };
+        Ok(Bindings {
+            paths: kernel_paths,
+            names,
+            outputs,
+            kind,
+            device_functions,
+            entry_points,
+            nvrtc_options,
+            compute_caps,
+            kernel_enum: false,
+            smoke_tests: false,
+            modules: false,
+            naming: self.naming,
+            manifest,
+            stub,
+            out_dir: self.out_dir,
+            compression: None,
+            doc_comments: false,
+            allow_lints: false,
+            rustfmt: false,
+        })
+    }
+
+    /// The compute cap kernels are compiled for, read from `CUDA_COMPUTE_CAP` or
+    /// detected with `nvidia-smi`, and validated against the targets `nvcc` supports.
+    /// Detection only happens on first use, since it spawns both `nvidia-smi` and `nvcc`.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default();
+    /// match builder.compute_cap() {
+    ///     Ok(compute_cap) => println!("compiling for sm_{compute_cap}"),
+    ///     Err(err) => println!("cargo:warning={err}"),
+    /// }
+    /// ```
+    /// `CUDA_COMPUTE_CAP` can be written `89`, `8.9`, `sm_89` or `compute_89`. It can also
+    /// list several compute caps (`80,89,90`), which are all built for like with
+    /// [`Builder::compute_cap_spec`], the oldest one being returned here.
+    /// ```
+    /// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+    /// use bindgen_cuda::{fixtures, Builder, Error};
+    /// for value in ["8.9", "sm_89", "compute_89", "89"] {
+    ///     let builder = Builder::default().probe(fixtures::healthy().var("CUDA_COMPUTE_CAP", value));
+    ///     assert_eq!(builder.compute_cap().unwrap(), 89);
+    /// }
+    /// let builder = Builder::default().probe(fixtures::healthy().var("CUDA_COMPUTE_CAP", "9.0,sm_80"));
+    /// assert_eq!(builder.compute_cap().unwrap(), 80);
+    /// let builder = Builder::default().probe(fixtures::healthy().var("CUDA_COMPUTE_CAP", "ampere"));
+    /// assert!(matches!(builder.compute_cap(), Err(Error::InvalidComputeCap(_))));
+    /// ```
+    pub fn compute_cap(&self) -> Result<usize, Error> {
+        self.compute_cap
+            .get_or_init(|| compute_cap(&self.recorder()))
+            .clone()
+    }
+
+    /// The `(major, minor)` version of the CUDA toolkit, parsed from `nvcc --version`.
+    /// ```
+    /// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+    /// let builder = bindgen_cuda::Builder::default().probe(bindgen_cuda::fixtures::healthy());
+    /// assert_eq!(builder.cuda_version().unwrap(), (12, 4));
+    /// ```
+    pub fn cuda_version(&self) -> Result<(usize, usize), Error> {
+        self.cuda_version
+            .get_or_init(|| cuda_version(&self.recorder()))
+            .clone()
+    }
+
+    /// The host compiler nvcc uses: `NVCC_CCBIN` when set, otherwise the compiler of
+    /// [`Builder::host_build`], otherwise for MSVC targets the `cl.exe` of the latest
+    /// Visual Studio found by `vswhere` which this nvcc supports. `None` leaves the
+    /// choice to nvcc, which looks in the `PATH`.
+    /// ```
+    /// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+    /// use bindgen_cuda::{fixtures, Builder};
+    /// let builder = Builder::default().probe(fixtures::windows());
+    /// assert!(builder.host_compiler().unwrap().ends_with("bin/Hostx64/x64/cl.exe"));
+    ///
+    /// // Visual Studio 17.10 needs CUDA 12.4, this is CUDA 12.2
+    /// let vs = "C:/Program Files/Microsoft Visual Studio/2022/Community";
+    /// let builder = Builder::default().probe(fixtures::windows().msvc(vs, "14.40.33807"));
+    /// assert_eq!(builder.host_compiler(), None);
+    ///
+    /// // Only MSVC targets look for Visual Studio
+    /// let builder = Builder::default().probe(fixtures::healthy());
+    /// assert_eq!(builder.host_compiler(), None);
+    /// ```
+    pub fn host_compiler(&self) -> Option<PathBuf> {
+        match (self.recorder().var("NVCC_CCBIN"), &self.host_tool) {
+            (Some(ccbin), _) => Some(PathBuf::from(ccbin)),
+            (None, Some(tool)) => Some(tool.path.clone()),
+            (None, None) => self.msvc(),
+        }
+    }
+
+    fn msvc(&self) -> Option<PathBuf> {
+        self.msvc
+            .get_or_init(|| {
+                let recorder = self.recorder();
+                if recorder.var("CARGO_CFG_TARGET_ENV").as_deref() != Some("msvc") {
+                    return None;
+                }
+                let (cl, toolset) = find_msvc(&recorder)?;
+                let cuda_version = self.cuda_version().ok()?;
+                if !msvc_supported(cuda_version, toolset) {
+                    println!(
+                        "cargo:warning=MSVC {}.{} at {} is not supported by CUDA {}.{}, set NVCC_CCBIN to pick another cl.exe",
+                        toolset.0,
+                        toolset.1,
+                        cl.display(),
+                        cuda_version.0,
+                        cuda_version.1
+                    );
+                    return None;
+                }
+                Some(cl)
+            })
+            .clone()
+    }
+
+    /// Replaces how the CUDA installation is detected, mostly useful to test a build
+    /// script against the canned environments of [`fixtures`]. The toolkit the
+    /// `ci-check` feature pretends to find is only assumed without a probe.
+    /// ```
+    /// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+    /// let builder = bindgen_cuda::Builder::default()
+    ///     .probe(bindgen_cuda::fixtures::jetson_orin().var("CUDA_COMPUTE_CAP", "87"));
+    /// assert_eq!(builder.compute_cap().unwrap(), 87);
+    /// ```
+    pub fn probe<P: Probe + 'static>(mut self, probe: P) -> Self {
+        let probe: Arc<dyn Probe> = Arc::new(probe);
+        self.cuda_root = cuda_include_dir(&Recorder {
+            probe: &*probe,
+            vars: &self.consulted_vars,
+        });
+        self.compute_cap = OnceLock::new();
+        self.cuda_version = OnceLock::new();
+        self.msvc = OnceLock::new();
+        self.probe = probe;
+        self
+    }
+
+    fn recorder(&self) -> Recorder<'_> {
+        Recorder {
+            probe: &*self.probe,
+            vars: &self.consulted_vars,
+        }
+    }
+
+    /// Prints `cargo:rustc-cfg` flags describing the CUDA environment, so the crate can
+    /// conditionally compile host code depending on it:
+    /// - `cuda_arch_XX` for the compute cap kernels are compiled for, the oldest
+    ///   one for fat builds,
+    /// - `cuda_arch_ge_XX` for every known compute cap up to that one,
+    /// - `cuda_XX` for the major version of the toolkit.
+    ///
+    /// The matching `cargo:rustc-check-cfg` lines are printed as well.
+    /// ```no_run
+    /// bindgen_cuda::Builder::default().emit_cfgs().unwrap();
+    /// // In the crate: #[cfg(cuda_arch_ge_80)] fn bf16_path() {}
+    /// ```
+    pub fn emit_cfgs(&self) -> Result<(), Error> {
+        let compute_cap = match self.profile() {
+            KernelProfile::Thin => self.compute_cap()?,
+            KernelProfile::Fat => self.compute_caps()[0],
+        };
+        let (major, _) = self.cuda_version()?;
+        let mut known: BTreeSet<usize> = KNOWN_COMPUTE_CAPS.iter().copied().collect();
+        known.insert(compute_cap);
+        for cap in &known {
+            println!("cargo:rustc-check-cfg=cfg(cuda_arch_{cap})");
+            println!("cargo:rustc-check-cfg=cfg(cuda_arch_ge_{cap})");
+        }
+        let majors: BTreeSet<usize> = KNOWN_CUDA_MAJORS.iter().copied().chain([major]).collect();
+        for version in majors {
+            println!("cargo:rustc-check-cfg=cfg(cuda_{version})");
+        }
+        println!("cargo:rustc-cfg=cuda_arch_{compute_cap}");
+        for cap in known.range(..=compute_cap) {
+            println!("cargo:rustc-cfg=cuda_arch_ge_{cap}");
+        }
+        println!("cargo:rustc-cfg=cuda_{major}");
+        Ok(())
+    }
+
+    /// The directories holding the CUDA libraries host code links against: the `cudart`
+    /// of the toolkit, and under WSL2 the `libcuda` the Windows driver exposes in
+    /// `/usr/lib/wsl/lib`.
+    /// ```
+    /// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+    /// let builder = bindgen_cuda::Builder::default().probe(bindgen_cuda::fixtures::wsl2());
+    /// assert_eq!(
+    ///     builder.cuda_lib_dirs(),
+    ///     ["/usr/local/cuda/lib64", "/usr/lib/wsl/lib"].map(std::path::PathBuf::from)
+    /// );
+    /// ```
+    pub fn cuda_lib_dirs(&self) -> Vec<PathBuf> {
+        let recorder = self.recorder();
+        let mut dirs = vec![];
+        if let Some(root) = &self.cuda_root {
+            let toolkit = ["lib64", "lib", "lib/x64", "targets/x86_64-linux/lib"]
+                .iter()
+                .map(|dir| root.join(dir))
+                .find(|dir| {
+                    ["libcudart.so", "libcudart_static.a", "cudart.lib"]
+                        .iter()
+                        .any(|lib| recorder.is_file(&dir.join(lib)))
+                });
+            dirs.extend(toolkit);
+        }
+        if is_wsl(&recorder) {
+            dirs.push(PathBuf::from(WSL_LIB_DIR));
+        }
+        dirs
+    }
+
+    /// Prints a `cargo:rustc-link-search` directive for each of [`Builder::cuda_lib_dirs`],
+    /// so host code can link against `cudart` or `cuda` with `cargo:rustc-link-lib`.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default();
+    /// builder.emit_link_search();
+    /// println!("cargo:rustc-link-lib=cudart");
+    /// ```
+    pub fn emit_link_search(&self) {
+        for dir in self.cuda_lib_dirs() {
+            println!("cargo:rustc-link-search=native={}", dir.display());
+        }
+    }
+
+    /// Prints `cargo:rerun-if-changed` and `cargo:rerun-if-env-changed` for every input
+    /// the builder consults: kernels, the headers they transitively include, watched paths,
+    /// and the environment variables used to detect CUDA.
+    /// Build scripts can call this instead of maintaining their own list.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default();
+    /// builder.emit_rerun_hints();
+    /// let bindings = builder.build_ptx().unwrap();
+    /// ```
+    pub fn emit_rerun_hints(&self) {
+        let include_dirs = self.include_dirs();
+        let mut paths: BTreeSet<PathBuf> = self
+            .kernel_paths
+            .iter()
+            .chain(&self.include_paths)
+            .chain(&self.watch)
+            .map(|path| includes::normalize(path))
+            .collect();
+        for kernel in &self.kernel_paths {
+            paths.extend(includes::closure(kernel, &include_dirs));
+        }
+        for path in &paths {
+            rerun_if_changed(path);
+        }
+        let mut vars = self
+            .consulted_vars
+            .lock()
+            .expect("consulted vars lock")
+            .clone();
+        // Consulted lazily, when compiling
+        vars.extend(BUILD_VARS.iter().map(|v| v.to_string()));
+        for var in vars {
+            println!("cargo:rerun-if-env-changed={var}");
+        }
+    }
+
+    /// Analyses the include closures of the kernels to find the include directories
+    /// no kernel needs, and the headers no kernel includes. Stale entries slow down
+    /// compilation and can shadow the intended version of a header.
+    /// ```no_run
+    /// let builder = bindgen_cuda::Builder::default();
+    /// builder.header_report().emit_warnings();
+    /// ```
+    /// Headers are matched whichever directory they are included through:
+    /// ```
+    /// let dir = std::env::temp_dir().join("bindgen_cuda_header_report");
+    /// std::fs::create_dir_all(dir.join("kernels")).unwrap();
+    /// std::fs::create_dir_all(dir.join("include")).unwrap();
+    /// std::fs::write(dir.join("include/common.cuh"), "").unwrap();
+    /// std::fs::write(dir.join("kernels/gemm.cu"), "#include \"../include/common.cuh\"\n").unwrap();
+    /// # std::env::set_var("OUT_DIR", &dir);
+    /// let report = bindgen_cuda::Builder::default()
+    ///     .kernel_paths(vec![dir.join("kernels/gemm.cu")])
+    ///     .include_paths(vec![dir.join("include/common.cuh")])
+    ///     .header_report();
+    /// assert!(report.unreferenced_headers().is_empty());
+    /// ```
+    pub fn header_report(&self) -> HeaderReport {
+        let include_dirs = self.include_dirs();
+        let mut included = BTreeSet::new();
+        let mut used_dirs = BTreeSet::new();
+        for kernel in &self.kernel_paths {
+            included.extend(includes::closure(kernel, &include_dirs));
+            used_dirs.extend(includes::used_dirs(kernel, &include_dirs));
+        }
+        HeaderReport {
+            unused_include_dirs: include_dirs
+                .into_iter()
+                .filter(|dir| !used_dirs.contains(dir))
+                .collect(),
+            unreferenced_headers: self
+                .include_files()
+                .into_iter()
+                .filter(|header| !included.contains(&includes::normalize(header)))
+                .collect(),
+        }
+    }
+
+    /// Reports everything detected about the CUDA environment, to paste in bug reports
+    /// when a build fails because of the machine rather than the kernels.
+    /// ```
+    /// # std::env::set_var("OUT_DIR", std::env::temp_dir());
+    /// let builder = bindgen_cuda::Builder::default().probe(bindgen_cuda::fixtures::healthy());
+    /// let diagnosis = builder.diagnose();
+    /// assert_eq!(diagnosis.compute_cap().unwrap(), 89);
+    /// assert_eq!(diagnosis.nvcc_version(), Some("12.4"));
+    /// println!("{diagnosis}");
+    /// ```
+    pub fn diagnose(&self) -> Diagnosis {
+        let recorder = self.recorder();
+        let cuda_roots = cuda_root_candidates(&recorder)
+            .into_iter()
+            .map(|root| {
+                let found = recorder.is_file(&root.join("include").join("cuda.h"));
+                (root, found)
+            })
+            .collect();
+        let smi = |query: &str| {
+            let output = nvidia_smi(&recorder, &[query, "--format=csv"])?;
+            Some(output.lines().nth(1)?.trim().to_string())
+        };
+        let nvidia_smi = smi("--query-gpu=compute_cap")
+            .zip(driver_version(&recorder))
+            .map(|(compute_cap, driver)| format!("compute cap {compute_cap}, driver {driver}"));
+        let nvcc = find_nvcc(&recorder);
+        let compute_cap = self.compute_cap();
+        let host_compiler = self.host_compiler();
+        let mut vars = self
+            .consulted_vars
+            .lock()
+            .expect("consulted vars lock")
+            .clone();
+        vars.extend(BUILD_VARS.iter().map(|v| v.to_string()));
+        vars.extend(WRAPPER_VARS.iter().map(|v| v.to_string()));
+        vars.insert("BINDGEN_CUDA_CACHE".to_string());
+        Diagnosis {
+            cuda_root: self.cuda_root.clone(),
+            cuda_roots,
+            nvcc,
+            nvcc_version: nvcc_version(&recorder),
+            nvidia_smi,
+            compute_cap,
+            supported_compute_caps: supported_compute_caps(&recorder),
+            host_compiler,
+            vars: vars
+                .into_iter()
+                .map(|var| {
+                    let value = self.probe.var(&var);
+                    (var, value)
+                })
+                .collect(),
+        }
+    }
+
+    /// Computes the fingerprint of the build named `name` (`ptx`, a library...).
+    fn fingerprint(&self, name: &str) -> Fingerprint {
+        let mut config = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut config);
+        name.hash(&mut config);
+        self.cuda_root.hash(&mut config);
+        // Only cheap inputs are hashed, nothing is run before knowing whether the build
+        // is up to date. A toolkit upgraded in place keeps its paths, its version file
+        // tells it apart without running nvcc.
+        let nvcc = find_nvcc(&self.recorder());
+        nvcc.hash(&mut config);
+        nvcc.and_then(|nvcc| toolkit_version(&self.recorder(), &nvcc))
+            .hash(&mut config);
+        self.out_dir.hash(&mut config);
+        self.kernel_paths.hash(&mut config);
+        self.include_paths.hash(&mut config);
+        self.watch.hash(&mut config);
+        self.extra_args.hash(&mut config);
+        self.cpp_args.hash(&mut config);
+        self.cpp_std.hash(&mut config);
+        self.msvc_runtime.hash(&mut config);
+        self.relaxed_constexpr.hash(&mut config);
+        self.extended_lambda.hash(&mut config);
+        self.versioned_args.hash(&mut config);
+        self.fat_compute_caps.hash(&mut config);
+        self.compute_cap_spec.hash(&mut config);
+        self.profile().hash(&mut config);
+        self.virtual_arch.hash(&mut config);
+        self.gpu_code.hash(&mut config);
+        self.naming.hash(&mut config);
+        self.variants.hash(&mut config);
+        self.kernel_configs.hash(&mut config);
+        self.unity_build.hash(&mut config);
+        self.deprecated_archs.hash(&mut config);
+        self.is_reproducible().hash(&mut config);
+        self.device_lto.hash(&mut config);
+        self.archiver.hash(&mut config);
+        self.thin_archive.hash(&mut config);
+        self.append_archive.hash(&mut config);
+        self.keep_intermediates.hash(&mut config);
+        self.provenance.hash(&mut config);
+        self.dependency_files.hash(&mut config);
+        self.ptx_per_compute_cap.hash(&mut config);
+        self.split_compile.hash(&mut config);
+        // The flags of `cargo_profile` follow from these and the arguments hashed above,
+        // the requirements of `arg_if_version` are matched against the version file.
+        self.cargo_profile.hash(&mut config);
+        if self.cargo_profile {
+            for var in ["PROFILE", "OPT_LEVEL", "DEBUG"] {
+                self.recorder().var(var).hash(&mut config);
+            }
+        }
+        // The host compiler is `NVCC_CCBIN`, hashed below, or the tool of `host_build`.
+        // The `cl.exe` otherwise found by `vswhere` is left out, looking for it runs
+        // `vswhere` and nvcc.
+        self.host_tool.hash(&mut config);
+        for hook in &self.command_hooks {
+            let mut reference = Command::new("nvcc");
+            reference.arg("--reference");
+            (hook.0)(&mut reference);
+            reference.get_program().hash(&mut config);
+            reference.get_args().for_each(|a| a.hash(&mut config));
+            reference.get_envs().for_each(|e| e.hash(&mut config));
+            reference.get_current_dir().hash(&mut config);
+        }
+        for var in BUILD_VARS {
+            self.recorder().var(var).hash(&mut config);
+        }
+
+        let mut inputs = DefaultHasher::new();
+        let sources: Vec<_> = self.kernel_paths.iter().map(|p| self.source(p)).collect();
+        for path in self
+            .kernel_paths
+            .iter()
+            .chain(&self.include_files())
+            .chain(&self.watch)
+        {
+            let metadata = path.metadata().ok();
+            metadata.as_ref().map(|m| m.len()).hash(&mut inputs);
+            metadata.and_then(|m| m.modified().ok()).hash(&mut inputs);
+        }
+        // Rewritten sources are hashed by content, they may not be written yet.
+        for (path, source) in self.kernel_paths.iter().zip(&sources) {
+            if source != path {
+                self.read(source).ok().hash(&mut inputs);
+            }
+        }
+        Fingerprint {
+            config: config.finish(),
+            inputs: inputs.finish(),
+        }
+    }
+
+    fn fingerprint_path(&self, name: &str) -> PathBuf {
+        self.build_dir()
+            .join(format!("bindgen_cuda-{name}.fingerprint"))
+    }
+
+    /// The fingerprint of the last successful build named `name`, with the compute cap
+    /// it detected if it needed to.
+    fn cached_fingerprint(&self, name: &str) -> Option<(Fingerprint, Option<usize>)> {
+        let content = std::fs::read_to_string(self.fingerprint_path(name)).ok()?;
+        let mut values = content.split_whitespace();
+        let config = u64::from_str_radix(values.next()?, 16).ok()?;
+        let inputs = u64::from_str_radix(values.next()?, 16).ok()?;
+        let compute_cap = values.next()?.parse().ok();
+        Some((Fingerprint { config, inputs }, compute_cap))
+    }
+
+    /// The compute caps of [`Builder::ptx_per_compute_cap`] the last build named `name`
+    /// compiled for.
+    fn cached_ptx_compute_caps(&self, name: &str) -> Option<Vec<usize>> {
+        let content = std::fs::read_to_string(self.fingerprint_path(name)).ok()?;
+        let compute_caps = content.split_whitespace().nth(3)?;
+        compute_caps.split(',').map(|c| c.parse().ok()).collect()
+    }
+
+    /// Whether nothing changed since the last build named `name`.
+    fn is_cached(&self, name: &str, fingerprint: &Fingerprint) -> bool {
+        self.cached_fingerprint(name)
+            .is_some_and(|(cached, _)| cached == *fingerprint)
+    }
+
+    /// Makes sure `CUDA_COMPUTE_CAP` is exported to the crate being built, reusing the
+    /// compute cap of the previous build when nothing needed to be compiled.
+    fn emit_compute_cap(&self, name: &str) {
+        if self.compute_cap.get().is_some() {
+            // The detection already exported it.
+            return;
+        }
+        match self.cached_fingerprint(name) {
+            Some((_, Some(compute_cap))) => {
+                println!("cargo:rerun-if-env-changed=CUDA_COMPUTE_CAP");
+                println!("cargo:rustc-env=CUDA_COMPUTE_CAP={compute_cap}");
+                let _ = self.compute_cap.set(Ok(compute_cap));
+            }
+            // Fat builds don't need to know the local GPU, don't fail them over it.
+            _ => {
+                let _ = self.compute_cap();
+            }
+        }
+    }
+
+    fn store_fingerprint(&self, name: &str, fingerprint: &Fingerprint) {
+        let compute_cap = match self.compute_cap.get() {
+            Some(Ok(compute_cap)) => compute_cap.to_string(),
+            _ => "-".to_string(),
+        };
+        let ptx_compute_caps = match self.ptx_compute_caps.get() {
+            Some(compute_caps) if !compute_caps.is_empty() => compute_caps
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+            _ => "-".to_string(),
+        };
+        let content = format!(
+            "{:x} {:x} {compute_cap} {ptx_compute_caps}\n",
+            fingerprint.config, fingerprint.inputs
+        );
+        let path = self.fingerprint_path(name);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).expect("create build directory");
+        }
+        std::fs::write(path, content).expect("write build fingerprint");
+    }
+
+    fn cuda_include_dir(&self) -> PathBuf {
+        let cuda_root = self.cuda_root.as_ref().expect("Could not find CUDA in standard locations, set it manually using Builder().set_cuda_root(...)");
+        cuda_root.join("include")
+    }
+
+    /// The include files, with include directories expanded to the files they contain.
+    fn include_files(&self) -> Vec<PathBuf> {
+        let mut files = vec![];
+        for path in &self.include_paths {
+            if path.is_dir() {
+                files_in(path, &mut files);
+            } else {
+                files.push(path.clone());
+            }
+        }
+        files
+    }
+
+    /// The include directories, deduced from the include files.
+    fn include_dirs(&self) -> Vec<PathBuf> {
+        let mut include_paths: Vec<PathBuf> = self
+            .include_paths
+            .iter()
+            .map(|path| {
+                let mut path = path.clone();
+                if !path.is_dir() {
+                    // remove the filename from the path so it's just the directory
+                    path.pop();
+                }
+                path
+            })
+            .collect();
+        include_paths.sort();
+        include_paths.dedup();
+        include_paths
+    }
+
+    /// The `-I` options of the include directories, CUDA's included.
+    fn include_options(&self) -> Vec<OsString> {
+        let mut include_paths = self.include_dirs();
+        include_paths.push(self.cuda_include_dir());
+        include_paths
+            .into_iter()
+            .map(|s| {
+                let mut option = OsString::from("-I");
+                option.push(s);
+                option
+            })
+            .collect()
+    }
+
+    /// The arguments of [`Builder::arg_if_version`] matching the CUDA version.
+    fn versioned_args(&self) -> Vec<&String> {
+        if self.versioned_args.is_empty() {
+            return vec![];
+        }
+        let version = self.cuda_version().unwrap_or_else(|e| panic!("{e}"));
+        self.versioned_args
+            .iter()
+            .filter(|(requirement, _)| requirement.matches(version))
+            .map(|(_, arg)| arg)
+            .collect()
+    }
+
+    /// Adds the flags shared by every compilation to `command`, followed by those
+    /// of `instance` when compiling a kernel.
+    fn common_args(&self, command: &mut Command, instance: Option<Instance>) {
+        command.args(["--default-stream", "per-thread"]);
+        if let Some(std) = self.cpp_std {
+            command.arg(format!("--std={}", std.name()));
+        }
+        if self.relaxed_constexpr {
+            command.arg("--expt-relaxed-constexpr");
+        }
+        if self.extended_lambda {
+            command.arg("--expt-extended-lambda");
+        }
+        let msvc_runtime = self
+            .msvc_runtime
+            .filter(|_| self.recorder().var("CARGO_CFG_TARGET_ENV").as_deref() == Some("msvc"));
+        if let Some(runtime) = msvc_runtime {
+            command.arg(format!("-Xcompiler={}", runtime.flag()));
+        }
+        command.args(self.cargo_profile_args(instance));
+        command.args(&self.extra_args);
+        if let Some(flags) = self.recorder().var("CUDA_NVCC_FLAGS") {
+            command.args(split_flags(&flags));
+        }
+        command.args(self.versioned_args());
+        if self.deprecated_archs == DeprecatedArchs::Silence {
+            command.arg("-Wno-deprecated-gpu-targets");
+        }
+        if self.is_reproducible() && !cfg!(windows) {
+            // Keep the build directory out of host objects.
+            if let Ok(cwd) = std::env::current_dir() {
+                let mut prefix_map = OsString::from("-ffile-prefix-map=");
+                prefix_map.push(cwd);
+                prefix_map.push("=.");
+                command.arg("-Xcompiler").arg(prefix_map);
+            }
+        }
+        if let Some(ccbin_path) = self.recorder().var("NVCC_CCBIN") {
+            command
+                .arg("-allow-unsupported-compiler")
+                .args(["-ccbin", &ccbin_path]);
+        } else if let Some(tool) = &self.host_tool {
+            command.arg("-ccbin").arg(&tool.path);
+        } else if let Some(cl) = self.msvc() {
+            command.arg("-ccbin").arg(cl);
+        }
+        if let Some(tool) = &self.host_tool {
+            for arg in &tool.args {
+                let Some(arg) = arg.to_str() else {
+                    command.arg("-Xcompiler").arg(arg);
+                    continue;
+                };
+                // The runtime set with `msvc_runtime` replaces the one `cc` selected.
+                if msvc_runtime.is_some() && MsvcRuntime::is_flag(arg) {
+                    continue;
+                }
+                command.arg(forward_flag("-Xcompiler", arg));
+            }
+        }
+        if let Some(instance) = instance {
+            command.args(instance.args());
+        }
+    }
+
+    /// The profile being built: the one set with [`Builder::kernel_profile`], or
+    /// `BINDGEN_CUDA_PROFILE=thin|fat`, or fat for release builds and for crates
+    /// enabling a `fat-kernels` feature when fat compute caps are configured.
+    fn profile(&self) -> KernelProfile {
+        match self.compute_cap_spec {
+            Some(ComputeCapSpec::Native) => return KernelProfile::Thin,
+            Some(_) => return KernelProfile::Fat,
+            None if self.listed_compute_caps().is_some() => return KernelProfile::Fat,
+            None => {}
+        }
+        if self.fat_compute_caps.is_empty() {
+            return KernelProfile::Thin;
+        }
+        if let Some(profile) = self.profile {
+            return profile;
+        }
+        let recorder = self.recorder();
+        match recorder.var("BINDGEN_CUDA_PROFILE").as_deref() {
+            Some("thin") => KernelProfile::Thin,
+            Some("fat") => KernelProfile::Fat,
+            _ if recorder.var("CARGO_FEATURE_FAT_KERNELS").is_some() => KernelProfile::Fat,
+            _ if recorder.var("PROFILE").as_deref() == Some("release") => KernelProfile::Fat,
+            _ => KernelProfile::Thin,
+        }
+    }
+
+    /// The compute caps of the profile being built, sorted.
+    fn compute_caps(&self) -> Vec<usize> {
+        match self.profile() {
+            KernelProfile::Thin => vec![self.compute_cap().unwrap_or_else(|e| panic!("{e}"))],
+            KernelProfile::Fat
+                if self.compute_cap_spec.is_some() || self.listed_compute_caps().is_some() =>
+            {
+                self.spec_compute_caps
+                    .get_or_init(|| self.resolve_compute_cap_spec())
+                    .clone()
+                    .unwrap_or_else(|e| panic!("{e}"))
+            }
+            KernelProfile::Fat => {
+                let mut compute_caps = self.fat_compute_caps.clone();
+                compute_caps.sort();
+                compute_caps.dedup();
+                compute_caps
+            }
+        }
+    }
+
+    /// The compute caps of a `CUDA_COMPUTE_CAP` listing several, which are then all built
+    /// for like with [`Builder::compute_cap_spec`]. Parse errors are reported by
+    /// [`Builder::compute_cap`].
+    fn listed_compute_caps(&self) -> Option<Vec<usize>> {
+        let compute_caps = parse_compute_caps(&self.recorder().var("CUDA_COMPUTE_CAP")?)?;
+        (compute_caps.len() > 1).then_some(compute_caps)
+    }
+
+    /// The compute caps [`Builder::compute_cap_spec`] expands to with the installed nvcc.
+    fn resolve_compute_cap_spec(&self) -> Result<Vec<usize>, Error> {
+        let supported = supported_compute_caps(&self.recorder())?;
+        let mut compute_caps = match &self.compute_cap_spec {
+            // Detecting the compute cap validates the listed ones.
+            None => match self.listed_compute_caps() {
+                Some(compute_caps) => self.compute_cap().map(|_| compute_caps)?,
+                None => vec![self.compute_cap()?],
+            },
+            Some(ComputeCapSpec::Native) => vec![self.compute_cap()?],
+            Some(ComputeCapSpec::All) => supported,
+            Some(ComputeCapSpec::AllMajor) => {
+                supported.into_iter().filter(|c| c % 10 == 0).collect()
+            }
+            Some(ComputeCapSpec::List(compute_caps)) => {
+                if let Some(&compute_cap) = compute_caps.iter().find(|c| !supported.contains(c)) {
+                    return Err(Error::UnsupportedComputeCap {
+                        compute_cap,
+                        supported,
+                    });
+                }
+                compute_caps.clone()
+            }
+        };
+        compute_caps.sort();
+        compute_caps.dedup();
+        Ok(compute_caps)
+    }
+
+    /// The nvcc flags selecting the GPUs objects are compiled for. Fat builds embed
+    /// SASS for every compute cap, plus PTX of the newest one for future GPUs.
+    fn gencode_args(&self) -> Vec<String> {
+        let compute_caps = self.compute_caps();
+        match self.profile() {
+            KernelProfile::Thin => self.arch_args(compute_caps[0]),
+            KernelProfile::Fat => {
+                let newest = compute_caps[compute_caps.len() - 1];
+                compute_caps
+                    .iter()
+                    .map(|c| format!("--generate-code=arch=compute_{c},code=sm_{c}"))
+                    .chain([format!(
+                        "--generate-code=arch=compute_{newest},code=compute_{newest}"
+                    )])
+                    .collect()
+            }
+        }
+    }
+
+    /// `--gpu-architecture` (and `--gpu-code` if set) for a single compute cap.
+    fn arch_args(&self, compute_cap: usize) -> Vec<String> {
+        let arch = if self.virtual_arch {
+            format!("compute_{compute_cap}")
+        } else {
+            format!("sm_{compute_cap}")
+        };
+        let mut args = vec![format!("--gpu-architecture={arch}")];
+        if !self.gpu_code.is_empty() {
+            args.push(format!("--gpu-code={}", self.gpu_code.join(",")));
+        }
+        args
+    }
+
+    /// Where the outputs of the profile being built go. Each profile gets its own
+    /// directory when fat compute caps are configured, so switching between them
+    /// doesn't invalidate the other's cache.
+    fn build_dir(&self) -> PathBuf {
+        if self.fat_compute_caps.is_empty() {
+            return self.out_dir.clone();
+        }
+        self.out_dir.join(self.profile().name())
+    }
+
+    /// Writes `content` to `path` before the jobs run, unless it already holds it.
+    fn write_later(&self, path: &Path, content: Vec<u8>) {
+        if std::fs::read(path).is_ok_and(|current| current == content) {
+            return;
+        }
+        let mut pending = self.pending.lock().expect("pending writes lock");
+        pending.files.insert(path.to_path_buf(), content);
+    }
+
+    /// Creates the directory `path` before the jobs run.
+    fn create_dir_later(&self, path: PathBuf) {
+        let mut pending = self.pending.lock().expect("pending writes lock");
+        pending.dirs.insert(path);
+    }
+
+    /// The content of `path`, including the writes which are still pending.
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        let pending = self.pending.lock().expect("pending writes lock");
+        match pending.files.get(path) {
+            Some(content) => Ok(content.clone()),
+            None => std::fs::read(path),
+        }
+    }
+
+    /// When `path` was last modified, now for the pending writes which change it.
+    fn modified(&self, path: &Path) -> std::io::Result<SystemTime> {
+        let pending = self.pending.lock().expect("pending writes lock");
+        if pending.files.contains_key(path) {
+            return Ok(SystemTime::now());
+        }
+        path.metadata().and_then(|m| m.modified())
+    }
+
+    /// Performs the writes queued by [`Builder::write_later`] and [`Builder::create_dir_later`].
+    fn flush_writes(&self) {
+        let pending = std::mem::take(&mut *self.pending.lock().expect("pending writes lock"));
+        for dir in &pending.dirs {
+            std::fs::create_dir_all(dir)
+                .unwrap_or_else(|e| panic!("create {}: {e}", dir.display()));
+        }
+        for (path, content) in pending.files {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .unwrap_or_else(|e| panic!("create {}: {e}", parent.display()));
+            }
+            std::fs::write(&path, content)
+                .unwrap_or_else(|e| panic!("write {}: {e}", path.display()));
+        }
+    }
+
+    /// The kernels which are CUDA sources, host C++ sources are only used by [`Builder::build_lib`].
+    fn cuda_sources(&self) -> Vec<PathBuf> {
+        let mut sources: Vec<_> = self
+            .kernel_paths
+            .iter()
+            .filter(|p| Language::of(p) == Language::Cuda)
+            .cloned()
+            .collect();
+        if self.is_reproducible() {
+            sources.sort();
+        }
+        sources
+    }
+
+    /// Every kernel to compile to `kind`: each CUDA source, once per [`Builder::kernel`]
+    /// configuring it, once per variant if there are any, and for PTX once per compute
+    /// cap with [`Builder::ptx_per_compute_cap`].
+    fn kernels(&self, kind: Output) -> Vec<(PathBuf, Instance<'_>)> {
+        let sources = match &self.unity_build {
+            Some(name) => {
+                if !self.kernel_configs.is_empty() {
+                    panic!(
+                        "kernels configured with `Builder::kernel` cannot be part of a unity build"
+                    );
+                }
+                vec![self.unity_source(name)]
+            }
+            None => self.cuda_sources(),
+        };
+        let compute_caps = match kind {
+            Output::Ptx if self.ptx_per_compute_cap => {
+                self.ptx_compute_caps().iter().copied().map(Some).collect()
+            }
+            _ => vec![None],
+        };
+        sources
+            .into_iter()
+            .flat_map(|p| {
+                self.instances(&p, true)
+                    .into_iter()
+                    .map(move |instance| (p.clone(), instance))
+            })
+            .flat_map(|(p, instance)| {
+                compute_caps.iter().map(move |&compute_cap| {
+                    (
+                        p.clone(),
+                        Instance {
+                            compute_cap,
+                            ..instance
+                        },
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// The compute caps of [`Builder::ptx_per_compute_cap`]: those of the last build
+    /// when nothing changed since, so that up to date builds don't detect them.
+    fn ptx_compute_caps(&self) -> &[usize] {
+        self.ptx_compute_caps.get_or_init(|| {
+            let name = Output::Ptx.extension();
+            match self.cached_ptx_compute_caps(name) {
+                Some(compute_caps) if self.is_cached(name, &self.fingerprint(name)) => compute_caps,
+                _ => self.compute_caps(),
+            }
+        })
+    }
+
+    /// How the source at `path` is compiled: once per [`Builder::kernel`] configuring
+    /// it, times once per variant when `variants` apply to it.
+    fn instances(&self, path: &Path, variants: bool) -> Vec<Instance<'_>> {
+        let mut configs: Vec<_> = self
+            .kernel_configs
+            .iter()
+            .filter(|config| config.path == path)
+            .map(Some)
+            .collect();
+        if configs.is_empty() {
+            configs.push(None);
+        }
+        let variants: Vec<_> = if variants && !self.variants.is_empty() {
+            self.variants.iter().map(Some).collect()
+        } else {
+            vec![None]
+        };
+        configs
+            .into_iter()
+            .flat_map(|config| {
+                variants.iter().map(move |&variant| Instance {
+                    config,
+                    variant,
+                    compute_cap: None,
+                })
+            })
+            .collect()
+    }
+
+    /// The source including every kernel for [`Builder::unity_build`], written before
+    /// the jobs run. It is rewritten when any kernel changes, so that the module is rebuilt.
+    fn unity_source(&self, name: &str) -> PathBuf {
+        let sources = self.cuda_sources();
+        // Paths are written as is rather than through `display`, which would replace
+        // the bytes of non UTF-8 paths and break the includes.
+        let content: Vec<u8> = sources
+            .iter()
+            .flat_map(|p| {
+                let p = self.source(p);
+                let p = std::path::absolute(&p).unwrap_or(p);
+                // Forward slashes are understood on every platform, and need no escaping.
+                let path = p
+                    .as_os_str()
+                    .as_encoded_bytes()
+                    .iter()
+                    .map(|&b| if b == b'\\' { b'/' } else { b })
+                    .collect::<Vec<_>>();
+                [b"#include \"".as_slice(), &path, b"\"\n"].concat()
+            })
+            .collect();
+        let path = self.build_dir().join(format!("{name}.cu"));
+        let stale = match self.modified(&path) {
+            Ok(written) => sources
+                .iter()
+                .any(|p| self.modified(&self.source(p)).ok() > Some(written)),
+            Err(_) => true,
+        };
+        if stale {
+            let mut pending = self.pending.lock().expect("pending writes lock");
+            pending.files.insert(path.clone(), content);
+        } else {
+            self.write_later(&path, content);
+        }
+        path
+    }
+
+    /// The name of the kernel at `path` compiled as `instance`.
+    fn kernel_name(&self, path: &Path, instance: Instance<'_>) -> String {
+        let name = match instance.config.and_then(|config| config.name.as_ref()) {
+            Some(name) => name.clone(),
+            None => self.naming.kernel_name(path),
+        };
+        let name = match instance.variant {
+            Some(variant) => format!("{name}_{}", variant.suffix),
+            None => name,
+        };
+        match instance.compute_cap {
+            Some(compute_cap) => format!("{name}_sm{compute_cap}"),
+            None => name,
+        }
+    }
+
+    /// The `--threads` given to each of `jobs` nvcc processes.
+    fn threads_per_job(&self, jobs: usize) -> usize {
+        let nvcc_threads = match self.nvcc_threads {
+            Some(nvcc_threads) => nvcc_threads,
+            // The threads beyond one per architecture go to the split compilation.
+            None if jobs > 0 && !self.single_threaded && self.split_compile.is_some() => {
+                (self.num_threads / jobs).min(self.compute_caps().len())
+            }
+            None if jobs > 0 && !self.single_threaded => self.num_threads / jobs,
+            None => 1,
+        };
+        if nvcc_threads > 1 && self.cuda_version().is_ok_and(|v| v >= (11, 5)) {
+            nvcc_threads.min(self.num_threads)
+        } else {
+            1
+        }
+    }
+
+    /// The `--split-compile` given to each of `jobs` nvcc processes running with
+    /// `nvcc_threads`, see [`Builder::split_compile`].
+    fn split_per_job(&self, jobs: usize, nvcc_threads: usize) -> Option<usize> {
+        let split = match self.split_compile? {
+            SplitCompile::Threads(threads) => threads,
+            SplitCompile::Auto if jobs > 0 && !self.single_threaded => {
+                self.num_threads / jobs.min(self.num_threads) / nvcc_threads
+            }
+            SplitCompile::Auto => 1,
+        };
+        (split > 1 && self.cuda_version().is_ok_and(|v| v >= (12, 0))).then_some(split)
+    }
+
+    /// The `--threads` and `--split-compile` arguments compiling `input` as one of `jobs`
+    /// concurrent processes, and how many threads the process then uses.
+    fn parallel_args(&self, input: &Path, jobs: usize) -> (Vec<String>, usize) {
+        let nvcc_threads = self.threads_per_job(jobs);
+        let mut args = vec![];
+        if nvcc_threads > 1 {
+            args.extend(["--threads".to_string(), nvcc_threads.to_string()]);
+        }
+        let split = match Language::of(input) {
+            Language::Cuda => self.split_per_job(jobs, nvcc_threads),
+            Language::Cpp => None,
+        };
+        if let Some(split) = split {
+            args.push(format!("--split-compile={split}"));
+        }
+        (args, nvcc_threads * split.unwrap_or(1))
+    }
+
+    /// Adds the [`Builder::parallel_args`] of running `jobs` concurrently to their commands.
+    fn parallelize(&self, jobs: &mut [Job]) {
+        let count = jobs.len();
+        for job in jobs {
+            let (args, threads) = self.parallel_args(&job.input, count);
+            job.command.args(args);
+            job.threads = threads;
+        }
+    }
+
+    /// Whether kernels are replaced by stubs, see [`Builder::fallback_stub`].
+    fn is_stub(&self) -> bool {
+        self.cuda_root.is_none() && (self.fallback_stub || self.recorder().var("DOCS_RS").is_some())
+    }
+
+    /// Whether builds should be bit for bit reproducible, see [`Builder::reproducible`].
+    fn is_reproducible(&self) -> bool {
+        self.reproducible || self.recorder().var("SOURCE_DATE_EPOCH").is_some()
+    }
+
+    /// How `path` is passed to nvcc: relative to the current directory for
+    /// reproducible builds, so that it doesn't end up in debug info.
+    fn command_path(&self, path: &Path) -> PathBuf {
+        if self.is_reproducible() {
+            if let Ok(cwd) = std::env::current_dir() {
+                if let Ok(relative) = path.strip_prefix(&cwd) {
+                    return relative.to_path_buf();
+                }
+            }
+        }
+        path.to_path_buf()
+    }
+
+    fn kernel_output(&self, kernel_path: &Path, instance: Instance<'_>, kind: Output) -> PathBuf {
+        let compute_cap = instance.compute_cap.map(|c| format!(".sm{c}"));
+        let instance = Instance {
+            compute_cap: None,
+            ..instance
+        };
+        let name = self.kernel_name(kernel_path, instance);
+        self.output_dir(kernel_path).join(format!(
+            "{name}{}.{}",
+            compute_cap.unwrap_or_default(),
+            kind.extension()
+        ))
+    }
+
+    /// Where nvcc writes the kernel: [`Builder::kernel_output`], unless it is PTX
+    /// rewritten by [`Builder::post_process`] hooks.
+    fn raw_output(&self, kernel_path: &Path, instance: Instance<'_>, kind: Output) -> PathBuf {
+        let output = self.kernel_output(kernel_path, instance, kind);
+        match kind {
+            Output::Ptx if !self.post_process.is_empty() => output.with_extension("raw.ptx"),
+            _ => output,
+        }
+    }
+
+    /// Applies the [`Builder::post_process`] hooks to every kernel, rewriting only
+    /// the outputs which changed.
+    fn run_post_process(&self, kind: Output) {
+        if kind != Output::Ptx || self.post_process.is_empty() {
+            return;
+        }
+        for (path, instance) in self.kernels(kind) {
+            let raw = self.raw_output(&path, instance, kind);
+            let ptx = std::fs::read_to_string(&raw)
+                .unwrap_or_else(|e| panic!("read {}: {e}", raw.display()));
+            let name = self.kernel_name(&path, instance);
+            let ptx = self
+                .post_process
+                .iter()
+                .fold(ptx, |ptx, hook| (hook.0)(&name, ptx));
+            let output = self.kernel_output(&path, instance, kind);
+            if std::fs::read_to_string(&output).ok().as_ref() != Some(&ptx) {
+                std::fs::write(&output, ptx)
+                    .unwrap_or_else(|e| panic!("write {}: {e}", output.display()));
+            }
+        }
+    }
+
+    /// Where the outputs of `source` go: its directory, relative to the crate,
+    /// mirrored under the build directory so that same named sources don't collide.
+    fn output_dir(&self, source: &Path) -> PathBuf {
+        let dir = source.parent().unwrap_or(Path::new(""));
+        // Generated sources already live in the build directory.
+        if dir.starts_with(self.build_dir()) {
+            return dir.to_path_buf();
+        }
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let dir = dir.strip_prefix(&cwd).unwrap_or(dir);
+        let mut output_dir = self.build_dir();
+        for component in dir.components() {
+            match component {
+                std::path::Component::Normal(part) => output_dir.push(part),
+                std::path::Component::ParentDir => output_dir.push("__"),
+                // Absolute sources outside of the crate are mirrored from the root
+                _ => {}
+            }
+        }
+        output_dir
+    }
+
+    /// The jobs compiling each kernel that is not up to date, along with the up to date ones.
+    fn kernel_jobs(&self, kind: Output) -> (Vec<Job>, Vec<PathBuf>) {
+        let fingerprint = self.fingerprint(kind.extension());
+        let kernels = self.kernels(kind);
+        let outputs_fresh = kernels.iter().all(|(p, instance)| {
+            let output = self.raw_output(p, *instance, kind);
+            output
+                .metadata()
+                .and_then(|m| m.modified())
+                .is_ok_and(|built| !self.dependencies_changed(&output, built))
+        });
+        if outputs_fresh && self.is_cached(kind.extension(), &fingerprint) {
+            // Nothing changed since the last build, skip everything including
+            // the compute cap detection.
+            return (vec![], self.cuda_sources());
+        }
+        let config_changed = self
+            .cached_fingerprint(kind.extension())
+            .is_none_or(|(cached, _)| cached.config != fingerprint.config);
+        let include_options = self.include_options();
+        let cache = self.cache();
+        let nvcc_version = cache
+            .as_ref()
+            .and_then(|_| self.recorder().output("nvcc", &["--version"]));
+        let mut jobs = vec![];
+        let mut up_to_date = vec![];
+        for (p, instance) in &kernels {
+            let output_filename = self.raw_output(p, *instance, kind);
+
+            let ignore = if config_changed {
+                false
+            } else if let Ok(metadata) = output_filename.metadata() {
+                let out_modified = metadata.modified().expect("modified to be accessible");
+                let in_modified = self
+                    .modified(&self.source(p))
+                    .expect("input modified to be accessible");
+                out_modified.duration_since(in_modified).is_ok()
+                    && !self.dependencies_changed(&output_filename, out_modified)
+            } else {
+                false
+            };
+            if ignore {
+                if !up_to_date.contains(p) {
+                    up_to_date.push(p.clone());
+                }
+                continue;
+            }
+            if let Some(dir) =
+                self.intermediates_dir(kind.extension(), &self.kernel_name(p, *instance))
+            {
+                self.create_dir_later(dir);
+            }
+            let command = self.kernel_command(p, *instance, kind, &include_options);
+            let cache_key = cache.as_ref().map(|_| {
+                self.cache_key(
+                    p,
+                    *instance,
+                    &command,
+                    nvcc_version.as_deref(),
+                    kind,
+                    &include_options,
+                )
+            });
+            jobs.push(Job {
+                input: p.clone(),
+                response_file: (
+                    output_filename.with_extension("rsp"),
+                    ResponseFile::OptionsFile,
+                ),
+                output: output_filename,
+                action: "compiling",
+                command,
+                cache_key,
+                threads: 1,
+                duration: Default::default(),
+                timed_out: false,
+            });
+        }
+        self.parallelize(&mut jobs);
+        (jobs, up_to_date)
+    }
+
+    /// The headers `output` was compiled from, read from its [`Builder::dependency_files`].
+    fn dependencies(&self, output: &Path) -> Option<Vec<PathBuf>> {
+        if !self.dependency_files {
+            return None;
+        }
+        let content = std::fs::read_to_string(output.with_extension("d")).ok()?;
+        Some(includes::dependency_file(&content))
+    }
+
+    /// Whether one of the headers `output` was compiled from changed after `built`.
+    /// Outputs restored from the [`Builder::artifact_cache`] have no dependency file,
+    /// only their source is then compared.
+    fn dependencies_changed(&self, output: &Path, built: SystemTime) -> bool {
+        let dependencies = self.dependencies(output).unwrap_or_default();
+        dependencies.iter().any(|dependency| {
+            dependency
+                .metadata()
+                .and_then(|m| m.modified())
+                .map_or(true, |modified| modified > built)
+        })
+    }
+
+    /// Tells cargo to rerun the build script when one of the headers `outputs` were
+    /// compiled from changes, see [`Builder::dependency_files`].
+    fn rerun_if_dependencies_changed(&self, outputs: impl IntoIterator<Item = PathBuf>) {
+        let dependencies: BTreeSet<PathBuf> = outputs
+            .into_iter()
+            .filter_map(|output| self.dependencies(&output))
+            .flatten()
+            .collect();
+        for dependency in &dependencies {
+            rerun_if_changed(dependency);
+        }
+    }
+
+    /// Where nvcc keeps the intermediate files of the kernel or object `name` built as
+    /// part of `group`, when [`Builder::keep_intermediates`] is set.
+    fn intermediates_dir(&self, group: &str, name: &str) -> Option<PathBuf> {
+        self.keep_intermediates
+            .then(|| self.out_dir.join("intermediates").join(group).join(name))
+    }
+
+    /// Prints where the intermediate files of `group` are, one warning per kernel.
+    fn report_intermediates(&self, group: &str) {
+        let Some(dir) = self.intermediates_dir(group, "") else {
+            return;
+        };
+        let mut files = vec![];
+        files_in(&dir, &mut files);
+        let mut kernels = BTreeMap::<&Path, Vec<_>>::new();
+        for file in &files {
+            if let (Some(dir), Some(name)) = (file.parent(), file.file_name()) {
+                kernels.entry(dir).or_default().push(name.to_string_lossy());
+            }
+        }
+        for (dir, names) in kernels {
+            println!(
+                "cargo:warning=intermediates in {}: {}",
+                dir.display(),
+                names.join(" ")
+            );
+        }
+    }
+
+    /// The [`Builder::artifact_cache`], or the one named by `BINDGEN_CUDA_CACHE`.
+    fn cache(&self) -> Option<ArtifactCache> {
+        if self.keep_intermediates || self.provenance {
+            // Restored kernels would come without their intermediates or provenance.
+            return None;
+        }
+        self.artifact_cache.clone().or_else(|| {
+            let var = self.recorder().var("BINDGEN_CUDA_CACHE")?;
+            Some(ArtifactCache::from_var(&var)).filter(|_| !var.is_empty())
+        })
+    }
+
+    /// What the [`ArtifactCache`] key of compiling the kernel at `path` as `instance`
+    /// with `command` is made of. Paths under `OUT_DIR` and `CARGO_MANIFEST_DIR` are made
+    /// relative to them, as they differ between machines.
+    fn cache_key(
+        &self,
+        path: &Path,
+        instance: Instance<'_>,
+        command: &Command,
+        nvcc_version: Option<&str>,
+        kind: Output,
+        include_options: &[OsString],
+    ) -> CacheKey {
+        let out_dir = self.out_dir.to_string_lossy();
+        let manifest_dir = self.recorder().var("CARGO_MANIFEST_DIR");
+        let mut parts = vec![nvcc_version.unwrap_or_default().as_bytes().to_vec()];
+        // nvcc reads those itself.
+        for var in ["NVCC_PREPEND_FLAGS", "NVCC_APPEND_FLAGS"] {
+            parts.push(self.recorder().var(var).unwrap_or_default().into_bytes());
+        }
+        for arg in command.get_args() {
+            let mut arg = arg.to_string_lossy().replace(&*out_dir, "$OUT_DIR");
+            if let Some(manifest_dir) = manifest_dir.as_deref().filter(|dir| !dir.is_empty()) {
+                arg = arg.replace(manifest_dir, "$CARGO_MANIFEST_DIR");
+            }
+            parts.push(arg.into_bytes());
+        }
+        // nvcc knows which files the kernel reads, including headers behind macros.
+        let mut dependencies = Command::new("nvcc");
+        dependencies.arg("--generate-dependencies");
+        self.common_args(&mut dependencies, Some(instance));
+        dependencies
+            .args(self.source_include_option(path))
+            .args(include_options)
+            .arg(self.command_path(&self.source(path)));
+        CacheKey {
+            parts,
+            dependencies,
+            extension: kind.extension(),
+        }
+    }
+
+    /// The key `key` stands for, hashing the content of the files nvcc lists. `None`
+    /// when they can't be listed, the kernel is then compiled without the cache.
+    fn resolve_cache_key(&self, key: &mut CacheKey) -> Option<String> {
+        let output = key.dependencies.output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let mut parts = key.parts.clone();
+        for dependency in includes::dependency_file(&String::from_utf8_lossy(&output.stdout)) {
+            parts.push(std::fs::read(dependency).ok()?);
+        }
+        Some(cache::key(&parts, key.extension))
+    }
+
+    /// Restores the outputs of `jobs` found in the [`ArtifactCache`], returning the
+    /// jobs which still have to run along with the keys to store their outputs under.
+    fn restore_cached(&self, jobs: Vec<Job>) -> (Vec<Job>, Vec<(String, PathBuf)>) {
+        let Some(cache) = self.cache() else {
+            return (jobs, vec![]);
+        };
+        // Listing the dependencies reads the sources, rewritten ones included.
+        self.flush_writes();
+        let results: Vec<_> = thread_pool(self.num_threads).install(|| {
+            jobs.into_par_iter()
+                .map(|mut job| {
+                    let Some(key) = job
+                        .cache_key
+                        .as_mut()
+                        .and_then(|key| self.resolve_cache_key(key))
+                    else {
+                        return (Some(job), None);
+                    };
+                    match cache.get(&key) {
+                        Ok(Some(content)) => {
+                            if let Some(dir) = job.output.parent() {
+                                std::fs::create_dir_all(dir)
+                                    .expect("create kernel output directory");
+                            }
+                            std::fs::write(&job.output, content).expect("write cached kernel");
+                            (None, None)
+                        }
+                        Ok(None) => (Some(job), Some(key)),
+                        Err(e) => {
+                            println!(
+                                "cargo:warning=could not read {key} from the artifact cache: {e}"
+                            );
+                            (Some(job), Some(key))
+                        }
+                    }
+                })
+                .collect()
+        });
+        let mut remaining = vec![];
+        let mut compiled = vec![];
+        for (job, key) in results {
+            let Some(job) = job else {
+                continue;
+            };
+            if let Some(key) = key {
+                compiled.push((key, job.output.clone()));
+            }
+            remaining.push(job);
+        }
+        (remaining, compiled)
+    }
+
+    /// Stores the outputs of the `compiled` jobs, with their keys, in the [`ArtifactCache`].
+    fn store_cached(&self, compiled: &[(String, PathBuf)]) {
+        let Some(cache) = self.cache() else {
+            return;
+        };
+        thread_pool(self.num_threads).install(|| {
+            compiled.par_iter().for_each(|(key, output)| {
+                let stored = std::fs::read(output).and_then(|content| cache.put(key, &content));
+                if let Err(e) = stored {
+                    println!("cargo:warning=could not store {key} in the artifact cache: {e}");
+                }
+            });
+        });
+    }
+
+    /// The compiler cache nvcc runs through and its arguments, see
+    /// [`Builder::compiler_wrapper`]. `CMAKE_CUDA_COMPILER_LAUNCHER` is a CMake list.
+    fn wrapper(&self) -> Vec<String> {
+        let wrapper = self
+            .compiler_wrapper
+            .clone()
+            .or_else(|| WRAPPER_VARS.iter().find_map(|var| self.recorder().var(var)));
+        wrapper
+            .unwrap_or_default()
+            .split(|c: char| c == ';' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    /// Completes the command of `job` right before it runs, as plans show it as is:
+    /// moves its arguments to its response file if needed and, for nvcc, runs it through
+    /// the [`Builder::compiler_wrapper`] then lets the hooks of
+    /// [`Builder::customize_command`] modify it. Commands run through a wrapper keep
+    /// their arguments on the command line, where the wrapper can see them.
+    fn finish_job(&self, job: &mut Job) {
+        let mut command = std::mem::replace(&mut job.command, Command::new(""));
+        let (rsp, style) = &job.response_file;
+        job.command = match style {
+            ResponseFile::At => response_file(command, rsp, self.response_file, *style),
+            ResponseFile::OptionsFile => {
+                if self.provenance {
+                    // Lists the sub-commands nvcc runs, for the provenance.
+                    command.arg("--verbose");
+                }
+                let wrapper = self.wrapper();
+                let mut command = match wrapper.split_first() {
+                    Some((wrapper, args)) => wrap_command(command, wrapper, args),
+                    None => response_file(command, rsp, self.response_file, *style),
+                };
+                for hook in &self.command_hooks {
+                    (hook.0)(&mut command);
+                }
+                command
+            }
+        };
+    }
+
+    /// The command archiving `objects` into the library `out_file`, see [`Builder::archiver`],
+    /// and how it is given a response file.
+    fn archive_command(&self, out_file: &Path, objects: &[PathBuf]) -> (Command, ResponseFile) {
+        match &self.archiver {
+            Archiver::Nvcc => {
+                assert!(
+                    !self.thin_archive && !self.append_archive,
+                    "thin and appended archives are not supported by `nvcc --lib`, see `Builder::archiver`"
+                );
+                let mut command = Command::new("nvcc");
+                command.arg("--lib").arg("-o").arg(out_file).args(objects);
+                (command, ResponseFile::OptionsFile)
+            }
+            Archiver::Ar(ar) => {
+                // Insert (replacing existing members), create quietly and index.
+                let mut operation = String::from("crs");
+                if self.thin_archive {
+                    operation.push('T');
+                }
+                if self.is_reproducible() {
+                    operation.push('D');
+                }
+                let mut command = Command::new(ar);
+                command.arg(operation).arg(out_file).args(objects);
+                (command, ResponseFile::At)
+            }
+            Archiver::Lib(lib) => {
+                assert!(
+                    !self.thin_archive,
+                    "thin archives are not supported by lib.exe, see `Builder::archiver`"
+                );
+                let mut out = OsString::from("/OUT:");
+                out.push(out_file);
+                let mut command = Command::new(lib);
+                command.arg("/NOLOGO").arg(out);
+                if self.append_archive && out_file.exists() {
+                    command.arg(out_file);
+                }
+                command.args(objects);
+                (command, ResponseFile::At)
+            }
+        }
+    }
+
+    /// Makes host code position independent, for [`Builder::build_shared`]. MSVC code
+    /// needs no particular relocation model to end up in a DLL.
+    fn pic_args(&self, command: &mut Command) {
+        if self.recorder().var("CARGO_CFG_TARGET_ENV").as_deref() != Some("msvc") {
+            command.arg("-Xcompiler").arg("-fPIC");
+        }
+    }
+
+    /// The nvcc invocation linking the shared library of [`Builder::build_shared`].
+    fn shared_link_command(&self, out_file: &Path, objects: &[PathBuf]) -> Command {
+        let mut command = Command::new("nvcc");
+        command.arg("--shared");
+        self.common_args(&mut command, None);
+        command.arg("-o").arg(out_file).args(objects);
+        command
+    }
+
+    /// The nvcc invocation compiling the kernel at `path` as `instance`.
+    fn kernel_command(
+        &self,
+        path: &Path,
+        instance: Instance<'_>,
+        kind: Output,
+        include_options: &[OsString],
+    ) -> Command {
+        let compute_caps = self.compute_caps();
+        let mut command = Command::new("nvcc");
+        match kind {
+            Output::Ptx => {
+                // PTX targets a single architecture, fat builds use the oldest
+                // virtual one which newer GPUs JIT compile.
+                let compute_cap = instance.compute_cap.unwrap_or(compute_caps[0]);
+                let arch_args = match self.profile() {
+                    KernelProfile::Thin => self.arch_args(compute_cap),
+                    KernelProfile::Fat => {
+                        vec![format!("--gpu-architecture=compute_{compute_cap}")]
+                    }
+                };
+                command.args(arch_args).arg("--ptx");
+            }
+            Output::DeviceLib(format) => {
+                command
+                    .args(format.nvcc_args(&compute_caps))
+                    .arg("--relocatable-device-code=true");
+            }
+            Output::OptixIr => {
+                command
+                    .arg(format!("--gpu-architecture=compute_{}", compute_caps[0]))
+                    .arg("--optix-ir");
+            }
+            Output::Nvrtc => unreachable!("NVRTC kernels are compiled at runtime"),
+        }
+        // Outputs are named explicitly since `Builder::naming` may not use the file stem.
+        let output = self.raw_output(path, instance, kind);
+        if self.dependency_files {
+            command
+                .arg("--generate-dependencies-with-compile")
+                .arg("--dependency-output")
+                .arg(output.with_extension("d"));
+        }
+        command.arg("-o").arg(output);
+        let name = self.kernel_name(path, instance);
+        if let Some(dir) = self.intermediates_dir(kind.extension(), &name) {
+            command.arg("--keep").arg("--keep-dir").arg(dir);
+        }
+        self.common_args(&mut command, Some(instance));
+        command.args(self.source_include_option(path));
+        command
+            .args(include_options)
+            .arg(self.command_path(&self.source(path)));
+        command
+    }
+
+    /// Describes the kernels built by the last build of `kind`, which compiled `jobs` of them.
+    fn manifest(&self, kind: Output, jobs: usize) -> Manifest {
+        let include_options = self.include_options();
+        let kernels = self
+            .kernels(kind)
+            .into_iter()
+            .map(|(source, instance)| {
+                let output = self.kernel_output(&source, instance, kind);
+                let mut command = self.kernel_command(&source, instance, kind, &include_options);
+                command.args(self.parallel_args(&source, jobs).0);
+                let content = std::fs::read(&output).expect("kernel output to be readable");
+                ManifestEntry {
+                    name: self.kernel_name(&source, instance),
+                    flags: command
+                        .get_args()
+                        .map(|a| a.to_string_lossy().into_owned())
+                        .collect(),
+                    compute_caps: instance
+                        .compute_cap
+                        .map_or_else(|| self.compute_caps(), |c| vec![c]),
+                    content_hash: fnv1a(&content),
+                    intermediates: self
+                        .intermediates_dir(kind.extension(), &self.kernel_name(&source, instance))
+                        .map(|dir| {
+                            let mut files = vec![];
+                            files_in(&dir, &mut files);
+                            files
+                        })
+                        .unwrap_or_default(),
+                    source,
+                    output,
+                }
+            })
+            .collect();
+        Manifest {
+            nvcc_version: nvcc_version(&self.recorder()),
+            kernels,
+        }
+    }
+
+    /// The jobs compiling each kernel into an object and the final linking jobs,
+    /// to be run in order, unless the library is up to date.
+    fn lib_jobs(&self, out_file: &Path, shared: bool) -> (Vec<Job>, Vec<Job>, Vec<PathBuf>) {
+        let name = lib_fingerprint_name(out_file);
+        let config_changed = self
+            .cached_fingerprint(&name)
+            .is_none_or(|(cached, _)| cached.config != self.fingerprint(&name).config);
+        let out_modified: Result<_, _> = out_file.metadata().and_then(|m| m.modified());
+        let should_compile = if config_changed {
+            true
+        } else if let Ok(out_modified) = out_modified {
+            let kernel_modified = self.kernel_paths.iter().any(|entry| {
+                let in_modified = self
+                    .modified(&self.source(entry))
+                    .expect("kernel modified to be accessible");
+                in_modified.duration_since(out_modified).is_ok()
+            });
+            let watch_modified = self.watch.iter().any(|entry| {
+                let in_modified = entry
+                    .metadata()
+                    .expect("watched file {entry} should exist")
+                    .modified()
+                    .expect("watch modified should be accessible");
+                in_modified.duration_since(out_modified).is_ok()
+            });
+            let dependencies_modified = self
+                .object_paths()
+                .iter()
+                .any(|object| self.dependencies_changed(object, out_modified));
+            kernel_modified || watch_modified || dependencies_modified
+        } else {
+            true
+        };
+        if !should_compile {
+            return (vec![], vec![], self.kernel_paths.clone());
+        }
+        let (compile_jobs, mut link_jobs, objects) = self.object_jobs(out_file, shared);
+        let (command, style, rsp) = match shared {
+            true => (
+                self.shared_link_command(out_file, &objects),
+                ResponseFile::OptionsFile,
+                "shared.rsp",
+            ),
+            false => {
+                let (command, style) = self.archive_command(out_file, &objects);
+                (command, style, "lib.rsp")
+            }
+        };
+        link_jobs.push(Job {
+            input: out_file.to_path_buf(),
+            response_file: (self.build_dir().join(rsp), style),
+            output: out_file.to_path_buf(),
+            action: "linking",
+            command,
+            cache_key: None,
+            threads: 1,
+            duration: Default::default(),
+            timed_out: false,
+        });
+        (compile_jobs, link_jobs, vec![])
+    }
+
+    /// The object [`Builder::build_lib`] compiles the source `f` to as `instance`.
+    fn object_path(&self, f: &Path, instance: Instance<'_>) -> PathBuf {
+        let mut s = DefaultHasher::new();
+        f.hash(&mut s);
+        let hash = s.finish();
+        let stem = match instance.config.and_then(|config| config.name.as_ref()) {
+            Some(name) => name.clone(),
+            None => f
+                .file_stem()
+                .expect("kernels paths should include a filename")
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let mut obj_file = self.output_dir(f).join(format!(
+            "{stem}{}-{hash:x}",
+            instance
+                .variant
+                .map(|v| format!("_{}", v.suffix))
+                .unwrap_or_default(),
+        ));
+        obj_file.set_extension("o");
+        obj_file
+    }
+
+    /// The objects [`Builder::build_lib`] compiles, one per source and instance.
+    fn object_paths(&self) -> Vec<PathBuf> {
+        self.kernel_paths
+            .iter()
+            .flat_map(|f| {
+                self.instances(f, Language::of(f) == Language::Cuda)
+                    .into_iter()
+                    .map(move |instance| self.object_path(f, instance))
+            })
+            .collect()
+    }
+
+    /// The jobs compiling each kernel into an object, then device linking them when
+    /// [`Builder::device_lto`] is set, along with the objects to archive. The device
+    /// linked object is named after `out_file`.
+    fn object_jobs(&self, out_file: &Path, pic: bool) -> (Vec<Job>, Vec<Job>, Vec<PathBuf>) {
+        // Configurations of a source only get distinct objects when they are named.
+        check_collisions(self.kernel_paths.iter().flat_map(|f| {
+            self.instances(f, Language::of(f) == Language::Cuda)
+                .into_iter()
+                .map(move |instance| {
+                    (
+                        self.object_path(f, instance).display().to_string(),
+                        f.clone(),
+                    )
+                })
+        }))
+        .unwrap_or_else(|e| panic!("{e}"));
+        let group = intermediates_group(out_file);
+        let gencode_args = if self.device_lto {
+            // Objects carry LTO-IR, SASS is only generated by the device link.
+            self.compute_caps()
+                .iter()
+                .map(|c| format!("--generate-code=arch=compute_{c},code=lto_{c}"))
+                .collect()
+        } else {
+            self.gencode_args()
+        };
+        let mut kernel_paths = self.kernel_paths.clone();
+        if self.is_reproducible() {
+            kernel_paths.sort();
+        }
+        let mut compile_jobs: Vec<_> = kernel_paths
+            .iter()
+            .flat_map(|f| {
+                // Host sources are compiled once, only CUDA sources come in variants.
+                self.instances(f, Language::of(f) == Language::Cuda)
+                    .into_iter()
+                    .map(move |instance| (f, instance))
+            })
+            .map(|(f, instance)| {
+                let obj_file = self.object_path(f, instance);
+                let mut command = Command::new("nvcc");
+                command.args(&gencode_args);
+                if self.device_lto {
+                    command.arg("-dc").arg("-dlto");
                 } else {
-                    false
+                    command.arg("-c");
+                }
+                command.arg("-o").arg(&obj_file);
+                if self.dependency_files {
+                    command
+                        .arg("--generate-dependencies-with-compile")
+                        .arg("--dependency-output")
+                        .arg(obj_file.with_extension("d"));
+                }
+                let object = obj_file.file_stem().expect("object file name");
+                if let Some(dir) = self.intermediates_dir(&group, &object.to_string_lossy()) {
+                    command.arg("--keep").arg("--keep-dir").arg(&dir);
+                    self.create_dir_later(dir);
+                }
+                self.common_args(&mut command, Some(instance));
+                if pic {
+                    self.pic_args(&mut command);
+                }
+                if Language::of(f) == Language::Cpp {
+                    command.args(&self.cpp_args);
+                }
+                command.args(self.source_include_option(f));
+                command.arg(self.command_path(&self.source(f)));
+                Job {
+                    input: f.clone(),
+                    response_file: (obj_file.with_extension("rsp"), ResponseFile::OptionsFile),
+                    output: obj_file,
+                    action: "compiling",
+                    command,
+                    cache_key: None,
+                    threads: 1,
+                    duration: Default::default(),
+                    timed_out: false,
+                }
+            })
+            .collect();
+        self.parallelize(&mut compile_jobs);
+        let mut link_jobs = vec![];
+        let mut objects: Vec<PathBuf> = compile_jobs.iter().map(|job| job.output.clone()).collect();
+        if self.device_lto {
+            let stem = out_file
+                .file_stem()
+                .expect("library to have a filename")
+                .to_string_lossy();
+            let dlink_file = self.build_dir().join(format!("{stem}-dlink.o"));
+            let mut command = Command::new("nvcc");
+            command.arg("-dlink").arg("-dlto").args(
+                self.compute_caps()
+                    .iter()
+                    .map(|c| format!("--generate-code=arch=compute_{c},code=sm_{c}")),
+            );
+            self.common_args(&mut command, None);
+            if pic {
+                self.pic_args(&mut command);
+            }
+            command.arg("-o").arg(&dlink_file).args(&objects);
+            link_jobs.push(Job {
+                input: out_file.to_path_buf(),
+                response_file: (
+                    self.build_dir().join("dlink.rsp"),
+                    ResponseFile::OptionsFile,
+                ),
+                output: dlink_file.clone(),
+                action: "device linking",
+                command,
+                cache_key: None,
+                threads: 1,
+                duration: Default::default(),
+                timed_out: false,
+            });
+            objects.push(dlink_file);
+        }
+        (compile_jobs, link_jobs, objects)
+    }
+
+    /// Runs the jobs concurrently, failing with nvcc's diagnostics if any of them failed.
+    fn run_jobs(&self, jobs: Vec<Job>) -> Result<(), Error> {
+        let job_threads = jobs.iter().map(|job| job.threads).max().unwrap_or(1);
+        let num_threads = (self.num_threads / job_threads).max(1);
+        self.flush_writes();
+        // Unless asked to keep going, jobs which haven't started are skipped once one fails.
+        let failed = AtomicBool::new(false);
+        let provenance_error = Mutex::new(None);
+        let run = |mut job: Job, capture: bool| {
+            if failed.load(Ordering::SeqCst) && !self.keep_going {
+                return (job, None);
+            }
+            if let Some(dir) = job.output.parent() {
+                std::fs::create_dir_all(dir)
+                    .unwrap_or_else(|e| panic!("create {}: {e}", dir.display()));
+            }
+            self.finish_job(&mut job);
+            if !capture {
+                eprintln!("{:?}", job.command);
+            }
+            // The provenance is read from the output, which is then relayed as it comes.
+            let stream = self.stream_output || (self.provenance && !capture);
+            let capture = capture || self.provenance;
+            let output = job.run(capture, stream, self.job_deadline());
+            match &output {
+                Ok(output) if output.status.success() => {
+                    if self.provenance && !job.timed_out {
+                        if let Err(e) = self.write_provenance(&job, output) {
+                            failed.store(true, Ordering::SeqCst);
+                            let mut error = provenance_error.lock().expect("provenance error lock");
+                            error.get_or_insert(e);
+                        }
+                    }
+                }
+                _ => failed.store(true, Ordering::SeqCst),
+            }
+            (job, Some(output))
+        };
+        let (results, verbosity): (Vec<_>, _) = if self.single_threaded {
+            // One job at a time, in order, echoing each command before its own output
+            // so failures can be attributed without any interleaving.
+            let results = jobs.into_iter().map(|job| run(job, false)).collect();
+            (results, Verbosity::Quiet)
+        } else {
+            let results = thread_pool(num_threads).install(|| {
+                jobs.into_par_iter()
+                    .map(|job| {
+                        let _token = self.job_server.acquire();
+                        run(job, true)
+                    })
+                    .collect()
+            });
+            (results, self.verbosity)
+        };
+        // Report every failed kernel at once, instead of one per build.
+        let mut inputs = vec![];
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+        let mut outputs = vec![];
+        let mut timeout = None;
+        for (job, output) in results {
+            let Some(output) = output else {
+                continue;
+            };
+            self.record_timing(&job);
+            if job.timed_out {
+                timeout.get_or_insert(Error::Timeout {
+                    input: job.input.clone(),
+                    action: job.action,
+                    elapsed: job.duration,
+                });
+                continue;
+            }
+            if let Err(Error::Compilation {
+                inputs: job_inputs,
+                diagnostics: job_diagnostics,
+                output,
+            }) = job.check(output, verbosity, self.deprecated_archs)
+            {
+                inputs.extend(job_inputs);
+                for diagnostic in job_diagnostics {
+                    if !diagnostics.contains(&diagnostic) {
+                        diagnostics.push(diagnostic);
+                    }
+                }
+                outputs.push(output);
+            }
+        }
+        if let Some(timeout) = timeout {
+            return Err(timeout);
+        }
+        if let Some(error) = provenance_error
+            .into_inner()
+            .expect("provenance error lock")
+        {
+            return Err(error);
+        }
+        if outputs.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Compilation {
+                inputs,
+                diagnostics,
+                output: outputs.join("\n"),
+            })
+        }
+    }
+
+    /// Writes the provenance of the output of `job` from what nvcc printed while
+    /// running it, see [`Builder::provenance`]. Jobs not running nvcc, like archivers,
+    /// have none.
+    fn write_provenance(&self, job: &Job, output: &std::process::Output) -> Result<(), Error> {
+        let Some(nvcc) = nvcc_program(&job.command) else {
+            return Ok(());
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let (mut environment, steps): (Vec<_>, Vec<_>) = stdout
+            .lines()
+            .chain(stderr.lines())
+            .filter_map(|line| line.strip_prefix("#$ "))
+            .partition(|line| is_assignment(line));
+        // Sub-commands are looked up in the PATH nvcc sets for them, which is not
+        // recorded: it holds the whole PATH of the build machine.
+        let path = environment
+            .iter()
+            .find_map(|line| {
+                line.strip_prefix("PATH=")
+                    .or(line.strip_prefix("set PATH="))
+            })
+            .unwrap_or_default();
+        environment.retain(|line| !line.starts_with("PATH=") && !line.starts_with("set PATH="));
+        let programs = std::iter::once(nvcc.to_string_lossy().into_owned())
+            .chain(steps.iter().filter_map(|step| step_program(step)));
+        let mut tools = BTreeMap::new();
+        for program in programs {
+            let program = Self::resolve_program(&program, path);
+            let name = Path::new(&program)
+                .file_stem()
+                .map_or(program.clone(), |stem| stem.to_string_lossy().into_owned());
+            if let Some(version) = self.tool_version(&program) {
+                tools.entry(name).or_insert(version);
+            }
+        }
+        let list = |items: &[&str]| {
+            items
+                .iter()
+                .map(|item| format!("    {}", json_string(item)))
+                .collect::<Vec<_>>()
+                .join(",\n")
+        };
+        let tools = tools
+            .iter()
+            .map(|(name, version)| format!("    {}: {}", json_string(name), json_string(version)))
+            .collect::<Vec<_>>()
+            .join(",\n");
+        let content = format!(
+            "{{\n  \"input\": {},\n  \"output\": {},\n  \"command\": {},\n  \"environment\": [\n{}\n  ],\n  \"steps\": [\n{}\n  ],\n  \"tools\": {{\n{tools}\n  }}\n}}\n",
+            json_string(&job.input.to_string_lossy()),
+            json_string(&job.output.to_string_lossy()),
+            json_string(&format!("{:?}", job.command)),
+            list(&environment),
+            list(&steps),
+        );
+        let mut path = job.output.clone().into_os_string();
+        path.push(".provenance.json");
+        let path = PathBuf::from(path);
+        std::fs::write(&path, content)
+            .map_err(|e| Error::Io(format!("write provenance to {}: {e}", path.display())))
+    }
+
+    /// `program` as found in `path`, when it is a bare name.
+    fn resolve_program(program: &str, path: &str) -> String {
+        if Path::new(program).components().count() > 1 {
+            return program.to_string();
+        }
+        std::env::split_paths(path)
+            .filter(|dir| !dir.to_string_lossy().contains(['$', '%']))
+            .flat_map(|dir| [dir.join(program), dir.join(format!("{program}.exe"))])
+            .find(|candidate| candidate.is_file())
+            .map_or(program.to_string(), |found| {
+                found.to_string_lossy().into_owned()
+            })
+    }
+
+    /// The `--version` of `program`, if it has one.
+    fn tool_version(&self, program: &str) -> Option<String> {
+        let mut versions = self.tool_versions.lock().expect("tool versions lock");
+        versions
+            .entry(program.to_string())
+            .or_insert_with(|| {
+                let version = self.recorder().output(program, &["--version"])?;
+                Some(version.trim().to_string()).filter(|v| !v.is_empty())
+            })
+            .clone()
+    }
+
+    fn record_timing(&self, job: &Job) {
+        self.timings.lock().expect("timings lock").push(Timing {
+            input: job.input.clone(),
+            action: job.action,
+            duration: job.duration,
+        });
+    }
+
+    /// Writes how long each nvcc invocation of the build took to `timings.json`,
+    /// and relays them as cargo warnings if requested, see [`Builder::report_timings`].
+    fn write_timings(&self) {
+        let mut timings = std::mem::take(&mut *self.timings.lock().expect("timings lock"));
+        if timings.is_empty() {
+            return;
+        }
+        timings.sort_by_key(|t| std::cmp::Reverse(t.duration));
+        let entries = timings
+            .iter()
+            .map(|t| {
+                format!(
+                    "  {{\"input\": {}, \"action\": {}, \"seconds\": {:.3}}}",
+                    json_string(&t.input.to_string_lossy()),
+                    json_string(t.action),
+                    t.duration.as_secs_f64()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",\n");
+        std::fs::write(
+            self.build_dir().join("timings.json"),
+            format!("[\n{entries}\n]\n"),
+        )
+        .expect("write timings");
+        if self.report_timings || self.recorder().var("BINDGEN_CUDA_TIMINGS").is_some() {
+            let total: std::time::Duration = timings.iter().map(|t| t.duration).sum();
+            println!(
+                "cargo:warning=nvcc took {:.1}s in total",
+                total.as_secs_f64()
+            );
+            for t in &timings {
+                println!(
+                    "cargo:warning={:>8.1}s {} {}",
+                    t.duration.as_secs_f64(),
+                    t.action,
+                    t.input.display()
+                );
+            }
+        }
+    }
+}
+
+/// How long a single nvcc invocation took.
+#[derive(Debug)]
+struct Timing {
+    input: PathBuf,
+    action: &'static str,
+    duration: std::time::Duration,
+}
+
+impl Job {
+    /// Runs the command, capturing its output unless it should go straight to the terminal.
+    /// Captured output is also relayed to stderr as it comes when `stream` is set.
+    /// The command is killed if it still runs at `deadline`.
+    fn run(
+        &mut self,
+        capture: bool,
+        stream: bool,
+        deadline: Option<std::time::Instant>,
+    ) -> std::io::Result<std::process::Output> {
+        if capture {
+            self.command
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped());
+        }
+        // Lets the compilers nvcc runs be killed along with it.
+        #[cfg(unix)]
+        if deadline.is_some() {
+            std::os::unix::process::CommandExt::process_group(&mut self.command, 0);
+        }
+        let start = std::time::Instant::now();
+        let child = self.command.spawn().expect("nvcc failed to start. Ensure that you have CUDA installed and that `nvcc` is in your PATH.");
+        let relay = capture && stream;
+        if relay {
+            eprintln!("{}: {}", self.input.display(), self.action);
+        }
+        let output = wait(child, &self.input, relay, deadline);
+        self.duration = start.elapsed();
+        let (output, timed_out) = output?;
+        self.timed_out = timed_out;
+        Ok(output)
+    }
+
+    /// Fails with nvcc's diagnostics if the job failed, otherwise relays them
+    /// to cargo according to `verbosity` and `deprecated_archs`.
+    fn check(
+        &self,
+        output: std::io::Result<std::process::Output>,
+        verbosity: Verbosity,
+        deprecated_archs: DeprecatedArchs,
+    ) -> Result<(), Error> {
+        let output = output.expect("nvcc failed to run. Ensure that you have CUDA installed and that `nvcc` is in your PATH.");
+        if verbosity == Verbosity::Verbose {
+            println!("cargo:warning={:?}", self.command);
+        }
+        if verbosity != Verbosity::Quiet && output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            // Sub-commands listed by `--verbose` for the provenance are not relayed.
+            for line in stdout.lines().chain(stderr.lines()) {
+                if line.starts_with("#$ ") {
+                    continue;
+                }
+                let is_warning = line.to_lowercase().contains("warning");
+                if is_warning || (verbosity == Verbosity::Verbose && !line.trim().is_empty()) {
+                    println!("cargo:warning={}: {line}", self.input.display());
+                }
+            }
+        }
+        if !output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let mut diagnostics: Vec<Diagnostic> = vec![];
+            for output in [&stdout, &stderr] {
+                for diagnostic in Diagnostic::parse_output(output, &self.input) {
+                    if !diagnostics.contains(&diagnostic) {
+                        diagnostics.push(diagnostic);
+                    }
+                }
+            }
+            return Err(Error::Compilation {
+                inputs: vec![self.input.clone()],
+                diagnostics,
+                output: format!(
+                    "nvcc error while {} {:?}:\n\n# CLI {:?} \n\n# stdout\n{stdout:#}\n\n# stderr\n{stderr:#}",
+                    self.action, self.input, self.command,
+                ),
+            });
+        }
+        let deprecated = deprecated_compute_caps(&String::from_utf8_lossy(&output.stderr));
+        if !deprecated.is_empty() {
+            let message = format!(
+                "{}: nvcc deprecated compute caps {deprecated:?}, drop them from the targeted compute caps before they are removed by a toolkit upgrade",
+                self.input.display()
+            );
+            match deprecated_archs {
+                DeprecatedArchs::Deny => {
+                    // Compiled again next time, to fail again.
+                    let _ = std::fs::remove_file(&self.output);
+                    return Err(Error::Compilation {
+                        inputs: vec![self.input.clone()],
+                        diagnostics: Diagnostic::parse_output(
+                            &String::from_utf8_lossy(&output.stderr),
+                            &self.input,
+                        ),
+                        output: message,
+                    });
+                }
+                DeprecatedArchs::Warn if verbosity != Verbosity::Quiet => {
+                    println!("cargo:warning={message}")
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A message reported by nvcc, or by the host compiler and ptxas it runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    file: PathBuf,
+    line: Option<usize>,
+    column: Option<usize>,
+    severity: Severity,
+    message: String,
+    context: Vec<String>,
+}
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Fails the compilation, catastrophic and fatal errors included.
+    Error,
+    /// Reported without failing the compilation.
+    Warning,
+    /// Context for a previous diagnostic, or remark.
+    Note,
+}
+
+impl Severity {
+    fn parse(word: &str) -> Option<Self> {
+        let word = word.to_lowercase();
+        if word.contains("error") || word.contains("fatal") {
+            Some(Severity::Error)
+        } else if word.contains("warning") {
+            Some(Severity::Warning)
+        } else if word.contains("note") || word.contains("remark") {
+            Some(Severity::Note)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+impl Diagnostic {
+    /// The file the diagnostic points at. Messages of ptxas and nvcc itself,
+    /// which have no source location, point at the kernel being compiled.
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    /// The line the diagnostic points at, if any.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// The column the diagnostic points at, if any. nvcc's front end only reports lines.
+    pub fn column(&self) -> Option<usize> {
+        self.column
+    }
+
+    /// How serious the diagnostic is.
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// The message, without its location and severity.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The lines nvcc printed below the message to explain it, like
+    /// `detected during instantiation of "f<T>" at line 30`.
+    pub fn context(&self) -> &[String] {
+        &self.context
+    }
+
+    /// Parses a line of nvcc's output produced while compiling `input`, in any of the
+    /// formats it relays:
+    /// - `src/kernel.cu(12): error: identifier "x" is undefined` (front end, MSVC)
+    /// - `src/kernel.cu:12:5: error: expected ';'` (gcc, clang)
+    /// - `ptxas error   : Entry function uses too much shared data` (ptxas, nvcc)
+    ///
+    /// ```
+    /// use bindgen_cuda::{Diagnostic, Severity};
+    /// use std::path::Path;
+    ///
+    /// let input = Path::new("src/kernel.cu");
+    /// let front_end = r#"src/kernel.cu(12): error: identifier "x" is undefined"#;
+    /// let diagnostic = Diagnostic::parse(front_end, input).unwrap();
+    /// assert_eq!(diagnostic.file(), input);
+    /// assert_eq!((diagnostic.line(), diagnostic.column()), (Some(12), None));
+    /// assert_eq!(diagnostic.message(), r#"identifier "x" is undefined"#);
+    ///
+    /// let msvc = r"C:\src\kernel.cu(12,5): error C2065: 'x': undeclared identifier";
+    /// let diagnostic = Diagnostic::parse(msvc, input).unwrap();
+    /// assert_eq!(diagnostic.file(), Path::new(r"C:\src\kernel.cu"));
+    /// assert_eq!((diagnostic.line(), diagnostic.column()), (Some(12), Some(5)));
+    /// assert_eq!(diagnostic.message(), "'x': undeclared identifier");
+    ///
+    /// let gcc = "src/kernel.cu:12:5: warning: unused variable 'y'";
+    /// let diagnostic = Diagnostic::parse(gcc, input).unwrap();
+    /// assert_eq!(diagnostic.severity(), Severity::Warning);
+    /// assert_eq!((diagnostic.line(), diagnostic.column()), (Some(12), Some(5)));
+    ///
+    /// let no_column = r"C:\x.cu:12: error: expected ';'";
+    /// let diagnostic = Diagnostic::parse(no_column, input).unwrap();
+    /// assert_eq!(diagnostic.file(), Path::new(r"C:\x.cu"));
+    /// assert_eq!((diagnostic.line(), diagnostic.column()), (Some(12), None));
+    ///
+    /// let ptxas = "ptxas error   : Entry function 'f' uses too much shared data";
+    /// let diagnostic = Diagnostic::parse(ptxas, input).unwrap();
+    /// assert_eq!(diagnostic.file(), input);
+    /// assert_eq!(diagnostic.line(), None);
+    /// assert_eq!(diagnostic.message(), "Entry function 'f' uses too much shared data");
+    ///
+    /// assert_eq!(Diagnostic::parse("1 error detected in the compilation", input), None);
+    /// ```
+    pub fn parse(line: &str, input: &Path) -> Option<Self> {
+        let line = line.trim();
+        for tool in ["ptxas", "nvcc", "nvlink", "fatbinary"] {
+            if let Some(rest) = line.strip_prefix(tool) {
+                let (head, message) = rest.split_once(" : ").or_else(|| rest.split_once(": "))?;
+                return Some(Diagnostic {
+                    file: input.to_path_buf(),
+                    line: None,
+                    column: None,
+                    severity: Severity::parse(head.split_whitespace().last()?)?,
+                    message: message.trim().to_string(),
+                    context: vec![],
+                });
+            }
+        }
+        // `file(line): ` or `file(line,column): `, then `file:line: ` or `file:line:column: `,
+        // the file itself possibly holding a drive letter.
+        let (file, line_number, column, rest) = line
+            .split_once("): ")
+            .and_then(|(location, rest)| {
+                let (file, position) = location.rsplit_once('(')?;
+                let (line_number, column) = match position.split_once(',') {
+                    Some((line_number, column)) => (line_number, Some(column.parse().ok()?)),
+                    None => (position, None),
                 };
-                if ignore {
-                    None
-                } else {
-                    let mut command = std::process::Command::new("nvcc");
-                    command.arg(format!("--gpu-architecture=sm_{compute_cap}"))
-                        .arg("--ptx")
-                        .args(["--default-stream", "per-thread"])
-                        .args(["--output-directory", &out_dir.display().to_string()])
-                        .args(&self.extra_args)
-                        .args(&include_options);
-                    if let Ok(ccbin_path) = &ccbin_env {
-                        command
-                            .arg("-allow-unsupported-compiler")
-                            .args(["-ccbin", ccbin_path]);
+                Some((file, line_number.parse().ok()?, column, rest))
+            })
+            .or_else(|| {
+                let (location, rest) = line.split_once(": ")?;
+                let (head, last) = location.rsplit_once(':')?;
+                let last = last.parse().ok()?;
+                let line_number = head
+                    .rsplit_once(':')
+                    .and_then(|(file, line_number)| Some((file, line_number.parse().ok()?)));
+                Some(match line_number {
+                    Some((file, line_number)) => (file, line_number, Some(last), rest),
+                    None => (head, last, None, rest),
+                })
+            })?;
+        let (severity, message) = rest.split_once(": ")?;
+        Some(Diagnostic {
+            file: file.into(),
+            line: Some(line_number),
+            column,
+            severity: Severity::parse(severity)?,
+            message: message.trim().to_string(),
+            context: vec![],
+        })
+    }
+
+    /// Parses nvcc's whole `output`, keeping the indented lines following a diagnostic
+    /// as its [`Diagnostic::context`].
+    ///
+    /// ```
+    /// use bindgen_cuda::Diagnostic;
+    /// use std::path::Path;
+    ///
+    /// let output = r#"src/kernel.cu(12): error: no operator "+" matches these operands
+    ///           detected during instantiation of "f<T>" at line 30
+    ///
+    /// 1 error detected in the compilation of "src/kernel.cu"."#;
+    /// let diagnostics = Diagnostic::parse_output(output, Path::new("src/kernel.cu"));
+    /// assert_eq!(diagnostics.len(), 1);
+    /// assert_eq!(
+    ///     diagnostics[0].to_string(),
+    ///     r#"src/kernel.cu:12: error: no operator "+" matches these operands
+    ///           detected during instantiation of "f<T>" at line 30"#
+    /// );
+    /// ```
+    pub fn parse_output(output: &str, input: &Path) -> Vec<Self> {
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+        let mut continued = false;
+        for line in output.lines() {
+            if let Some(diagnostic) = Self::parse(line, input) {
+                diagnostics.push(diagnostic);
+                continued = true;
+            } else if continued && line.starts_with(char::is_whitespace) && !line.trim().is_empty()
+            {
+                if let Some(diagnostic) = diagnostics.last_mut() {
+                    diagnostic.context.push(line.trim_end().to_string());
+                }
+            } else {
+                continued = false;
+            }
+        }
+        diagnostics
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+        }
+        if let Some(column) = self.column {
+            write!(f, ":{column}")?;
+        }
+        write!(f, ": {}: {}", self.severity, self.message)?;
+        for line in &self.context {
+            write!(f, "\n{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The compute caps nvcc warns are deprecated in its `output`, like
+/// `nvcc warning : The 'compute_35', 'sm_35' architectures are deprecated`.
+fn deprecated_compute_caps(output: &str) -> Vec<usize> {
+    let mut compute_caps: Vec<usize> = output
+        .lines()
+        .filter(|line| line.contains("deprecated") && line.contains("architecture"))
+        .flat_map(|line| line.split(|c: char| !c.is_ascii_alphanumeric() && c != '_'))
+        .filter_map(|word| {
+            word.strip_prefix("compute_")
+                .or_else(|| word.strip_prefix("sm_"))
+                .and_then(|cap| cap.parse().ok())
+        })
+        .collect();
+    compute_caps.sort();
+    compute_caps.dedup();
+    compute_caps
+}
+
+/// Include hygiene of a kernel tree, see [`Builder::header_report`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderReport {
+    unused_include_dirs: Vec<PathBuf>,
+    unreferenced_headers: Vec<PathBuf>,
+}
+
+impl HeaderReport {
+    /// Include directories (`-I`) through which no kernel includes anything.
+    pub fn unused_include_dirs(&self) -> &[PathBuf] {
+        &self.unused_include_dirs
+    }
+
+    /// Headers which are not included, even transitively, by any kernel.
+    pub fn unreferenced_headers(&self) -> &[PathBuf] {
+        &self.unreferenced_headers
+    }
+
+    /// Whether nothing was found.
+    pub fn is_clean(&self) -> bool {
+        self.unused_include_dirs.is_empty() && self.unreferenced_headers.is_empty()
+    }
+
+    /// Prints each finding as a cargo warning.
+    pub fn emit_warnings(&self) {
+        for line in self.to_string().lines() {
+            println!("cargo:warning={line}");
+        }
+    }
+}
+
+impl std::fmt::Display for HeaderReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for dir in &self.unused_include_dirs {
+            writeln!(f, "unused include directory {}", dir.display())?;
+        }
+        for header in &self.unreferenced_headers {
+            writeln!(
+                f,
+                "header {} is not included by any kernel",
+                header.display()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The CUDA environment as detected by the builder, see [`Builder::diagnose`].
+/// Its `Display` implementation prints it in a human readable form.
+#[derive(Debug, Clone)]
+pub struct Diagnosis {
+    cuda_root: Option<PathBuf>,
+    cuda_roots: Vec<(PathBuf, bool)>,
+    nvcc: Option<PathBuf>,
+    nvcc_version: Option<String>,
+    nvidia_smi: Option<String>,
+    compute_cap: Result<usize, Error>,
+    supported_compute_caps: Result<Vec<usize>, Error>,
+    host_compiler: Option<PathBuf>,
+    vars: Vec<(String, Option<String>)>,
+}
+
+impl Diagnosis {
+    /// The root of the CUDA toolkit in use.
+    pub fn cuda_root(&self) -> Option<&Path> {
+        self.cuda_root.as_deref()
+    }
+
+    /// The directories the toolkit was looked for in, in order, and whether it was found there.
+    pub fn cuda_root_candidates(&self) -> &[(PathBuf, bool)] {
+        &self.cuda_roots
+    }
+
+    /// The `nvcc` found in the `PATH`.
+    pub fn nvcc(&self) -> Option<&Path> {
+        self.nvcc.as_deref()
+    }
+
+    /// The release of `nvcc` (`"12.4"`).
+    pub fn nvcc_version(&self) -> Option<&str> {
+        self.nvcc_version.as_deref()
+    }
+
+    /// The compute cap and driver version reported by `nvidia-smi`.
+    pub fn nvidia_smi(&self) -> Option<&str> {
+        self.nvidia_smi.as_deref()
+    }
+
+    /// The compute cap kernels are compiled for, see [`Builder::compute_cap`].
+    pub fn compute_cap(&self) -> Result<usize, Error> {
+        self.compute_cap.clone()
+    }
+
+    /// The compute caps `nvcc` can generate code for.
+    pub fn supported_compute_caps(&self) -> Result<Vec<usize>, Error> {
+        self.supported_compute_caps.clone()
+    }
+
+    /// The host compiler nvcc uses, see [`Builder::host_compiler`].
+    pub fn host_compiler(&self) -> Option<&Path> {
+        self.host_compiler.as_deref()
+    }
+
+    /// The environment variables the builder consults, with their values.
+    pub fn vars(&self) -> &[(String, Option<String>)] {
+        &self.vars
+    }
+}
+
+impl std::fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn or_missing(path: Option<&Path>) -> String {
+            path.map_or("not found".to_string(), |p| p.display().to_string())
+        }
+        writeln!(f, "CUDA root: {}", or_missing(self.cuda_root()))?;
+        for (root, found) in &self.cuda_roots {
+            let mark = if *found { "x" } else { " " };
+            writeln!(f, "  [{mark}] {}", root.display())?;
+        }
+        writeln!(f, "nvcc: {}", or_missing(self.nvcc()))?;
+        writeln!(
+            f,
+            "nvcc release: {}",
+            self.nvcc_version().unwrap_or("unknown")
+        )?;
+        writeln!(
+            f,
+            "nvidia-smi: {}",
+            self.nvidia_smi().unwrap_or("not found")
+        )?;
+        match &self.compute_cap {
+            Ok(compute_cap) => writeln!(f, "compute cap: {compute_cap}")?,
+            Err(e) => writeln!(f, "compute cap: {e}")?,
+        }
+        match &self.supported_compute_caps {
+            Ok(caps) => {
+                let caps: Vec<_> = caps.iter().map(|c| format!("sm_{c}")).collect();
+                writeln!(f, "nvcc targets: {}", caps.join(" "))?
+            }
+            Err(e) => writeln!(f, "nvcc targets: {e}")?,
+        }
+        match self.host_compiler() {
+            Some(compiler) => writeln!(f, "host compiler: {}", compiler.display())?,
+            None => writeln!(f, "host compiler: nvcc's default")?,
+        }
+        writeln!(f, "environment:")?;
+        for (var, value) in &self.vars {
+            match value {
+                Some(value) => writeln!(f, "  {var}={value}")?,
+                None => writeln!(f, "  {var} is not set")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Summary of everything a build depends on, persisted in OUT_DIR so that repeated
+/// invocations of the build script (rust-analyzer, `cargo check` loops) can skip
+/// the build entirely, including the slow `nvidia-smi` and `nvcc` detection.
+#[derive(Debug, PartialEq, Eq)]
+struct Fingerprint {
+    /// Hash of the builder configuration and environment, a change invalidates every output.
+    config: u64,
+    /// Hash of the size and modification time of every input.
+    inputs: u64,
+}
+
+/// Name under which the fingerprint of a library build is stored.
+fn lib_fingerprint_name(out_file: &Path) -> String {
+    let mut s = DefaultHasher::new();
+    out_file.hash(&mut s);
+    format!("lib-{:x}", s.finish())
+}
+
+/// A library produced by [`Builder::build_lib`] or [`Builder::build_shared`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Library {
+    name: String,
+    path: PathBuf,
+    shared: bool,
+}
+
+impl Library {
+    fn new(path: PathBuf, shared: bool) -> Self {
+        let stem = path
+            .file_stem()
+            .expect("library to have a filename")
+            .to_string_lossy();
+        let name = match path.extension().and_then(|e| e.to_str()) {
+            Some("lib" | "dll") => stem.to_string(),
+            _ => stem.strip_prefix("lib").unwrap_or(&stem).to_string(),
+        };
+        Self { name, path, shared }
+    }
+
+    /// The name the library is linked with, `flash` for `libflash.a`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Where the library was created.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the library is a shared one, from [`Builder::build_shared`].
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// Prints the directives linking the library.
+    fn emit_link_directives(&self) {
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+        let kind = if self.shared { "dylib" } else { "static" };
+        println!("cargo:rustc-link-search=native={}", dir.display());
+        println!("cargo:rustc-link-lib={kind}={}", self.name);
+    }
+}
+
+/// How much of nvcc's output is relayed to cargo when compilation succeeds.
+/// Failures always report the full output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Verbosity {
+    /// Nothing is relayed.
+    Quiet,
+    /// Warnings are relayed as `cargo:warning` prefixed with the source file.
+    #[default]
+    Warnings,
+    /// Every command is echoed, and its whole output relayed.
+    Verbose,
+}
+
+/// What to do when nvcc warns that targeted architectures are deprecated,
+/// which usually means they are removed in the next major toolkit release.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DeprecatedArchs {
+    /// Passes `-Wno-deprecated-gpu-targets` to nvcc.
+    Silence,
+    /// Relays nvcc's warning, along with which compute caps to drop.
+    #[default]
+    Warn,
+    /// Fails the build with an [`Error::Compilation`]. Needs nvcc's output to be
+    /// captured, so it has no effect in [`Builder::single_threaded`] mode.
+    Deny,
+}
+
+/// A C++ standard, see [`Builder::cpp_std`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CppStd {
+    /// C++03, `--std=c++03`.
+    Cpp03,
+    /// C++11, `--std=c++11`.
+    Cpp11,
+    /// C++14, `--std=c++14`, supported since CUDA 9.
+    Cpp14,
+    /// C++17, `--std=c++17`, supported since CUDA 11.
+    Cpp17,
+    /// C++20, `--std=c++20`, supported since CUDA 12.
+    Cpp20,
+}
+
+impl CppStd {
+    fn name(self) -> &'static str {
+        match self {
+            CppStd::Cpp03 => "c++03",
+            CppStd::Cpp11 => "c++11",
+            CppStd::Cpp14 => "c++14",
+            CppStd::Cpp17 => "c++17",
+            CppStd::Cpp20 => "c++20",
+        }
+    }
+
+    /// The first CUDA version supporting the standard, if any is needed.
+    fn min_cuda_version(self) -> Option<Version> {
+        match self {
+            CppStd::Cpp03 | CppStd::Cpp11 => None,
+            CppStd::Cpp14 => Some((9, 0)),
+            CppStd::Cpp17 => Some((11, 0)),
+            CppStd::Cpp20 => Some((12, 0)),
+        }
+    }
+}
+
+/// The C runtime library of MSVC host code, see [`Builder::msvc_runtime`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MsvcRuntime {
+    /// The DLL runtime, `/MD`.
+    Dynamic,
+    /// The debug DLL runtime, `/MDd`.
+    DynamicDebug,
+    /// The static runtime, `/MT`.
+    Static,
+    /// The debug static runtime, `/MTd`.
+    StaticDebug,
+}
+
+impl MsvcRuntime {
+    fn flag(&self) -> &'static str {
+        match self {
+            MsvcRuntime::Dynamic => "/MD",
+            MsvcRuntime::DynamicDebug => "/MDd",
+            MsvcRuntime::Static => "/MT",
+            MsvcRuntime::StaticDebug => "/MTd",
+        }
+    }
+
+    /// Whether `arg` selects a runtime, like `-MD` or `/MT`.
+    fn is_flag(arg: &str) -> bool {
+        let flag = arg.strip_prefix(['-', '/']).unwrap_or_default();
+        ["MD", "MDd", "MT", "MTd"].contains(&flag)
+    }
+}
+
+/// The tool archiving the objects of [`Builder::build_lib`], see [`Builder::archiver`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Archiver {
+    /// `nvcc --lib`, which runs the host toolchain's archiver.
+    #[default]
+    Nvcc,
+    /// `ar`, or a compatible archiver like `llvm-ar`, given by name or path.
+    Ar(PathBuf),
+    /// MSVC's `lib.exe`, given by name or path.
+    Lib(PathBuf),
+}
+
+/// How many threads nvcc splits the compilation of a kernel across, see
+/// [`Builder::split_compile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SplitCompile {
+    /// The threads of the [`Builder::num_threads`] budget left by concurrent processes
+    /// and their [`Builder::nvcc_threads`].
+    Auto,
+    /// A fixed number of threads per kernel, fewer kernels being compiled concurrently
+    /// to stay within the budget.
+    Threads(usize),
+}
+
+/// The host compiler of a `cc::Build`, see [`Builder::host_build`].
+#[derive(Debug, Clone, Hash)]
+#[cfg_attr(not(feature = "cc"), allow(dead_code))]
+struct HostTool {
+    path: PathBuf,
+    args: Vec<OsString>,
+}
+
+/// Preprocessor defines kernels are compiled with, see [`Builder::variant`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Variant {
+    suffix: String,
+    defines: Vec<(String, String)>,
+}
+
+impl Variant {
+    fn args(&self) -> impl Iterator<Item = String> + '_ {
+        self.defines
+            .iter()
+            .map(|(name, value)| format!("-D{name}={value}"))
+    }
+}
+
+/// How a single kernel is compiled, see [`Builder::kernel`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KernelConfig {
+    path: PathBuf,
+    name: Option<String>,
+    args: Vec<String>,
+    entry_points: Vec<String>,
+}
+
+impl KernelConfig {
+    /// The kernel at `path`, named and compiled like any other until configured.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            name: None,
+            args: vec![],
+            entry_points: vec![],
+        }
+    }
+
+    /// Names the kernel `name` instead of following [`Builder::naming`], which renames
+    /// both its output file and its constant.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Passes `arg` to nvcc when compiling this kernel only, after the builder's arguments.
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// The entry points the PTX of this kernel must define, [`Builder::build_ptx`] fails
+    /// with [`Error::MissingEntryPoints`] when one of them silently disappeared. Other
+    /// builds can't verify them and panic when they are set.
+    /// ```no_run
+    /// use bindgen_cuda::{Builder, KernelConfig};
+    /// let builder = Builder::default()
+    ///     .kernel(KernelConfig::new("src/gemm.cu").entry_points(["gemm_f32", "gemm_f16"]));
+    /// ```
+    pub fn entry_points<I, S>(mut self, entry_points: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.entry_points = entry_points.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+/// One compilation of a source: its [`KernelConfig`], [`Variant`] and compute cap, if any.
+#[derive(Debug, Clone, Copy)]
+struct Instance<'a> {
+    config: Option<&'a KernelConfig>,
+    variant: Option<&'a Variant>,
+    /// The compute cap of [`Builder::ptx_per_compute_cap`].
+    compute_cap: Option<usize>,
+}
+
+impl Instance<'_> {
+    fn args(&self) -> impl Iterator<Item = String> + '_ {
+        let config = self.config.into_iter().flat_map(|c| c.args.iter().cloned());
+        config.chain(self.variant.into_iter().flat_map(Variant::args))
+    }
+}
+
+/// The compute caps to build for, see [`Builder::compute_cap_spec`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ComputeCapSpec {
+    Native,
+    All,
+    AllMajor,
+    List(Vec<usize>),
+}
+
+impl ComputeCapSpec {
+    fn parse(spec: &str) -> Option<Self> {
+        match spec.trim() {
+            "native" => Some(ComputeCapSpec::Native),
+            "all" => Some(ComputeCapSpec::All),
+            "all-major" => Some(ComputeCapSpec::AllMajor),
+            list => parse_compute_caps(list).map(ComputeCapSpec::List),
+        }
+    }
+}
+
+/// A requirement on the CUDA toolkit version, like `>=12.0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct VersionReq {
+    op: Comparison,
+    version: (usize, usize),
+}
+
+/// The comparisons a [`VersionReq`] can use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Comparison {
+    Ge,
+    Gt,
+    Le,
+    Lt,
+    Eq,
+}
+
+impl VersionReq {
+    fn parse(requirement: &str) -> Option<Self> {
+        let requirement = requirement.trim();
+        let (op, version) = [
+            (">=", Comparison::Ge),
+            ("<=", Comparison::Le),
+            (">", Comparison::Gt),
+            ("<", Comparison::Lt),
+            ("==", Comparison::Eq),
+            ("=", Comparison::Eq),
+        ]
+        .into_iter()
+        .find_map(|(prefix, op)| requirement.strip_prefix(prefix).map(|v| (op, v)))?;
+        let mut parts = version.trim().split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = match parts.next() {
+            Some(minor) => minor.parse().ok()?,
+            None => 0,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            op,
+            version: (major, minor),
+        })
+    }
+
+    fn matches(&self, version: (usize, usize)) -> bool {
+        match self.op {
+            Comparison::Ge => version >= self.version,
+            Comparison::Gt => version > self.version,
+            Comparison::Le => version <= self.version,
+            Comparison::Lt => version < self.version,
+            Comparison::Eq => version == self.version,
+        }
+    }
+}
+
+/// A hook registered with [`Builder::customize_command`].
+#[derive(Clone)]
+struct CommandHook(Arc<dyn Fn(&mut Command) + Send + Sync>);
+
+impl std::fmt::Debug for CommandHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CommandHook")
+    }
+}
+
+/// Rewrites the PTX of the kernel it is given the name of.
+type PostProcessFn = dyn Fn(&str, String) -> String + Send + Sync;
+
+/// Rewrites the source at the path it is given.
+type PreprocessFn = dyn Fn(&Path, String) -> String + Send + Sync;
+
+/// A hook registered with [`Builder::preprocess`].
+#[derive(Clone)]
+struct Preprocess(Arc<PreprocessFn>);
+
+impl std::fmt::Debug for Preprocess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Preprocess")
+    }
+}
+
+/// A hook registered with [`Builder::post_process`].
+#[derive(Clone)]
+struct PostProcess(Arc<PostProcessFn>);
+
+impl std::fmt::Debug for PostProcess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PostProcess")
+    }
+}
+
+/// A single nvcc invocation producing `output` from `input`.
+#[derive(Debug)]
+struct Job {
+    input: PathBuf,
+    output: PathBuf,
+    action: &'static str,
+    /// The command as planned, completed by [`Builder::finish_job`] when it runs.
+    command: Command,
+    /// Where the arguments go if the command line is too long, see [`Builder::response_file`].
+    response_file: (PathBuf, ResponseFile),
+    /// How many threads the command uses, see [`Builder::parallel_args`].
+    threads: usize,
+    /// Where the output is stored in the [`ArtifactCache`], if there is one.
+    cache_key: Option<CacheKey>,
+    /// How long running the command took.
+    duration: std::time::Duration,
+    /// Whether the command was killed for running past [`Builder::timeout`] or
+    /// [`Builder::build_timeout`].
+    timed_out: bool,
+}
+
+/// What a [`Job`]'s [`ArtifactCache`] key is made of, resolved only when it runs since
+/// listing the files it reads runs nvcc.
+#[derive(Debug)]
+struct CacheKey {
+    /// The toolkit, environment and arguments.
+    parts: Vec<Vec<u8>>,
+    /// `nvcc --generate-dependencies`, listing the files compiling the kernel reads.
+    dependencies: Command,
+    extension: &'static str,
+}
+
+/// The writes queued by [`Builder::write_later`] and [`Builder::create_dir_later`].
+#[derive(Debug, Default)]
+struct PendingWrites {
+    dirs: BTreeSet<PathBuf>,
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+/// The nvcc invocations a build would run, as returned by [`Builder::plan_ptx`]
+/// or [`Builder::plan_lib`].
+/// Its `Display` implementation prints one command per line, which can be
+/// copy-pasted to reproduce a build by hand.
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    commands: Vec<String>,
+    up_to_date: Vec<PathBuf>,
+}
+
+impl Plan {
+    fn new<'a>(jobs: impl IntoIterator<Item = &'a Job>, up_to_date: Vec<PathBuf>) -> Self {
+        let commands = jobs
+            .into_iter()
+            .map(|job| format!("{:?}", job.command))
+            .collect();
+        Self {
+            commands,
+            up_to_date,
+        }
+    }
+
+    /// The commands that would be executed, in order.
+    pub fn commands(&self) -> &[String] {
+        &self.commands
+    }
+
+    /// The kernels which are up to date and would not be recompiled.
+    pub fn up_to_date(&self) -> &[PathBuf] {
+        &self.up_to_date
+    }
+}
+
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for command in &self.commands {
+            writeln!(f, "{command}")?;
+        }
+        for path in &self.up_to_date {
+            writeln!(f, "# up to date: {}", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl Bindings {
+    /// Also generate a `Kernel` enum with one variant per kernel module, on top
+    /// of the `ALL_KERNELS` registry.
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default().build_ptx().unwrap();
+    /// bindings.kernel_enum(true).write("src/lib.rs").unwrap();
+    /// ```
+    pub fn kernel_enum(mut self, kernel_enum: bool) -> Self {
+        self.kernel_enum = kernel_enum;
+        self
+    }
+
+    /// Also generate a `#[cfg(test)]` module loading each PTX module with
+    /// [cudarc](https://github.com/coreylowman/cudarc/) (0.13 or newer) on the first GPU,
+    /// and resolving each of its entry points, so that PTX the driver rejects fails
+    /// `cargo test` instead of production. The tests pass without running anything
+    /// when no GPU is available. Ignored for device libraries and OptiX IR.
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default().build_ptx().unwrap();
+    /// bindings.smoke_tests(true).write("src/lib.rs").unwrap();
+    /// ```
+    /// The test of each kernel is named after it, `load_gemm` for `gemm.cu`.
+    pub fn smoke_tests(mut self, smoke_tests: bool) -> Self {
+        self.smoke_tests = smoke_tests;
+        self
+    }
+
+    /// Describes the kernels which were built, also written as JSON next to them
+    /// (`ptx-manifest.json`, `fatbin-manifest.json`...).
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default().build_ptx().unwrap();
+    /// for kernel in bindings.manifest().kernels() {
+    ///     println!("{} {:x}", kernel.name(), kernel.content_hash());
+    /// }
+    /// ```
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Nest the constants in `pub mod` blocks mirroring the directories of the kernels,
+    /// relative to their common ancestor: `src/attention/flash.cu` becomes
+    /// `attention::FLASH` instead of `FLASH`. `ALL_KERNELS` and `Kernel` stay at the top.
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default().build_ptx().unwrap();
+    /// bindings.modules(true).write("src/kernels.rs").unwrap();
+    /// ```
+    pub fn modules(mut self, modules: bool) -> Self {
+        self.modules = modules;
+        self
+    }
+
+    /// Embeds PTX compressed, as bytes, instead of as text. Every constant gets a
+    /// `kernel_ptx()` accessor decompressing it, and `ALL_KERNELS` holds the compressed
+    /// bytes. The crate including the bindings needs the matching dependency (`flate2`
+    /// or `zstd`). Device libraries and OptiX IR are left untouched.
+    /// The compressed kernels are written right away next to the uncompressed ones,
+    /// rendering the bindings then writes nothing but the bindings.
+    /// ```ignore
+    /// let bindings = bindgen_cuda::Builder::default().build_ptx().unwrap();
+    /// bindings
+    ///     .compress(bindgen_cuda::Compression::Zstd)
+    ///     .unwrap()
+    ///     .write("src/lib.rs")
+    ///     .unwrap();
+    /// ```
+    pub fn compress(mut self, compression: Compression) -> Result<Self, Error> {
+        self.compression = Some(compression);
+        if let (Some(compression), false) = (self.compression(), self.stub) {
+            self.write_compressed(compression)?;
+        }
+        Ok(self)
+    }
+
+    /// Documents every generated item, kernels with their source, the compute caps they
+    /// were compiled for and the nvcc release, so that the bindings pass `missing_docs`
+    /// in crates denying it.
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default().build_ptx().unwrap();
+    /// bindings.doc_comments(true).write("src/kernels.rs").unwrap();
+    /// ```
+    pub fn doc_comments(mut self, doc_comments: bool) -> Self {
+        self.doc_comments = doc_comments;
+        self
+    }
+
+    /// Starts the bindings with `#![allow(non_upper_case_globals, missing_docs)]`.
+    /// Inner attributes are only valid at the top of a module, the file must then be
+    /// declared with `mod` rather than pulled in with `include!`.
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default().build_ptx().unwrap();
+    /// bindings.allow_lints(true).write("src/kernels.rs").unwrap();
+    /// ```
+    pub fn allow_lints(mut self, allow_lints: bool) -> Self {
+        self.allow_lints = allow_lints;
+        self
+    }
+
+    /// Formats the bindings with `rustfmt` (or the one `RUSTFMT` names), so that
+    /// `cargo fmt --check` passes on a committed file. They are written unformatted,
+    /// with a warning, when `rustfmt` cannot be run.
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default().build_ptx().unwrap();
+    /// bindings.rustfmt(true).write("src/kernels.rs").unwrap();
+    /// ```
+    pub fn rustfmt(mut self, rustfmt: bool) -> Self {
+        self.rustfmt = rustfmt;
+        self
+    }
+
+    /// The doc comment of a generated item, empty unless [`Bindings::doc_comments`] is set.
+    fn doc(&self, text: &str) -> String {
+        match self.doc_comments {
+            true => format!("/// {text}\n"),
+            false => String::new(),
+        }
+    }
+
+    /// The doc comment of the `i`th kernel.
+    fn kernel_doc(&self, i: usize) -> String {
+        let source = self.paths[i].to_string_lossy().replace('\\', "/");
+        if self.kind == Output::Nvrtc {
+            return self.doc(&format!("`{source}`, compiled at runtime with NVRTC."));
+        }
+        let mut text = format!("Compiled from `{source}`");
+        // Stubs have no manifest.
+        if let Some(kernel) = self.manifest.kernels.get(i) {
+            let compute_caps = kernel
+                .compute_caps
+                .iter()
+                .map(|c| format!("sm_{c}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            if !compute_caps.is_empty() {
+                text.push_str(&format!(" for {compute_caps}"));
+            }
+        }
+        if let Some(version) = &self.manifest.nvcc_version {
+            text.push_str(&format!(" by nvcc {version}"));
+        }
+        text.push('.');
+        self.doc(&text)
+    }
+
+    /// The compression applied to the kernels, only PTX is compressed.
+    fn compression(&self) -> Option<Compression> {
+        match self.kind {
+            Output::Ptx => self.compression,
+            Output::DeviceLib(_) | Output::OptixIr | Output::Nvrtc => None,
+        }
+    }
+
+    /// Compresses every kernel next to it, rewriting only the ones which changed.
+    fn write_compressed(&self, compression: Compression) -> Result<(), Error> {
+        for output in &self.outputs {
+            let output = self.out_dir.join(output);
+            let ptx = std::fs::read(&output)
+                .map_err(|e| Error::Io(format!("read {}: {e}", output.display())))?;
+            let compressed = compression.compress(&ptx);
+            let mut path = output.into_os_string();
+            path.push(".");
+            path.push(compression.extension());
+            let path = PathBuf::from(path);
+            if std::fs::read(&path).ok().as_ref() != Some(&compressed) {
+                std::fs::write(&path, compressed)
+                    .map_err(|e| Error::Io(format!("write {}: {e}", path.display())))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The module of each kernel, empty unless [`Bindings::modules`] is set.
+    fn kernel_modules(&self) -> Vec<Vec<String>> {
+        if !self.modules {
+            return vec![vec![]; self.paths.len()];
+        }
+        let dirs: Vec<Vec<_>> = self
+            .paths
+            .iter()
+            .map(|p| {
+                p.parent()
+                    .map(|d| d.components().map(|c| c.as_os_str()).collect())
+                    .unwrap_or_default()
+            })
+            .collect();
+        let common = dirs
+            .iter()
+            .map(|d| d.iter().zip(&dirs[0]).take_while(|(a, b)| a == b).count())
+            .min()
+            .unwrap_or(0);
+        dirs.iter()
+            .map(|d| {
+                d[common..]
+                    .iter()
+                    .map(|c| module_name(&c.to_string_lossy()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Writes a helper rust file that will include the PTX sources as
+    /// `const KERNEL_NAME` making it easier to interact with the PTX sources.
+    /// Device libraries and OptiX IR are included as bytes, the former along with a
+    /// `const KERNEL_NAME_DEVICE_FUNCTIONS` listing their exported functions.
+    /// A `const ALL_KERNELS` slice of `(module_name, source)` pairs is also generated
+    /// so all modules can be loaded without maintaining a list by hand.
+    /// The file is only rewritten when its content changes.
+    pub fn write<P>(&self, out: P) -> Result<(), Error>
+    where
+        P: AsRef<Path>,
+    {
+        let content = self.render()?;
+        // Leave the file untouched when nothing changed, rewriting it would
+        // trigger rebuilds of everything depending on it.
+        let out = out.as_ref();
+        if std::fs::read_to_string(out).ok().as_deref() != Some(content.as_str()) {
+            std::fs::write(out, content)
+                .map_err(|e| Error::Io(format!("write bindings to {}: {e}", out.display())))?;
+        }
+        Ok(())
+    }
+
+    /// The code [`Bindings::write`] writes, to post-process it before writing it
+    /// elsewhere.
+    /// ```no_run
+    /// let bindings = bindgen_cuda::Builder::default().build_ptx().unwrap();
+    /// let code = bindings.render().unwrap();
+    /// std::fs::write("src/kernels.rs", format!("// @generated\n{code}")).unwrap();
+    /// ```
+    pub fn render(&self) -> Result<String, Error> {
+        self.naming
+            .check(&self.names, &self.paths, &self.kernel_modules(), self.kind)?;
+        let content = self.generate();
+        if !self.rustfmt {
+            return Ok(content);
+        }
+        Ok(rustfmt(&content).unwrap_or_else(|e| {
+            println!("cargo:warning=bindings left unformatted, {e}");
+            content
+        }))
+    }
+
+    /// Writes the code [`Bindings::write`] writes to `writer`, which is always written to.
+    /// ```no_run
+    /// use std::io::Write;
+    /// let bindings = bindgen_cuda::Builder::default().build_ptx().unwrap();
+    /// let mut module = std::fs::File::create("src/generated.rs").unwrap();
+    /// writeln!(module, "pub mod kernels {{").unwrap();
+    /// bindings.write_to(&mut module).unwrap();
+    /// writeln!(module, "}}").unwrap();
+    /// ```
+    pub fn write_to<W: std::io::Write>(&self, mut writer: W) -> Result<(), Error> {
+        let content = self.render()?;
+        writer
+            .write_all(content.as_bytes())
+            .map_err(|e| Error::Io(format!("write bindings: {e}")))
+    }
+
+    /// The content of the bindings file.
+    fn generate(&self) -> String {
+        let compression = self.compression();
+        let source_type = match (self.kind, compression) {
+            (Output::Ptx, None) => "&str",
+            (Output::Nvrtc, _) => "NvrtcKernel",
+            _ => "&[u8]",
+        };
+        let mut content = String::new();
+        if self.allow_lints {
+            content.push_str("#![allow(non_upper_case_globals, missing_docs)]\n");
+        }
+        content.push_str(&self.doc("Version of the layout of these bindings."));
+        content.push_str(&format!(
+            "pub const BINDINGS_VERSION: u32 = {BINDINGS_VERSION};\n"
+        ));
+        if self.kind == Output::Nvrtc {
+            content.push_str(NVRTC_KERNEL);
+        }
+        if let Some(compression) = compression {
+            content.push_str(&self.doc("Decompresses an embedded PTX module."));
+            content.push_str(&format!(
+                "pub fn decompress(bytes: &[u8]) -> String {{\n{}}}\n",
+                compression.decompressor()
+            ));
+        }
+        let mut kernels = vec![];
+        let mut modules = BTreeMap::<Vec<String>, String>::new();
+        let kernel_modules = self.kernel_modules();
+        let allow = match self.naming.case {
+            NameCase::Preserve => "#[allow(non_upper_case_globals)]\n",
+            _ => "",
+        };
+        for (i, name) in self.names.iter().enumerate() {
+            let const_name = self.naming.const_name(name);
+            let source_type = match self.kind {
+                Output::Nvrtc => {
+                    format!("{}NvrtcKernel", "super::".repeat(kernel_modules[i].len()))
+                }
+                _ => source_type.to_string(),
+            };
+            let output = &self.outputs[i];
+            let module = &kernel_modules[i];
+            let items = modules.entry(module.clone()).or_default();
+            let (include, placeholder, output) = match (self.kind, compression) {
+                (Output::Ptx, None) => ("include_str", r#""""#, output.clone()),
+                (Output::Ptx, Some(compression)) => (
+                    "include_bytes",
+                    "&[]",
+                    format!("{output}.{}", compression.extension()),
+                ),
+                (Output::DeviceLib(_) | Output::OptixIr, _) => {
+                    ("include_bytes", "&[]", output.clone())
+                }
+                (Output::Nvrtc, _) => ("include_str", "", output.clone()),
+            };
+            let mut source = format!(r#"{include}!(concat!(env!("OUT_DIR"), "/{output}"))"#);
+            if self.kind == Output::Nvrtc {
+                let options = self.nvrtc_options[i]
+                    .iter()
+                    .map(|o| format!("{o:?}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                source = format!(
+                    "{}NvrtcKernel {{\n    name: {name:?},\n    source: {source},\n    options: &[{options}],\n}}",
+                    "super::".repeat(module.len())
+                );
+            }
+            let doc = self.kernel_doc(i);
+            if self.stub {
+                items.push_str(&format!(
+                    "#[cfg(not(cuda_stub))]\n{doc}{allow}pub const {const_name}: {source_type} = {source};\n\
+                     #[cfg(cuda_stub)]\n{doc}{allow}pub const {const_name}: {source_type} = {placeholder};"
+                ));
+            } else {
+                items.push_str(&format!(
+                    "{doc}{allow}pub const {const_name}: {source_type} = {source};"
+                ));
+            }
+            if compression.is_some() {
+                let decompress = "super::".repeat(module.len());
+                let doc = self.doc(&format!("The decompressed PTX of [`{const_name}`]."));
+                items.push_str(&format!(
+                    "\n{doc}pub fn {}_ptx() -> String {{\n    {decompress}decompress({const_name})\n}}",
+                    const_name.to_lowercase()
+                ));
+            }
+            if self.kind == Output::Ptx {
+                let entry_points = self.entry_points[i]
+                    .iter()
+                    .map(|e| format!("{e:?}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let doc = self.doc(&format!("The kernels of [`{const_name}`]."));
+                items.push_str(&format!(
+                    "\n{doc}{allow}pub const {const_name}_KERNELS: &[&str] = &[{entry_points}];"
+                ));
+            }
+            if let Output::DeviceLib(_) = self.kind {
+                let functions = self.device_functions[i]
+                    .iter()
+                    .map(|f| format!("{f:?}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let doc = self.doc(&format!(
+                    "The `extern \"C\"` device functions exported by [`{const_name}`]."
+                ));
+                items.push_str(&format!(
+                    "\n{doc}{allow}pub const {const_name}_DEVICE_FUNCTIONS: &[&str] = &[{functions}];"
+                ));
+            }
+            items.push('\n');
+            let path = module
+                .iter()
+                .chain([&const_name])
+                .cloned()
+                .collect::<Vec<_>>()
+                .join("::");
+            kernels.push((name, path));
+        }
+        // Kernels built per compute cap get a function selecting the newest the device runs.
+        let mut selectors = BTreeMap::<(Vec<String>, String), Vec<(usize, String)>>::new();
+        for (i, name) in self.names.iter().enumerate() {
+            let Some(compute_cap) = self.compute_caps[i] else {
+                continue;
+            };
+            let base = name
+                .strip_suffix(&format!("_sm{compute_cap}"))
+                .unwrap_or(name);
+            selectors
+                .entry((kernel_modules[i].clone(), base.to_string()))
+                .or_default()
+                .push((compute_cap, self.naming.const_name(name)));
+        }
+        for ((module, base), mut compute_caps) in selectors {
+            compute_caps.sort_by_key(|(compute_cap, _)| std::cmp::Reverse(*compute_cap));
+            let return_type = match compression {
+                Some(_) => "String",
+                None => "&'static str",
+            };
+            let arms: String = compute_caps
+                .iter()
+                .map(|(compute_cap, const_name)| {
+                    let ptx = match compression {
+                        Some(_) => format!("{}_ptx()", const_name.to_lowercase()),
+                        None => const_name.clone(),
+                    };
+                    format!("        {compute_cap}.. => Some({ptx}),\n")
+                })
+                .collect();
+            let function = self.naming.const_name(&base).to_lowercase();
+            let doc = self.doc(&format!(
+                "The PTX of `{base}` for the newest compute cap up to `compute_cap`, if any."
+            ));
+            modules.entry(module).or_default().push_str(&format!(
+                "{doc}pub fn {function}_ptx(compute_cap: u32) -> Option<{return_type}> {{\n    match compute_cap {{\n{arms}        _ => None,\n    }}\n}}\n"
+            ));
+        }
+        content.push_str(&render_modules(&modules, self.doc_comments));
+
+        let all_kernels = kernels
+            .iter()
+            .map(|(name, const_name)| format!("({name:?}, {const_name})"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        content.push_str(&self.doc("Every kernel, as `(name, source)` pairs."));
+        content.push_str(&format!(
+            "pub const ALL_KERNELS: &[(&str, {source_type})] = &[{all_kernels}];\n"
+        ));
+
+        if self.kernel_enum {
+            let variants: Vec<_> = kernels
+                .iter()
+                .map(|(name, const_name)| (variant_name(name), name, const_name))
+                .collect();
+            let doc = |text: &str| self.doc(text).replace("///", "    ///");
+            content.push_str(&self.doc("The kernels, one variant each."));
+            content.push_str("#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]\n");
+            content.push_str("pub enum Kernel {\n");
+            for (variant, name, _) in &variants {
+                content.push_str(&doc(&format!("The `{name}` kernel.")));
+                content.push_str(&format!("    {variant},\n"));
+            }
+            content.push_str("}\n");
+            content.push_str("impl Kernel {\n");
+            let all = variants
+                .iter()
+                .map(|(variant, _, _)| format!("Kernel::{variant}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            content.push_str(&doc("Every kernel."));
+            content.push_str(&format!(
+                "    pub const ALL: &'static [Kernel] = &[{all}];\n"
+            ));
+            content.push_str(&doc("The name of the kernel, as in `ALL_KERNELS`."));
+            content.push_str("    pub fn name(&self) -> &'static str {\n        match self {\n");
+            for (variant, name, _) in &variants {
+                content.push_str(&format!("            Kernel::{variant} => {name:?},\n"));
+            }
+            content.push_str("        }\n    }\n");
+            content.push_str(&doc("The source of the kernel, as in `ALL_KERNELS`."));
+            content.push_str(&format!(
+                "    pub fn source(&self) -> {} {{\n        match self {{\n",
+                source_type.replace('&', "&'static ")
+            ));
+            for (variant, _, const_name) in &variants {
+                content.push_str(&format!("            Kernel::{variant} => {const_name},\n"));
+            }
+            content.push_str("        }\n    }\n");
+            if compression.is_some() {
+                content.push_str(&doc("The decompressed PTX of the kernel."));
+                content.push_str(
+                    "    pub fn ptx(&self) -> String {\n        decompress(self.source())\n    }\n",
+                );
+            }
+            content.push_str("}\n");
+        }
+        if self.smoke_tests && self.kind == Output::Ptx {
+            content.push_str(&self.generate_smoke_tests(&kernels));
+        }
+        content
+    }
+
+    /// The module of [`Bindings::smoke_tests`], given the name and constant path of
+    /// each kernel.
+    fn generate_smoke_tests(&self, kernels: &[(&String, String)]) -> String {
+        let mut content = "#[cfg(all(test, not(cuda_stub)))]\nmod smoke_tests {\n".to_string();
+        // cudarc panics when the driver library cannot be loaded.
+        content.push_str(concat!(
+            "    fn load(ptx: &str, entry_points: &[&str]) {\n",
+            "        let ctx = std::panic::catch_unwind(|| cudarc::driver::CudaContext::new(0));\n",
+            "        let Ok(Ok(ctx)) = ctx else {\n",
+            "            eprintln!(\"no CUDA device, skipping\");\n",
+            "            return;\n",
+            "        };\n",
+            "        let module = ctx\n",
+            "            .load_module(cudarc::nvrtc::Ptx::from_src(ptx))\n",
+            "            .expect(\"PTX to load\");\n",
+            "        for entry_point in entry_points {\n",
+            "            if let Err(e) = module.load_function(entry_point) {\n",
+            "                panic!(\"{entry_point}: {e}\");\n",
+            "            }\n",
+            "        }\n",
+            "    }\n",
+        ));
+        for ((_, const_path), entry_points) in kernels.iter().zip(&self.entry_points) {
+            let entry_points = entry_points
+                .iter()
+                .map(|e| format!("{e:?}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            // Prefixed, so kernels named like the helper or like keywords get valid tests.
+            let test = format!("load_{}", const_path.to_lowercase().replace("::", "_"));
+            let ptx = match self.compression() {
+                Some(_) => format!("&super::{}_ptx()", const_path.to_lowercase()),
+                None => format!("super::{const_path}"),
+            };
+            content.push_str(&format!(
+                "    #[test]\n    fn {test}() {{\n        load({ptx}, &[{entry_points}]);\n    }}\n"
+            ));
+        }
+        content.push_str("}\n");
+        content
+    }
+}
+
+/// Metadata of a build, see [`Bindings::manifest`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    nvcc_version: Option<String>,
+    kernels: Vec<ManifestEntry>,
+}
+
+/// Metadata of a single built kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    name: String,
+    source: PathBuf,
+    output: PathBuf,
+    compute_caps: Vec<usize>,
+    flags: Vec<String>,
+    content_hash: u64,
+    intermediates: Vec<PathBuf>,
+}
+
+impl Manifest {
+    /// The release of `nvcc` (`"12.4"`), if it could be determined.
+    pub fn nvcc_version(&self) -> Option<&str> {
+        self.nvcc_version.as_deref()
+    }
+
+    /// The built kernels.
+    pub fn kernels(&self) -> &[ManifestEntry] {
+        &self.kernels
+    }
+
+    /// The manifest as a JSON document.
+    pub fn to_json(&self) -> String {
+        let nvcc_version = match &self.nvcc_version {
+            Some(version) => json_string(version),
+            None => "null".to_string(),
+        };
+        let kernels = self
+            .kernels
+            .iter()
+            .map(|kernel| {
+                let list = |items: Vec<String>| format!("[{}]", items.join(", "));
+                format!(
+                    "    {{\"name\": {}, \"source\": {}, \"output\": {}, \"compute_caps\": {}, \"flags\": {}, \"content_hash\": \"{:016x}\", \"intermediates\": {}}}",
+                    json_string(&kernel.name),
+                    json_string(&kernel.source.to_string_lossy()),
+                    json_string(&kernel.output.to_string_lossy()),
+                    list(kernel.compute_caps.iter().map(|c| c.to_string()).collect()),
+                    list(kernel.flags.iter().map(|f| json_string(f)).collect()),
+                    kernel.content_hash,
+                    list(
+                        kernel
+                            .intermediates
+                            .iter()
+                            .map(|p| json_string(&p.to_string_lossy()))
+                            .collect()
+                    ),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",\n");
+        format!("{{\n  \"nvcc_version\": {nvcc_version},\n  \"kernels\": [\n{kernels}\n  ]\n}}\n")
+    }
+
+    /// Reads back a manifest written by [`Manifest::to_json`].
+    fn from_json(json: &str) -> Option<Self> {
+        let json = json::parse(json)?;
+        let paths = |value: &json::Json| -> Option<Vec<PathBuf>> {
+            value
+                .as_array()?
+                .iter()
+                .map(|p| p.as_str().map(PathBuf::from))
+                .collect()
+        };
+        let kernels = json
+            .get("kernels")?
+            .as_array()?
+            .iter()
+            .map(|kernel| {
+                Some(ManifestEntry {
+                    name: kernel.get("name")?.as_str()?.to_string(),
+                    source: kernel.get("source")?.as_str()?.into(),
+                    output: kernel.get("output")?.as_str()?.into(),
+                    compute_caps: kernel
+                        .get("compute_caps")?
+                        .as_array()?
+                        .iter()
+                        .map(|c| c.as_number())
+                        .collect::<Option<_>>()?,
+                    flags: kernel
+                        .get("flags")?
+                        .as_array()?
+                        .iter()
+                        .map(|f| f.as_str().map(str::to_string))
+                        .collect::<Option<_>>()?,
+                    content_hash: u64::from_str_radix(kernel.get("content_hash")?.as_str()?, 16)
+                        .ok()?,
+                    intermediates: paths(kernel.get("intermediates")?)?,
+                })
+            })
+            .collect::<Option<_>>()?;
+        Some(Self {
+            nvcc_version: json.get("nvcc_version")?.as_str().map(str::to_string),
+            kernels,
+        })
+    }
+}
+
+impl ManifestEntry {
+    /// The kernel name, as in `ALL_KERNELS`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The kernel source.
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    /// The compiled kernel.
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    /// The compute caps the kernel was compiled for.
+    pub fn compute_caps(&self) -> &[usize] {
+        &self.compute_caps
+    }
+
+    /// The arguments nvcc was run with.
+    pub fn flags(&self) -> &[String] {
+        &self.flags
+    }
+
+    /// FNV-1a hash of the compiled kernel, stable across platforms and releases.
+    pub fn content_hash(&self) -> u64 {
+        self.content_hash
+    }
+
+    /// The intermediate files kept by [`Builder::keep_intermediates`].
+    pub fn intermediates(&self) -> &[PathBuf] {
+        &self.intermediates
+    }
+}
+
+/// Quotes `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// 64 bits FNV-1a hash, unlike `DefaultHasher` it is stable across Rust releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// How kernels are named. By default a kernel is named after its file stem,
+/// and its constant is the uppercased name: `src/reduce.cu` becomes `REDUCE`.
+#[derive(Debug, Clone, Default, Hash)]
+pub struct Naming {
+    prefix: String,
+    case: NameCase,
+    parent_dir: bool,
+}
+
+/// Case of the generated constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum NameCase {
+    /// Uppercases the name: `flashAttn.v2` becomes `FLASHATTN_V2`.
+    #[default]
+    Upper,
+    /// Also splits camel case words: `flashAttn.v2` becomes `FLASH_ATTN_V2`.
+    ScreamingSnake,
+    /// Keeps the name as is: `flashAttn.v2` becomes `flashAttn_v2`.
+    Preserve,
+}
+
+impl Naming {
+    /// Prefixes every constant with `prefix`.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Sets the case of the constants.
+    pub fn case(mut self, case: NameCase) -> Self {
+        self.case = case;
+        self
+    }
+
+    /// Prefixes kernel names with their parent directory, `src/a/reduce.cu` becomes `a_reduce`.
+    pub fn parent_dir(mut self, parent_dir: bool) -> Self {
+        self.parent_dir = parent_dir;
+        self
+    }
+
+    /// The name of a kernel, used for its output file and in `ALL_KERNELS`.
+    fn kernel_name(&self, kernel_path: &Path) -> String {
+        let stem = kernel_path
+            .file_stem()
+            .expect("kernel to have stem")
+            .to_string_lossy();
+        let parent = kernel_path
+            .parent()
+            .and_then(|p| p.file_name())
+            .map(|p| p.to_string_lossy());
+        match parent {
+            Some(parent) if self.parent_dir => format!("{parent}_{stem}"),
+            _ => stem.to_string(),
+        }
+    }
+
+    /// The constant holding a kernel named `name`.
+    fn const_name(&self, name: &str) -> String {
+        let name = format!("{}{name}", self.prefix);
+        let identifier = |c: char| if c.is_ascii_alphanumeric() { c } else { '_' };
+        match self.case {
+            NameCase::Upper => name.to_uppercase().chars().map(identifier).collect(),
+            NameCase::ScreamingSnake => {
+                let mut constant = String::new();
+                let mut previous_lower = false;
+                for c in name.chars().map(identifier) {
+                    if c.is_ascii_uppercase() && previous_lower {
+                        constant.push('_');
                     }
-                    command.arg(p);
-                    Some((p, format!("{command:?}"), command.spawn()
-                        .expect("nvcc failed to start. Ensure that you have CUDA installed and that `nvcc` is in your PATH.").wait_with_output()))
+                    previous_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+                    constant.push(c.to_ascii_uppercase());
                 }
+                constant
+            }
+            NameCase::Preserve => name.chars().map(identifier).collect(),
+        }
+    }
+
+    /// Fails if two kernels of the same module share a constant, including the ones
+    /// generated alongside each kernel of `kind`, `CUDA_KERNELS` for `cuda.cu` clashes
+    /// with the kernel `cuda_kernels.cu`.
+    fn check(
+        &self,
+        names: &[String],
+        kernel_paths: &[PathBuf],
+        modules: &[Vec<String>],
+        kind: Output,
+    ) -> Result<(), Error> {
+        let suffixes: &[&str] = match kind {
+            Output::Ptx => &["", "_KERNELS"],
+            Output::DeviceLib(_) => &["", "_DEVICE_FUNCTIONS"],
+            _ => &[""],
+        };
+        let kernels = names.iter().zip(kernel_paths).zip(modules);
+        check_collisions(kernels.flat_map(|((name, path), module)| {
+            let const_name = self.const_name(name);
+            suffixes.iter().map(move |suffix| {
+                let const_path = module
+                    .iter()
+                    .cloned()
+                    .chain([format!("{const_name}{suffix}")])
+                    .collect::<Vec<_>>()
+                    .join("::");
+                (const_path, path.clone())
             })
-            .collect::<Vec<_>>();
+        }))
+    }
+}
 
-        let ptx_paths: Vec<PathBuf> = glob::glob(&format!("{0}/**/*.ptx", out_dir.display()))
-            .expect("valid glob")
-            .map(|p| p.expect("valid path for PTX"))
-            .collect();
-        // We should rewrite `src/lib.rs` only if there are some newly compiled kernels, or removed
-        // some old ones
-        let write = !children.is_empty() || self.kernel_paths.len() < ptx_paths.len();
-        for (kernel_path, command, child) in children {
-            let output = child.expect("nvcc failed to run. Ensure that you have CUDA installed and that `nvcc` is in your PATH.");
-            assert!(
-                output.status.success(),
-                "nvcc error while compiling {kernel_path:?}:\n\n# CLI {command} \n\n# stdout\n{:#}\n\n# stderr\n{:#}",
-                String::from_utf8_lossy(&output.stdout),
-                String::from_utf8_lossy(&output.stderr)
-            );
+/// Fails if several kernels map to the same name.
+fn check_collisions(names: impl Iterator<Item = (String, PathBuf)>) -> Result<(), Error> {
+    let mut kernels: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for (name, path) in names {
+        kernels.entry(name).or_default().push(path);
+    }
+    let mut collisions: Vec<_> = kernels
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .collect();
+    collisions.sort();
+    match collisions.into_iter().next() {
+        Some((name, paths)) => Err(Error::NameCollision { name, paths }),
+        None => Ok(()),
+    }
+}
+
+/// Renders the constants of each module, nesting them in `pub mod` blocks.
+fn render_modules(modules: &BTreeMap<Vec<String>, String>, doc_comments: bool) -> String {
+    let mut content = String::new();
+    let mut open: Vec<&String> = vec![];
+    for (module, items) in modules {
+        let common = open
+            .iter()
+            .zip(module)
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        while open.len() > common {
+            open.pop();
+            content.push_str(&format!("{}}}\n", "    ".repeat(open.len())));
         }
-        Ok(Bindings {
-            write,
-            paths: self.kernel_paths,
+        for name in &module[common..] {
+            let indent = "    ".repeat(open.len());
+            if doc_comments {
+                content.push_str(&format!("{indent}/// The kernels under `{name}`.\n"));
+            }
+            content.push_str(&format!("{indent}pub mod {name} {{\n"));
+            open.push(name);
+        }
+        let indent = "    ".repeat(open.len());
+        for line in items.lines() {
+            content.push_str(&format!("{indent}{line}\n"));
+        }
+    }
+    while !open.is_empty() {
+        open.pop();
+        content.push_str(&format!("{}}}\n", "    ".repeat(open.len())));
+    }
+    content
+}
+
+/// Formats generated `code` with `rustfmt`, or the one `RUSTFMT` names.
+fn rustfmt(code: &str) -> Result<String, String> {
+    println!("cargo:rerun-if-env-changed=RUSTFMT");
+    let program = std::env::var_os("RUSTFMT").unwrap_or_else(|| "rustfmt".into());
+    let mut child = Command::new(&program)
+        .args(["--edition", "2021"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot run {}: {e}", Path::new(&program).display()))?;
+    // rustfmt reads its whole input before writing anything, the pipes cannot fill up.
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    std::io::Write::write_all(&mut stdin, code.as_bytes())
+        .map_err(|e| format!("cannot write to rustfmt: {e}"))?;
+    drop(stdin);
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("rustfmt failed: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "rustfmt failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|e| format!("rustfmt output: {e}"))
+}
+
+/// Turns a directory name into a valid module name.
+fn module_name(dir: &str) -> String {
+    let name: String = dir
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
         })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{name}")
+    } else {
+        name
     }
 }
 
-impl Bindings {
-    /// Writes a helper rust file that will include the PTX sources as
-    /// `const KERNEL_NAME` making it easier to interact with the PTX sources.
-    pub fn write<P>(&self, out: P) -> Result<(), Error>
-    where
-        P: AsRef<Path>,
-    {
-        if self.write {
-            let mut file = std::fs::File::create(out).expect("Create lib in {out}");
-            for kernel_path in &self.paths {
-                let name = kernel_path
-                    .file_stem()
-                    .expect("kernel to have stem")
-                    .to_str()
-                    .expect("kernel path to be valid");
-                file.write_all(
-                format!(
-                    r#"pub const {}: &str = include_str!(concat!(env!("OUT_DIR"), "/{}.ptx"));"#,
-                    name.to_uppercase().replace('.', "_"),
-                    name
-                )
-                .as_bytes(),
-                )
-                .expect("write to {out}");
-                file.write_all(&[b'\n']).expect("write to {out}");
+/// Turns a kernel file stem like `flash_attn.v2` into a `FlashAttnV2` enum variant.
+fn variant_name(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/// Builds the thread pool used by a single build, rather than configuring rayon's
+/// global pool which other crates may rely on.
+fn thread_pool(num_threads: usize) -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .build()
+        .expect("build rayon threadpool")
+}
+
+/// Limits the number of concurrent nvcc processes with cargo's jobserver.
+#[derive(Debug)]
+struct JobServer {
+    client: Option<jobserver::Client>,
+    /// Whether the token implicitly owned by this process is available.
+    implicit: AtomicBool,
+}
+
+/// A token allowing to run one nvcc process, released on drop.
+enum JobToken<'a> {
+    Implicit(&'a AtomicBool),
+    #[allow(unused)]
+    Acquired(jobserver::Acquired),
+}
+
+impl Drop for JobToken<'_> {
+    fn drop(&mut self) {
+        if let JobToken::Implicit(implicit) = self {
+            implicit.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+impl JobServer {
+    fn from_env() -> Self {
+        // SAFETY: the jobserver file descriptors are inherited from cargo, and
+        // nothing else in the build script is expected to close them.
+        let client = unsafe { jobserver::Client::from_env() };
+        Self {
+            client,
+            implicit: AtomicBool::new(true),
+        }
+    }
+
+    /// Waits for a token, or returns `None` when there is no jobserver to coordinate with.
+    fn acquire(&self) -> Option<JobToken<'_>> {
+        let client = self.client.as_ref()?;
+        loop {
+            if self.implicit.swap(false, Ordering::SeqCst) {
+                return Some(JobToken::Implicit(&self.implicit));
+            }
+            // Never block on the jobserver, the implicit token might be released
+            // in the meantime and nobody else would give us a token back.
+            match client.try_acquire() {
+                Ok(Some(acquired)) => return Some(JobToken::Acquired(acquired)),
+                Ok(None) => std::thread::sleep(std::time::Duration::from_millis(10)),
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+/// Maximum command line length before falling back to a response file.
+/// `cmd.exe` limits command lines to 8191 characters, nvcc forwards its
+/// arguments to `cl.exe` through it on Windows.
+const MAX_COMMAND_LINE: usize = if cfg!(windows) { 8000 } else { 128 * 1024 };
+
+/// The [`Builder::keep_intermediates`] group of the objects archived into `out_file`.
+fn intermediates_group(out_file: &Path) -> String {
+    out_file.file_stem().map_or("lib".to_string(), |stem| {
+        stem.to_string_lossy().into_owned()
+    })
+}
+
+/// Waits for `child` like `wait_with_output`, relaying each line it prints to stderr
+/// right away when `relay` is set, prefixed with the `input` it compiles. It is killed
+/// once `deadline` passes, which the returned flag tells.
+fn wait(
+    child: std::process::Child,
+    input: &Path,
+    relay: bool,
+    deadline: Option<std::time::Instant>,
+) -> std::io::Result<(std::process::Output, bool)> {
+    let mut child = KillOnDrop {
+        child,
+        tree: deadline.is_some(),
+    };
+    let child = &mut child.child;
+    let drain = |pipe: &mut dyn std::io::Read| -> std::io::Result<Vec<u8>> {
+        let mut reader = std::io::BufReader::new(pipe);
+        let mut content = vec![];
+        let mut line = vec![];
+        while std::io::BufRead::read_until(&mut reader, b'\n', &mut line)? > 0 {
+            if relay {
+                let text = String::from_utf8_lossy(&line);
+                eprintln!("{}: {}", input.display(), text.trim_end());
+            }
+            content.append(&mut line);
+        }
+        Ok(content)
+    };
+    let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+    // Both pipes are drained while waiting, nvcc could block on a full one otherwise.
+    std::thread::scope(|scope| {
+        let stdout = stdout.map(|mut pipe| scope.spawn(move || drain(&mut pipe)));
+        let stderr = stderr.map(|mut pipe| scope.spawn(move || drain(&mut pipe)));
+        let mut timed_out = false;
+        let status = match deadline {
+            None => child.wait()?,
+            Some(deadline) => loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if std::time::Instant::now() >= deadline {
+                    kill_tree(child);
+                    timed_out = true;
+                    break child.wait()?;
+                }
+                std::thread::sleep(WAIT_POLL_INTERVAL);
+            },
+        };
+        let join = |thread: Option<std::thread::ScopedJoinHandle<'_, _>>| {
+            thread
+                .map(|thread| thread.join().expect("output relay not to panic"))
+                .transpose()
+        };
+        let output = std::process::Output {
+            status,
+            stdout: join(stdout)?.unwrap_or_default(),
+            stderr: join(stderr)?.unwrap_or_default(),
+        };
+        Ok((output, timed_out))
+    })
+}
+
+/// How often commands with a deadline are checked on, see [`Builder::timeout`].
+const WAIT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// A child process killed when it is dropped before exiting, so that nvcc doesn't
+/// outlive a build which gave up on it. Along with the processes it started when
+/// `tree` is set, as for commands with a deadline.
+struct KillOnDrop {
+    child: std::process::Child,
+    tree: bool,
+}
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        if !self.child.try_wait().is_ok_and(|status| status.is_none()) {
+            return;
+        }
+        if self.tree {
+            kill_tree(&mut self.child);
+        } else {
+            let _ = self.child.kill();
+        }
+    }
+}
+
+/// Kills `child` and the processes it started, nvcc running cicc and ptxas: its
+/// process group on Unix, where [`Job::run`] gives it one, its process tree on Windows.
+fn kill_tree(child: &mut std::process::Child) {
+    #[cfg(unix)]
+    let killed = Command::new("kill")
+        .args(["-KILL", "--", &format!("-{}", child.id())])
+        .output()
+        .is_ok_and(|output| output.status.success());
+    #[cfg(windows)]
+    let killed = Command::new("taskkill")
+        .args(["/T", "/F", "/PID", &child.id().to_string()])
+        .output()
+        .is_ok_and(|output| output.status.success());
+    #[cfg(not(any(unix, windows)))]
+    let killed = false;
+    if !killed {
+        println!(
+            "cargo:warning=could not kill the processes started by nvcc (pid {}), they may keep running",
+            child.id()
+        );
+        let _ = child.kill();
+    }
+}
+
+/// Splits `CUDA_NVCC_FLAGS` on whitespace and on `;`, like CMake lists, except
+/// within single or double quotes, which are removed.
+fn split_flags(flags: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut arg: Option<String> = None;
+    let mut quote = None;
+    for c in flags.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => arg.get_or_insert_default().push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                arg.get_or_insert_default();
+            }
+            None if c.is_whitespace() || c == ';' => args.extend(arg.take()),
+            None => arg.get_or_insert_default().push(c),
+        }
+    }
+    args.extend(arg);
+    args
+}
+
+/// Forwards `flag` to the tool behind `option` (`-Xcompiler`, `-Xptxas`...), which
+/// takes a comma separated list unless the flag is quoted.
+fn forward_flag(option: &str, flag: &str) -> String {
+    if flag.contains(',') {
+        format!("{option}=\"{}\"", flag.replace('"', "\\\""))
+    } else {
+        format!("{option}={flag}")
+    }
+}
+
+/// Quotes an argument for an nvcc options file, where arguments are split on
+/// whitespace like on a command line.
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || c == '"') {
+        return arg.to_string();
+    }
+    format!("\"{}\"", arg.replace('"', "\\\""))
+}
+
+/// Runs `command` through `wrapper`, called with `args` before nvcc's own command line.
+fn wrap_command(command: Command, wrapper: &str, args: &[String]) -> Command {
+    let mut wrapped = Command::new(wrapper);
+    wrapped
+        .args(args)
+        .arg(command.get_program())
+        .args(command.get_args());
+    with_environment(wrapped, &command)
+}
+
+/// The nvcc `command` runs, possibly through a wrapper. `None` when it does not run nvcc.
+fn nvcc_program(command: &Command) -> Option<&std::ffi::OsStr> {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .find(|program| {
+            Path::new(program)
+                .file_stem()
+                .is_some_and(|stem| stem == "nvcc")
+        })
+}
+
+/// Whether the `--verbose` line `line` sets a variable, like `_NVVM_BRANCH_=nvvm`.
+fn is_assignment(line: &str) -> bool {
+    let line = line.strip_prefix("set ").unwrap_or(line);
+    line.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// The program of the `--verbose` step `step`, which may be partially quoted like
+/// `"/usr/local/cuda/bin"/../nvvm/bin/cicc --arg`.
+fn step_program(step: &str) -> Option<String> {
+    let mut program = String::new();
+    let mut quoted = false;
+    for c in step.trim_start().chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => break,
+            c => program.push(c),
+        }
+    }
+    Some(program).filter(|p| !p.is_empty())
+}
+
+/// Gives `wrapped` the environment and working directory of `command`.
+fn with_environment(mut wrapped: Command, command: &Command) -> Command {
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => wrapped.env(key, value),
+            None => wrapped.env_remove(key),
+        };
+    }
+    if let Some(dir) = command.get_current_dir() {
+        wrapped.current_dir(dir);
+    }
+    wrapped
+}
+
+/// How a program is given a response file.
+#[derive(Debug, Clone, Copy)]
+enum ResponseFile {
+    /// `--options-file path`, for nvcc.
+    OptionsFile,
+    /// `@path`, for archivers.
+    At,
+}
+
+/// Moves the arguments of `command` into the response file `path` when `force` says so,
+/// or when they would make the command line too long.
+fn response_file(
+    command: Command,
+    path: &Path,
+    force: Option<bool>,
+    style: ResponseFile,
+) -> Command {
+    let length: usize = command.get_args().map(|a| a.len() + 3).sum();
+    if !force.unwrap_or(length > MAX_COMMAND_LINE) {
+        return command;
+    }
+    // nvcc reads response files as text, arguments which aren't valid UTF-8 (paths on
+    // Unix) can only be passed on the command line.
+    let Some(args) = command
+        .get_args()
+        .map(|a| a.to_str().map(quote_arg))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return command;
+    };
+    let content = args.join("\n");
+    std::fs::write(path, content).expect("write nvcc response file");
+    let mut wrapped = Command::new(command.get_program());
+    match style {
+        ResponseFile::OptionsFile => wrapped.arg("--options-file").arg(path),
+        ResponseFile::At => {
+            let mut at = OsString::from("@");
+            at.push(path);
+            wrapped.arg(at)
+        }
+    };
+    with_environment(wrapped, &command)
+}
+
+/// Lists the kernels (`.entry`) a PTX module defines.
+fn ptx_entry_points(ptx: &str) -> Vec<String> {
+    ptx.lines()
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            tokens.find(|t| *t == ".entry")?;
+            let name = tokens.next()?.split('(').next()?;
+            Some(name.to_string()).filter(|name| !name.is_empty())
+        })
+        .collect()
+}
+
+/// Lists the `extern "C"` device functions declared in a kernel source.
+/// Those are the ones which keep an unmangled name, and can therefore be looked
+/// up by name once the library is linked at runtime.
+fn exported_device_functions(source: &str) -> Vec<String> {
+    let mut functions = vec![];
+    for line in source.lines() {
+        let line = line.trim();
+        if !line.contains("extern \"C\"") || !line.contains("__device__") {
+            continue;
+        }
+        if let Some(head) = line.split('(').next() {
+            if let Some(name) = head.split_whitespace().last() {
+                let name = name.trim_start_matches('*');
+                if !name.is_empty() && !functions.iter().any(|f| f == name) {
+                    functions.push(name.to_string());
+                }
             }
         }
-        Ok(())
     }
+    functions
+}
+
+fn cuda_include_dir(probe: &dyn Probe) -> Option<PathBuf> {
+    let candidates = cuda_root_candidates(probe);
+    println!("cargo:info={candidates:?}");
+    candidates
+        .into_iter()
+        .find(|path| probe.is_file(&path.join("include").join("cuda.h")))
 }
 
-fn cuda_include_dir() -> Option<PathBuf> {
+/// The directories where the CUDA toolkit is looked for, in order.
+fn cuda_root_candidates(probe: &dyn Probe) -> Vec<PathBuf> {
     // NOTE: copied from cudarc build.rs.
     let env_vars = [
         "CUDA_PATH",
@@ -441,11 +7156,9 @@ fn cuda_include_dir() -> Option<PathBuf> {
         "CUDA_TOOLKIT_ROOT_DIR",
         "CUDNN_LIB",
     ];
-    #[allow(unused)]
     let env_vars = env_vars
         .into_iter()
-        .map(std::env::var)
-        .filter_map(Result::ok)
+        .filter_map(|var| probe.var(var))
         .map(Into::<PathBuf>::into);
 
     let roots = [
@@ -457,82 +7170,328 @@ fn cuda_include_dir() -> Option<PathBuf> {
         "C:/CUDA",
     ];
 
-    println!("cargo:info={roots:?}");
+    // Toolkits of the active conda or Python environment take precedence over system ones,
+    // the toolkit of the nvcc in the PATH covers the ones installed anywhere else.
+    env_vars
+        .chain(python_roots(probe))
+        .chain(roots.into_iter().map(Into::<PathBuf>::into))
+        .chain(nvcc_root(probe))
+        .collect()
+}
+
+/// The `nvcc` found in the `PATH`.
+fn find_nvcc(probe: &dyn Probe) -> Option<PathBuf> {
+    let path = probe.var("PATH")?;
+    std::env::split_paths(&path)
+        .flat_map(|dir| [dir.join("nvcc"), dir.join("nvcc.exe")])
+        .find(|nvcc| probe.is_file(nvcc))
+}
 
-    #[allow(unused)]
-    let roots = roots.into_iter().map(Into::<PathBuf>::into);
+/// The version file of the toolkit `nvcc` belongs to, `version.json` since CUDA 11.1
+/// and `version.txt` before.
+fn toolkit_version(probe: &dyn Probe, nvcc: &Path) -> Option<String> {
+    let nvcc = probe
+        .canonicalize(nvcc)
+        .unwrap_or_else(|| nvcc.to_path_buf());
+    let root = nvcc.parent()?.parent()?;
+    ["version.json", "version.txt"]
+        .iter()
+        .find_map(|file| probe.read_to_string(&root.join(file)))
+}
 
-    #[cfg(feature = "ci-check")]
-    let root: PathBuf = "ci".into();
+/// The toolkit of the `nvcc` in the `PATH`, which lives in its `bin` directory.
+/// Symlinks are followed, since distributions often link it from `/usr/bin`.
+fn nvcc_root(probe: &dyn Probe) -> Option<PathBuf> {
+    let nvcc = find_nvcc(probe)?;
+    let nvcc = probe.canonicalize(&nvcc).unwrap_or(nvcc);
+    Some(nvcc.parent()?.parent()?.to_path_buf())
+}
 
-    #[cfg(not(feature = "ci-check"))]
-    env_vars
-        .chain(roots)
-        .find(|path| path.join("include").join("cuda.h").is_file())
+/// Where toolkits installed by conda (`cuda-toolkit`) or pip wheels
+/// (`nvidia-cuda-runtime-cu12`...) in the active environment live.
+fn python_roots(probe: &dyn Probe) -> Vec<PathBuf> {
+    let mut roots = vec![];
+    let conda = probe.var("CONDA_PREFIX").map(PathBuf::from);
+    if let Some(conda) = &conda {
+        roots.push(conda.clone());
+        roots.push(conda.join("Library"));
+        for target in ["x86_64-linux", "sbsa-linux", "aarch64-linux"] {
+            roots.push(conda.join("targets").join(target));
+        }
+    }
+    let environments = probe
+        .var("VIRTUAL_ENV")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(conda);
+    for environment in environments {
+        let site_packages = (8..=14)
+            .map(|minor| environment.join(format!("lib/python3.{minor}/site-packages")))
+            .chain([environment.join("Lib/site-packages")]);
+        for site_packages in site_packages {
+            roots.push(site_packages.join("nvidia/cuda_runtime"));
+            roots.push(site_packages.join("nvidia/cu13"));
+        }
+    }
+    roots
+}
+
+/// The release of `nvcc` (`"12.4"`), parsed from `nvcc --version`.
+fn nvcc_version(probe: &dyn Probe) -> Option<String> {
+    let output = probe.output("nvcc", &["--version"])?;
+    let release = output.split("release ").nth(1)?;
+    let version = release.split(',').next()?.trim();
+    Some(version.to_string())
+}
+
+/// The `cl.exe` of the latest Visual Studio 2017 to 2022 with C++ tools installed,
+/// as found by `vswhere`, and its toolset version.
+fn find_msvc(probe: &dyn Probe) -> Option<(PathBuf, (usize, usize))> {
+    let program_files = probe
+        .var("ProgramFiles(x86)")
+        .unwrap_or_else(|| "C:/Program Files (x86)".to_string());
+    let vswhere = format!("{program_files}/Microsoft Visual Studio/Installer/vswhere.exe");
+    let installation = probe.output(
+        &vswhere,
+        &[
+            "-latest",
+            "-products",
+            "*",
+            "-requires",
+            "Microsoft.VisualStudio.Component.VC.Tools.x86.x64",
+            "-version",
+            "[15.0,18.0)",
+            "-property",
+            "installationPath",
+        ],
+    )?;
+    let installation = PathBuf::from(installation.trim());
+    let vc = installation.join("VC");
+    let version =
+        probe.read_to_string(&vc.join("Auxiliary/Build/Microsoft.VCToolsVersion.default.txt"))?;
+    let version = version.trim();
+    let mut parts = version.split('.').map(|p| p.parse::<usize>().ok());
+    let toolset = (parts.next()??, parts.next()??);
+    let cl = vc.join(format!("Tools/MSVC/{version}/bin/Hostx64/x64/cl.exe"));
+    probe.is_file(&cl).then_some((cl, toolset))
+}
+
+/// A `(major, minor)` version.
+type Version = (usize, usize);
+
+/// The MSVC toolsets accepted by each CUDA release, as checked by nvcc's
+/// `host_config.h`: from the CUDA version, the range of toolsets. Newest releases first.
+const MSVC_TOOLSETS: [(Version, std::ops::Range<Version>); 3] = [
+    // Visual Studio 2017 to 2022 17.10+
+    ((12, 4), (14, 10)..(14, 50)),
+    // Visual Studio 2017 to 2022 17.9
+    ((11, 6), (14, 10)..(14, 40)),
+    // Visual Studio 2017 and 2019
+    ((10, 0), (14, 10)..(14, 30)),
+];
+
+/// Whether CUDA `cuda_version` accepts the MSVC `toolset` as its host compiler.
+/// Releases older than the table are given the benefit of the doubt.
+fn msvc_supported(cuda_version: Version, toolset: Version) -> bool {
+    MSVC_TOOLSETS
+        .iter()
+        .find(|(since, _)| cuda_version >= *since)
+        .is_none_or(|(_, toolsets)| toolsets.contains(&toolset))
+}
+
+/// The `(major, minor)` version of the CUDA toolkit.
+fn cuda_version(probe: &dyn Probe) -> Result<(usize, usize), Error> {
+    let version = nvcc_version(probe).ok_or_else(|| {
+        Error::Nvcc("could not read the CUDA version from `nvcc --version`".to_string())
+    })?;
+    let (major, minor) = version.split_once('.').unwrap_or((&version, "0"));
+    match (major.parse(), minor.parse()) {
+        (Ok(major), Ok(minor)) => Ok((major, minor)),
+        _ => Err(Error::Nvcc(format!(
+            "could not parse CUDA version {version:?}"
+        ))),
+    }
+}
+
+/// Where WSL2 exposes the driver of the Windows host: `libcuda` and `nvidia-smi`.
+const WSL_LIB_DIR: &str = "/usr/lib/wsl/lib";
+
+/// Whether this runs under WSL2 with a GPU exposed by the Windows driver.
+fn is_wsl(probe: &dyn Probe) -> bool {
+    probe.var("WSL_DISTRO_NAME").is_some()
+        && probe.is_file(&Path::new(WSL_LIB_DIR).join("libcuda.so"))
+}
+
+/// Runs `nvidia-smi` with `args`, falling back under WSL2 to the one of the driver,
+/// which is not always in the `PATH`.
+fn nvidia_smi(probe: &dyn Probe, args: &[&str]) -> Option<String> {
+    probe.output("nvidia-smi", args).or_else(|| {
+        let wsl = Path::new(WSL_LIB_DIR).join("nvidia-smi");
+        is_wsl(probe)
+            .then(|| probe.output(&wsl.to_string_lossy(), args))
+            .flatten()
+    })
+}
+
+/// The version of the NVIDIA driver, like `550.54.15`, as reported by `nvidia-smi`.
+fn driver_version(probe: &dyn Probe) -> Option<String> {
+    let output = nvidia_smi(probe, &["--query-gpu=driver_version", "--format=csv"])?;
+    Some(output.lines().nth(1)?.trim().to_string())
+}
+
+/// Parses the compute caps of `CUDA_COMPUTE_CAP`, sorted: `89`, `8.9`, `sm_89` or
+/// `compute_89`, several of them being separated by commas, semicolons or spaces.
+fn parse_compute_caps(value: &str) -> Option<Vec<usize>> {
+    let mut compute_caps = value
+        .split([',', ';', ' '])
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(|c| {
+            let c = c.to_ascii_lowercase();
+            let c = ["sm_", "compute_", "sm"]
+                .iter()
+                .find_map(|prefix| c.strip_prefix(prefix))
+                .unwrap_or(&c);
+            match c.split_once('.') {
+                Some((major, minor)) if minor.len() == 1 => {
+                    Some(major.parse::<usize>().ok()? * 10 + minor.parse::<usize>().ok()?)
+                }
+                Some(_) => None,
+                None => c.parse().ok(),
+            }
+        })
+        .collect::<Option<Vec<usize>>>()?;
+    compute_caps.sort();
+    compute_caps.dedup();
+    (!compute_caps.is_empty()).then_some(compute_caps)
+}
+
+/// The compute caps the installed nvcc can generate code for, sorted.
+fn supported_compute_caps(probe: &dyn Probe) -> Result<Vec<usize>, Error> {
+    let out = probe.output("nvcc", &["--list-gpu-code"]).ok_or_else(|| {
+        Error::Nvcc(
+            "`nvcc` failed. Ensure that you have CUDA installed and that `nvcc` is in your PATH."
+                .to_string(),
+        )
+    })?;
+
+    let out = out.lines().collect::<Vec<&str>>();
+    let mut codes = Vec::with_capacity(out.len());
+    for code in out {
+        let code = code.split('_').collect::<Vec<&str>>();
+        if !code.is_empty() && code.contains(&"sm") {
+            if let Ok(num) = code[1].parse::<usize>() {
+                codes.push(num);
+            }
+        }
+    }
+    codes.sort();
+    if codes.is_empty() {
+        return Err(Error::Nvcc("no gpu codes parsed from nvcc".to_string()));
+    }
+    Ok(codes)
 }
 
-fn compute_cap() -> Result<usize, Error> {
+fn compute_cap(probe: &dyn Probe) -> Result<usize, Error> {
     println!("cargo:rerun-if-env-changed=CUDA_COMPUTE_CAP");
 
     // Try to parse compute caps from env
-    let compute_cap = if let Ok(compute_cap_str) = std::env::var("CUDA_COMPUTE_CAP") {
-        println!("cargo:rustc-env=CUDA_COMPUTE_CAP={compute_cap_str}");
-        compute_cap_str
-            .parse::<usize>()
-            .expect("Could not parse code")
+    let compute_caps = if let Some(value) = probe.var("CUDA_COMPUTE_CAP") {
+        parse_compute_caps(&value).ok_or(Error::InvalidComputeCap(value))?
     } else {
         // Use nvidia-smi to get the current compute cap
-        let out = std::process::Command::new("nvidia-smi")
-                .arg("--query-gpu=compute_cap")
-                .arg("--format=csv")
-                .output()
-                .expect("`nvidia-smi` failed. Ensure that you have CUDA installed and that `nvidia-smi` is in your PATH.");
-        let out = std::str::from_utf8(&out.stdout).expect("stdout is not a utf8 string");
+        let out = nvidia_smi(probe, &["--query-gpu=compute_cap", "--format=csv"])
+            .ok_or_else(|| Error::NvidiaSmi("`nvidia-smi` failed. Ensure that you have CUDA installed and that `nvidia-smi` is in your PATH, or set `CUDA_COMPUTE_CAP` manually.".to_string()))?;
         let mut lines = out.lines();
-        assert_eq!(lines.next().expect("missing line in stdout"), "compute_cap");
+        if lines.next() != Some("compute_cap") {
+            return Err(Error::NvidiaSmi(format!(
+                "unexpected `nvidia-smi` output {out:?}"
+            )));
+        }
         let cap = lines
             .next()
-            .expect("missing line in stdout")
+            .ok_or_else(|| Error::NvidiaSmi("`nvidia-smi` did not list any GPU".to_string()))?
             .replace('.', "");
-        let cap = cap.parse::<usize>().expect("cannot parse as int {cap}");
-        println!("cargo:rustc-env=CUDA_COMPUTE_CAP={cap}");
-        cap
+        let cap = cap
+            .parse::<usize>()
+            .map_err(|_| Error::NvidiaSmi(format!("cannot parse compute cap {cap}")))?;
+        vec![cap]
     };
 
-    // Grab available GPU codes from nvcc and select the highest one
-    let (supported_nvcc_codes, max_nvcc_code) = {
-        let out = std::process::Command::new("nvcc")
-                .arg("--list-gpu-code")
-                .output()
-                .expect("`nvcc` failed. Ensure that you have CUDA installed and that `nvcc` is in your PATH.");
-        let out = std::str::from_utf8(&out.stdout).expect("valid utf-8 nvcc output");
+    let supported_nvcc_codes = supported_compute_caps(probe)?;
 
-        let out = out.lines().collect::<Vec<&str>>();
-        let mut codes = Vec::with_capacity(out.len());
-        for code in out {
-            let code = code.split('_').collect::<Vec<&str>>();
-            if !code.is_empty() && code.contains(&"sm") {
-                if let Ok(num) = code[1].parse::<usize>() {
-                    codes.push(num);
-                }
-            }
+    // Check that nvcc supports the asked compute caps
+    if let Some(&compute_cap) = compute_caps
+        .iter()
+        .find(|c| !supported_nvcc_codes.contains(c))
+    {
+        return Err(Error::UnsupportedComputeCap {
+            compute_cap,
+            supported: supported_nvcc_codes,
+        });
+    }
+
+    // Lists build for each of their compute caps, the crate gets the oldest.
+    let compute_cap = compute_caps[0];
+    println!("cargo:rustc-env=CUDA_COMPUTE_CAP={compute_cap}");
+    Ok(compute_cap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The bindings [`Builder::build_ptx`] returns for the kernels at `paths`, without
+    /// building anything.
+    fn ptx_bindings(paths: &[&str]) -> Bindings {
+        let naming = Naming::default();
+        let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+        let names: Vec<String> = paths.iter().map(|p| naming.kernel_name(p)).collect();
+        Bindings {
+            outputs: names.iter().map(|name| format!("{name}.ptx")).collect(),
+            names,
+            kind: Output::Ptx,
+            device_functions: vec![],
+            entry_points: vec![vec![]; paths.len()],
+            nvrtc_options: vec![],
+            compute_caps: vec![None; paths.len()],
+            paths,
+            kernel_enum: false,
+            smoke_tests: false,
+            modules: false,
+            naming,
+            manifest: Manifest::default(),
+            stub: false,
+            out_dir: std::env::temp_dir(),
+            compression: None,
+            doc_comments: false,
+            allow_lints: false,
+            rustfmt: false,
         }
-        codes.sort();
-        let max_nvcc_code = *codes.last().expect("no gpu codes parsed from nvcc");
-        (codes, max_nvcc_code)
-    };
+    }
 
-    // Check that nvcc supports the asked compute caps
-    if !supported_nvcc_codes.contains(&compute_cap) {
-        panic!(
-            "nvcc cannot target gpu arch {compute_cap}. Available nvcc targets are {supported_nvcc_codes:?}."
-        );
+    #[test]
+    fn entry_point_lists_collide_with_kernels() {
+        // The entry points of `cuda.cu` are listed in `CUDA_KERNELS`.
+        let bindings = ptx_bindings(&["src/cuda.cu", "src/cuda_kernels.cu"]);
+        assert!(matches!(
+            bindings.render(),
+            Err(Error::NameCollision { name, .. }) if name == "CUDA_KERNELS"
+        ));
     }
-    if compute_cap > max_nvcc_code {
-        panic!(
-            "CUDA compute cap {compute_cap} is higher than the highest gpu code from nvcc {max_nvcc_code}"
-        );
+
+    #[test]
+    fn smoke_tests_are_prefixed() {
+        // Neither clashes with the `load` helper nor with a keyword.
+        let bindings = ptx_bindings(&["src/load.cu", "src/match.cu"]).smoke_tests(true);
+        let code = bindings.render().unwrap();
+        assert!(code.contains("fn load_load()") && code.contains("fn load_match()"));
     }
 
-    Ok(compute_cap)
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn compressing_missing_ptx_fails() {
+        let bindings = ptx_bindings(&["src/never_built.cu"]).compress(Compression::Gzip);
+        assert!(matches!(bindings, Err(Error::Io(_))));
+    }
 }
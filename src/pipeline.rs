@@ -0,0 +1,122 @@
+//! Ordering of builds depending on each other's outputs.
+use crate::Error;
+
+/// A set of named build steps run in dependency order, for projects where the
+/// outputs of one kernel group (a generated header, a device library...) are
+/// inputs of another. Steps are closures so builders are only created once the
+/// steps they depend on have run, and see their outputs.
+/// ```no_run
+/// use bindgen_cuda::{Builder, Pipeline};
+/// Pipeline::new()
+///     .step("tables", &[], || {
+///         std::fs::write("src/generated/tables.cuh", "// lookup tables").unwrap();
+///         Ok(())
+///     })
+///     .step("kernels", &["tables"], || {
+///         Builder::default().build_ptx()?.write("src/kernels.rs")
+///     })
+///     .run()
+///     .unwrap();
+/// ```
+#[derive(Default)]
+pub struct Pipeline {
+    steps: Vec<Step>,
+}
+
+struct Step {
+    name: String,
+    depends_on: Vec<String>,
+    run: Box<dyn FnOnce() -> Result<(), Error>>,
+}
+
+impl Pipeline {
+    /// An empty pipeline.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the step `name`, run after every step of `depends_on`.
+    pub fn step<F>(mut self, name: &str, depends_on: &[&str], run: F) -> Self
+    where
+        F: FnOnce() -> Result<(), Error> + 'static,
+    {
+        self.steps.push(Step {
+            name: name.to_string(),
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+            run: Box::new(run),
+        });
+        self
+    }
+
+    /// The step names in the order they run: dependencies first, otherwise in the
+    /// order they were added.
+    /// ```
+    /// use bindgen_cuda::Pipeline;
+    /// let pipeline = Pipeline::new()
+    ///     .step("kernels", &["device_lib"], || Ok(()))
+    ///     .step("device_lib", &[], || Ok(()));
+    /// assert_eq!(pipeline.order().unwrap(), ["device_lib", "kernels"]);
+    ///
+    /// let pipeline = Pipeline::new()
+    ///     .step("a", &["b"], || Ok(()))
+    ///     .step("b", &["a"], || Ok(()));
+    /// assert!(pipeline.order().is_err());
+    /// ```
+    pub fn order(&self) -> Result<Vec<&str>, Error> {
+        for step in &self.steps {
+            if self.steps.iter().filter(|s| s.name == step.name).count() > 1 {
+                return Err(Error::Pipeline(format!(
+                    "step {:?} is declared twice",
+                    step.name
+                )));
+            }
+            if let Some(missing) = step
+                .depends_on
+                .iter()
+                .find(|d| !self.steps.iter().any(|s| &s.name == *d))
+            {
+                return Err(Error::Pipeline(format!(
+                    "step {:?} depends on unknown step {missing:?}",
+                    step.name
+                )));
+            }
+        }
+        let mut order: Vec<&str> = vec![];
+        while order.len() < self.steps.len() {
+            let ready = self.steps.iter().find(|s| {
+                !order.contains(&s.name.as_str())
+                    && s.depends_on.iter().all(|d| order.contains(&d.as_str()))
+            });
+            match ready {
+                Some(step) => order.push(&step.name),
+                None => {
+                    let cycle: Vec<_> = self
+                        .steps
+                        .iter()
+                        .map(|s| s.name.as_str())
+                        .filter(|s| !order.contains(s))
+                        .collect();
+                    return Err(Error::Pipeline(format!(
+                        "steps {cycle:?} depend on each other"
+                    )));
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    /// Runs every step in order, stopping at the first failure.
+    pub fn run(mut self) -> Result<(), Error> {
+        let order: Vec<String> = self.order()?.into_iter().map(String::from).collect();
+        for name in order {
+            let index = self
+                .steps
+                .iter()
+                .position(|s| s.name == name)
+                .expect("ordered step to exist");
+            let step = self.steps.swap_remove(index);
+            (step.run)()?;
+        }
+        Ok(())
+    }
+}